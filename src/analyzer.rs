@@ -2,17 +2,51 @@ use log::{error, info};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-
-use crate::ast::Ast;
-use crate::parser::{self};
-use crate::typechecker::BurnTypeChecker;
-use crate::utils;
+use tower_lsp::lsp_types::{DiagnosticTag, Range, TextDocumentContentChangeEvent};
+
+use crate::ast::{Ast, Span};
+use crate::cst::SyntaxNode;
+use crate::inference;
+use crate::lints;
+use crate::parser::{self, ParseError, ParseResult};
+use crate::resolver::{self, Resolution};
+use crate::typechecker::{BurnTypeChecker, ConstAssignment};
+use crate::types;
+use crate::utils::{self, LineIndex};
 
 #[derive(Clone)]
 pub struct Document {
     pub uri: String,
     pub content: String,
-    pub ast: Option<Ast>,
+    pub line_index: LineIndex,
+    pub ast: Ast,
+    pub cst: SyntaxNode,
+    pub parse_errors: Vec<ParseError>,
+    pub resolution: Resolution,
+    /// Assignments to constants found when the document was last analyzed.
+    pub const_assignments: Vec<ConstAssignment>,
+}
+
+impl Document {
+    fn new(uri: &str, content: String, result: ParseResult) -> Self {
+        for err in &result.errors {
+            error!("Parse error in {}: {}", uri, err);
+        }
+
+        let mut resolution = resolver::resolve(&result.ast, uri);
+        inference::infer_types(&result.ast, &mut resolution);
+
+        Document {
+            uri: uri.to_string(),
+            line_index: LineIndex::new(&content),
+            resolution,
+            content,
+            ast: result.ast,
+            cst: result.cst,
+            parse_errors: result.errors,
+            const_assignments: Vec::new(),
+        }
+    }
 }
 
 pub struct BurnAnalyzer {
@@ -42,34 +76,107 @@ impl BurnAnalyzer {
     pub fn open_document(&self, uri: &str, content: String) {
         info!("Opening document: {}", uri);
 
-        let ast = match parser::parse(&content) {
-            Ok(ast) => Some(ast),
-            Err(errors) => {
-                for err in &errors {
-                    error!("Parse error in {}: {}", uri, err);
-                }
-                None
+        let mut documents = self.documents.lock().unwrap();
+        if documents.remove(uri).is_some() {
+            self.release_document(uri);
+        }
+        let result = parser::parse(&content);
+        let document = Document::new(uri, content, result);
+        documents.insert(uri.to_string(), document);
+    }
+
+    /// Applies the edits of a `didChange` notification in order and returns
+    /// the new document text. The document is reparsed and analysed once
+    /// after all edits; ranged edits only reparse the items they touch.
+    pub fn change_document(
+        &self,
+        uri: &str,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Option<String> {
+        let mut documents = self.documents.lock().unwrap();
+        let document = match documents.remove(uri) {
+            Some(document) => document,
+            None => {
+                error!("Document not found for change: {}", uri);
+                return None;
             }
         };
 
-        let document = Document {
-            uri: uri.to_string(),
-            content,
-            ast,
-        };
+        let mut content = document.content;
+        let mut line_index = document.line_index;
+        let mut line_index_stale = false;
+        // The span of the original text replaced so far and the length of
+        // what replaced it, unless the whole document has to be parsed again.
+        let mut edit: Option<(Span, usize)> = None;
+        let mut full_reparse = false;
+
+        for change in changes {
+            let Some(range) = change.range else {
+                content = change.text;
+                line_index_stale = true;
+                full_reparse = true;
+                continue;
+            };
+
+            if line_index_stale {
+                line_index = LineIndex::new(&content);
+            }
+            let (start, end) = match (line_index.offset(range.start), line_index.offset(range.end))
+            {
+                (Some(start), Some(end)) if start <= end => (start, end),
+                (start, end) => {
+                    // Lines past the last one are taken as the end of the text.
+                    error!(
+                        "Invalid change range {:?} in {}, reparsing the whole document",
+                        range, uri
+                    );
+                    full_reparse = true;
+                    let start = start.unwrap_or(content.len());
+                    let end = end.unwrap_or(content.len());
+                    (start.min(end), start.max(end))
+                }
+            };
 
-        let mut documents = self.documents.lock().unwrap();
+            content.replace_range(start..end, &change.text);
+            line_index_stale = true;
+            edit = Some(merge_edits(edit, Span::new(start, end), change.text.len()));
+        }
+
+        let previous = ParseResult {
+            ast: document.ast,
+            cst: document.cst,
+            errors: document.parse_errors,
+        };
+        let result = match edit {
+            _ if full_reparse => parser::parse(&content),
+            Some((edit, inserted_len)) => parser::reparse(previous, &content, edit, inserted_len),
+            None => previous,
+        };
+        self.release_document(uri);
+        let document = Document::new(uri, content, result);
+        let content = document.content.clone();
         documents.insert(uri.to_string(), document);
+        Some(content)
     }
 
     pub fn close_document(&self, uri: &str) {
         info!("Closing document: {}", uri);
         let mut documents = self.documents.lock().unwrap();
-        documents.remove(uri);
+        if documents.remove(uri).is_some() {
+            self.release_document(uri);
+        }
+    }
+
+    /// Frees the types of the previous analysis of `uri`. A clone of its
+    /// `Document` still answering a request keeps handles to them, which read
+    /// as `any` from then on.
+    fn release_document(&self, uri: &str) {
+        self.type_checker.forget_file(uri);
+        types::release(uri);
     }
 
     pub fn analyze_document(&self, uri: &str) -> Vec<AnalysisError> {
-        let documents = self.documents.lock().unwrap();
+        let mut documents = self.documents.lock().unwrap();
         let document = match documents.get(uri) {
             Some(doc) => doc,
             None => {
@@ -80,39 +187,60 @@ impl BurnAnalyzer {
 
         let mut errors = Vec::new();
 
-        match &document.ast {
-            Some(ast) => {
-                self.type_checker.set_current_file(uri);
-
-                match self.type_checker.check_types(ast, uri) {
-                    Ok(_) => {}
-                    Err(type_errors) => {
-                        for err in type_errors {
-                            errors.push(AnalysisError {
-                                message: err.message,
-                                error_type: ErrorType::TypeError,
-                                line: err.line,
-                                column: err.column,
-                                length: err.length,
-                            });
-                        }
-                    }
-                }
-            }
-            None => match parser::parse(&document.content) {
-                Ok(_) => {}
-                Err(parse_errors) => {
-                    for err in parse_errors {
-                        errors.push(AnalysisError {
-                            message: err.message,
-                            error_type: ErrorType::ParseError,
-                            line: err.line,
-                            column: err.column,
-                            length: 1,
-                        });
+        for err in &document.parse_errors {
+            errors.push(AnalysisError {
+                message: err.message.clone(),
+                error_type: ErrorType::ParseError,
+                range: document.line_index.range(err.span),
+                tags: Vec::new(),
+            });
+        }
+
+        self.type_checker.set_current_file(uri);
+
+        let type_check = self
+            .type_checker
+            .check_types(&document.ast, &document.resolution, uri);
+        for err in type_check.errors {
+            errors.push(AnalysisError {
+                message: err.message,
+                error_type: ErrorType::TypeError,
+                range: document.line_index.range(err.span),
+                tags: Vec::new(),
+            });
+        }
+
+        let is_imported_elsewhere = |name: &str| {
+            documents
+                .iter()
+                .filter(|(other, _)| other.as_str() != uri)
+                .flat_map(|(_, other)| &other.ast.nodes)
+                .any(|node| match node {
+                    crate::ast::Node::ImportDeclaration { imported_items, .. } => {
+                        imported_items.iter().any(|item| item.name == name)
                     }
-                }
-            },
+                    _ => false,
+                })
+        };
+        let warnings = lints::check_unused(&document.resolution, is_imported_elsewhere)
+            .into_iter()
+            .chain(lints::check_unreachable(&document.ast));
+        for warning in warnings {
+            errors.push(AnalysisError {
+                message: warning.message,
+                error_type: ErrorType::SemanticError,
+                range: document.line_index.range(warning.span),
+                tags: if warning.unnecessary {
+                    vec![DiagnosticTag::UNNECESSARY]
+                } else {
+                    Vec::new()
+                },
+            });
+        }
+
+        // Kept for the quick fixes, which would otherwise check again.
+        if let Some(document) = documents.get_mut(uri) {
+            document.const_assignments = type_check.const_assignments;
         }
 
         errors
@@ -136,7 +264,7 @@ impl BurnAnalyzer {
     }
 
     pub fn is_burn_file(&self, path: &Path) -> bool {
-        path.extension().map_or(false, |ext| ext == "bn")
+        path.extension().is_some_and(|ext| ext == "bn")
     }
 
     pub fn get_workspace_root(&self) -> Option<PathBuf> {
@@ -161,67 +289,45 @@ impl BurnAnalyzer {
         let documents = self.documents.lock().unwrap();
 
         if let Some(document) = documents.get(uri) {
-            if let Ok(offset) = utils::position_to_offset(
-                &document.content,
-                tower_lsp::lsp_types::Position::new(line as u32, character as u32),
-            ) {
+            if let Some(offset) = document
+                .line_index
+                .offset(tower_lsp::lsp_types::Position::new(
+                    line as u32,
+                    character as u32,
+                ))
+            {
+                if let Some(symbol) = document.resolution.symbol_at(offset) {
+                    let symbol = &document.resolution.symbols[symbol];
+                    return Some(DefinitionLocation {
+                        uri: uri.to_string(),
+                        range: document.line_index.range(symbol.name_span),
+                    });
+                }
+
                 if let Some((start, end)) = utils::find_word_at_offset(&document.content, offset) {
                     let word = &document.content[start..end];
 
                     for (doc_uri, doc) in documents.iter() {
-                        if let Some(ast) = &doc.ast {
-                            for node in &ast.nodes {
-                                match node {
-                                    crate::ast::Node::FunctionDeclaration {
-                                        name,
-                                        line,
-                                        column,
-                                        ..
-                                    } if name == word => {
-                                        return Some(DefinitionLocation {
-                                            uri: doc_uri.clone(),
-                                            line: *line,
-                                            character: *column,
-                                        });
-                                    }
-                                    crate::ast::Node::VariableDeclaration {
-                                        name,
-                                        line,
-                                        column,
-                                        ..
-                                    } if name == word => {
-                                        return Some(DefinitionLocation {
-                                            uri: doc_uri.clone(),
-                                            line: *line,
-                                            character: *column,
-                                        });
-                                    }
-                                    crate::ast::Node::StructDeclaration {
-                                        name,
-                                        line,
-                                        column,
-                                        ..
-                                    } if name == word => {
-                                        return Some(DefinitionLocation {
-                                            uri: doc_uri.clone(),
-                                            line: *line,
-                                            character: *column,
-                                        });
-                                    }
-                                    crate::ast::Node::ClassDeclaration {
-                                        name,
-                                        line,
-                                        column,
-                                        ..
-                                    } if name == word => {
-                                        return Some(DefinitionLocation {
-                                            uri: doc_uri.clone(),
-                                            line: *line,
-                                            character: *column,
-                                        });
-                                    }
-                                    _ => {}
+                        for node in &doc.ast.nodes {
+                            match node {
+                                crate::ast::Node::FunctionDeclaration {
+                                    name, name_span, ..
+                                }
+                                | crate::ast::Node::VariableDeclaration {
+                                    name, name_span, ..
+                                }
+                                | crate::ast::Node::StructDeclaration {
+                                    name, name_span, ..
                                 }
+                                | crate::ast::Node::ClassDeclaration {
+                                    name, name_span, ..
+                                } if name == word => {
+                                    return Some(DefinitionLocation {
+                                        uri: doc_uri.clone(),
+                                        range: doc.line_index.range(*name_span),
+                                    });
+                                }
+                                _ => {}
                             }
                         }
                     }
@@ -232,56 +338,120 @@ impl BurnAnalyzer {
         None
     }
 
+    /// Every use of the symbol at the given position, optionally with its
+    /// declaration first.
+    pub fn find_references(
+        &self,
+        uri: &str,
+        line: usize,
+        character: usize,
+        include_declaration: bool,
+    ) -> Vec<Range> {
+        let documents = self.documents.lock().unwrap();
+        let mut ranges = Vec::new();
+
+        if let Some(document) = documents.get(uri) {
+            if let Some(offset) = document
+                .line_index
+                .offset(tower_lsp::lsp_types::Position::new(
+                    line as u32,
+                    character as u32,
+                ))
+            {
+                if let Some(symbol) = document.resolution.symbol_at(offset) {
+                    if include_declaration {
+                        let declaration = &document.resolution.symbols[symbol];
+                        ranges.push(document.line_index.range(declaration.name_span));
+                    }
+                    for span in document.resolution.references_to(symbol) {
+                        ranges.push(document.line_index.range(span));
+                    }
+                }
+            }
+        }
+
+        ranges
+    }
+
     pub fn get_document_symbols(&self, uri: &str) -> Vec<DocumentSymbol> {
         let documents = self.documents.lock().unwrap();
         let mut symbols = Vec::new();
 
         if let Some(document) = documents.get(uri) {
-            if let Some(ast) = &document.ast {
-                for node in &ast.nodes {
-                    match node {
-                        crate::ast::Node::FunctionDeclaration {
-                            name, line, column, ..
-                        } => {
-                            symbols.push(DocumentSymbol {
-                                name: name.clone(),
-                                symbol_type: SymbolType::Function,
-                                line: *line,
-                                character: *column,
-                            });
-                        }
-                        crate::ast::Node::VariableDeclaration {
-                            name, line, column, ..
-                        } => {
+            for node in &document.ast.nodes {
+                match node {
+                    crate::ast::Node::FunctionDeclaration { name, span, .. } => {
+                        symbols.push(DocumentSymbol {
+                            name: name.clone(),
+                            symbol_type: SymbolType::Function,
+                            range: document.line_index.range(*span),
+                            container_name: None,
+                        });
+                    }
+                    crate::ast::Node::VariableDeclaration { name, span, .. } => {
+                        symbols.push(DocumentSymbol {
+                            name: name.clone(),
+                            symbol_type: SymbolType::Variable,
+                            range: document.line_index.range(*span),
+                            container_name: None,
+                        });
+                    }
+                    crate::ast::Node::StructDeclaration {
+                        name, fields, span, ..
+                    } => {
+                        symbols.push(DocumentSymbol {
+                            name: name.clone(),
+                            symbol_type: SymbolType::Struct,
+                            range: document.line_index.range(*span),
+                            container_name: None,
+                        });
+                        for field in fields {
                             symbols.push(DocumentSymbol {
-                                name: name.clone(),
-                                symbol_type: SymbolType::Variable,
-                                line: *line,
-                                character: *column,
+                                name: field.name.clone(),
+                                symbol_type: SymbolType::Property,
+                                range: document.line_index.range(field.span),
+                                container_name: Some(name.clone()),
                             });
                         }
-                        crate::ast::Node::StructDeclaration {
-                            name, line, column, ..
-                        } => {
+                    }
+                    crate::ast::Node::ClassDeclaration {
+                        name,
+                        methods,
+                        properties,
+                        span,
+                        ..
+                    } => {
+                        symbols.push(DocumentSymbol {
+                            name: name.clone(),
+                            symbol_type: SymbolType::Class,
+                            range: document.line_index.range(*span),
+                            container_name: None,
+                        });
+                        for property in properties {
                             symbols.push(DocumentSymbol {
-                                name: name.clone(),
-                                symbol_type: SymbolType::Struct,
-                                line: *line,
-                                character: *column,
+                                name: property.name.clone(),
+                                symbol_type: SymbolType::Property,
+                                range: document.line_index.range(property.span),
+                                container_name: Some(name.clone()),
                             });
                         }
-                        crate::ast::Node::ClassDeclaration {
-                            name, line, column, ..
-                        } => {
-                            symbols.push(DocumentSymbol {
-                                name: name.clone(),
-                                symbol_type: SymbolType::Class,
-                                line: *line,
-                                character: *column,
-                            });
+                        for method in methods {
+                            if let crate::ast::Node::FunctionDeclaration {
+                                name: method_name,
+                                span: method_span,
+                                ..
+                            } = method
+                            {
+                                symbols.push(DocumentSymbol {
+                                    name: method_name.clone(),
+                                    symbol_type: SymbolType::Method,
+                                    range: document.line_index.range(*method_span),
+                                    container_name: Some(name.clone()),
+                                });
+                            }
                         }
-                        _ => {}
                     }
+                    _ => {}
                 }
             }
         }
@@ -290,6 +460,26 @@ impl BurnAnalyzer {
     }
 }
 
+/// Combines an `earlier` edit, given as the span of the original text it
+/// replaced and the length of its replacement, with a later `edit` of the
+/// text it produced, into one edit of the original text.
+fn merge_edits(earlier: Option<(Span, usize)>, edit: Span, inserted_len: usize) -> (Span, usize) {
+    let Some((original, replaced_len)) = earlier else {
+        return (edit, inserted_len);
+    };
+
+    let earlier_end = original.start + replaced_len;
+    let start = original.start.min(edit.start);
+    let end = earlier_end.max(edit.end);
+    // Text outside both edits is unchanged, so past the earlier replacement
+    // offsets only differ from the original ones by its change in length.
+    let original_end = original.end + (end - earlier_end);
+    (
+        Span::new(start, original_end),
+        end - start - edit.len() + inserted_len,
+    )
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ErrorType {
     ParseError,
@@ -301,16 +491,14 @@ pub enum ErrorType {
 pub struct AnalysisError {
     pub message: String,
     pub error_type: ErrorType,
-    pub line: usize,
-    pub column: usize,
-    pub length: usize,
+    pub range: Range,
+    pub tags: Vec<DiagnosticTag>,
 }
 
 #[derive(Debug, Clone)]
 pub struct DefinitionLocation {
     pub uri: String,
-    pub line: usize,
-    pub character: usize,
+    pub range: Range,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -327,6 +515,118 @@ pub enum SymbolType {
 pub struct DocumentSymbol {
     pub name: String,
     pub symbol_type: SymbolType,
-    pub line: usize,
-    pub character: usize,
+    pub range: Range,
+    pub container_name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::Position;
+
+    fn change(
+        range: Option<((u32, u32), (u32, u32))>,
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: range.map(|(start, end)| Range {
+                start: Position::new(start.0, start.1),
+                end: Position::new(end.0, end.1),
+            }),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    fn assert_matches_full_parse(analyzer: &BurnAnalyzer, uri: &str, expected: &str) {
+        let documents = analyzer.documents.lock().unwrap();
+        let document = &documents[uri];
+        let full = parser::parse(expected);
+        assert_eq!(document.content, expected);
+        assert_eq!(document.cst, full.cst);
+        assert_eq!(format!("{:?}", document.ast), format!("{:?}", full.ast));
+        assert_eq!(
+            format!("{:?}", document.parse_errors),
+            format!("{:?}", full.errors)
+        );
+    }
+
+    #[test]
+    fn batched_edits_apply_in_order() {
+        // The interner is shared, so each test releases only its own file.
+        let uri = "file:///batched_edits.bn";
+        let analyzer = BurnAnalyzer::new(Arc::new(BurnTypeChecker::new()));
+        analyzer.open_document(
+            uri,
+            "let a = 1\nfn f() {\n  print(a)\n}\nlet b = 2\n".to_string(),
+        );
+
+        let content = analyzer.change_document(
+            uri,
+            vec![
+                change(Some(((0, 8), (0, 9))), "10"),
+                change(Some(((2, 8), (2, 9))), "a, b"),
+                change(Some(((0, 4), (0, 5))), "c"),
+                change(Some(((4, 0), (4, 9))), "let b = {"),
+                change(Some(((1, 0), (1, 0))), "// f\n"),
+            ],
+        );
+
+        let expected = "let c = 10\n// f\nfn f() {\n  print(a, b)\n}\nlet b = {\n";
+        assert_eq!(content.as_deref(), Some(expected));
+        assert_matches_full_parse(&analyzer, uri, expected);
+    }
+
+    #[test]
+    fn full_and_invalid_changes_reparse_everything() {
+        let uri = "file:///full_changes.bn";
+        let analyzer = BurnAnalyzer::new(Arc::new(BurnTypeChecker::new()));
+        analyzer.open_document(uri, "let a = 1\n".to_string());
+
+        analyzer.change_document(
+            uri,
+            vec![
+                change(None, "let a = 1\nlet b = 2\n"),
+                change(Some(((1, 8), (1, 9))), "(3"),
+            ],
+        );
+        assert_matches_full_parse(&analyzer, uri, "let a = 1\nlet b = (3\n");
+
+        analyzer.change_document(uri, vec![change(Some(((1, 8), (7, 0))), "4")]);
+        assert_matches_full_parse(&analyzer, uri, "let a = 1\nlet b = 4");
+    }
+
+    #[test]
+    fn annotations_after_an_edit_move_with_it() {
+        let uri = "file:///moved_annotations.bn";
+        let analyzer = BurnAnalyzer::new(Arc::new(BurnTypeChecker::new()));
+        analyzer.open_document(
+            uri,
+            "let a = 1\nfn f(p: Point): Point? { return p }\nstruct Point { next: Point? }\n"
+                .to_string(),
+        );
+
+        analyzer.change_document(uri, vec![change(Some(((0, 8), (0, 9))), "100")]);
+        assert_matches_full_parse(
+            &analyzer,
+            uri,
+            "let a = 100\nfn f(p: Point): Point? { return p }\nstruct Point { next: Point? }\n",
+        );
+    }
+
+    #[test]
+    fn merged_edits_cover_both_changes() {
+        // "abcdef" -> "abXYZef" -> "aQYZef"
+        let first = merge_edits(None, Span::new(2, 4), 3);
+        assert_eq!(first, (Span::new(2, 4), 3));
+        assert_eq!(
+            merge_edits(Some(first), Span::new(1, 3), 1),
+            (Span::new(1, 4), 3)
+        );
+        // An edit after the first one maps back past its change in length.
+        assert_eq!(
+            merge_edits(Some(first), Span::new(6, 7), 0),
+            (Span::new(2, 6), 4)
+        );
+    }
 }
@@ -1,3 +1,45 @@
+use std::fmt;
+
+/// Half-open byte range `[start, end)` into the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    pub fn shifted(self, delta: isize) -> Span {
+        Span {
+            start: self.start.saturating_add_signed(delta),
+            end: self.end.saturating_add_signed(delta),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Ast {
     pub nodes: Vec<Node>,
@@ -7,81 +49,89 @@ pub struct Ast {
 pub enum Node {
     VariableDeclaration {
         name: String,
+        name_span: Span,
         initializer: Option<Box<Expression>>,
         data_type: Option<Type>,
         is_mutable: bool,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     FunctionDeclaration {
         name: String,
+        name_span: Span,
         params: Vec<Parameter>,
         return_type: Option<Type>,
-        body: Vec<Box<Node>>,
-        line: usize,
-        column: usize,
+        body: Vec<Node>,
+        span: Span,
     },
     StructDeclaration {
         name: String,
+        name_span: Span,
         fields: Vec<StructField>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     ClassDeclaration {
         name: String,
-        methods: Vec<Box<Node>>,
+        name_span: Span,
+        methods: Vec<Node>,
         properties: Vec<StructField>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     ImportDeclaration {
         path: String,
-        imported_items: Vec<String>,
-        line: usize,
-        column: usize,
+        imported_items: Vec<ImportedItem>,
+        span: Span,
     },
     ExpressionStatement {
         expression: Box<Expression>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     ReturnStatement {
         expression: Option<Box<Expression>>,
-        line: usize,
-        column: usize,
+        span: Span,
+    },
+    BreakStatement {
+        span: Span,
+    },
+    ContinueStatement {
+        span: Span,
     },
     IfStatement {
         condition: Box<Expression>,
-        then_branch: Vec<Box<Node>>,
-        else_branch: Option<Vec<Box<Node>>>,
-        line: usize,
-        column: usize,
+        then_branch: Vec<Node>,
+        /// The braces of `then_branch`.
+        then_span: Span,
+        else_branch: Option<Vec<Node>>,
+        /// The braces of `else_branch`, or the `if` of an `else if`.
+        else_span: Option<Span>,
+        span: Span,
     },
     WhileStatement {
         condition: Box<Expression>,
-        body: Vec<Box<Node>>,
-        line: usize,
-        column: usize,
+        body: Vec<Node>,
+        /// The braces of `body`.
+        body_span: Span,
+        span: Span,
     },
     ForStatement {
         initializer: Option<Box<Node>>,
         condition: Option<Box<Expression>>,
         increment: Option<Box<Expression>>,
-        body: Vec<Box<Node>>,
-        line: usize,
-        column: usize,
+        body: Vec<Node>,
+        span: Span,
     },
     ForInStatement {
         variable: String,
+        variable_span: Span,
         iterable: Box<Expression>,
-        body: Vec<Box<Node>>,
-        line: usize,
-        column: usize,
+        body: Vec<Node>,
+        span: Span,
     },
     Block {
-        statements: Vec<Box<Node>>,
-        line: usize,
-        column: usize,
+        statements: Vec<Node>,
+        span: Span,
+    },
+    Error {
+        span: Span,
     },
 }
 
@@ -89,70 +139,375 @@ pub enum Node {
 pub enum Expression {
     Literal {
         value: LiteralValue,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     Variable {
         name: String,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     BinaryOperation {
         operator: String,
         left: Box<Expression>,
         right: Box<Expression>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     UnaryOperation {
         operator: String,
         operand: Box<Expression>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     Call {
         callee: Box<Expression>,
         arguments: Vec<Expression>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     PropertyAccess {
         object: Box<Expression>,
         property: String,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     ArrayAccess {
         array: Box<Expression>,
         index: Box<Expression>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     Assignment {
+        operator: String,
         target: Box<Expression>,
         value: Box<Expression>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     ArrayLiteral {
         elements: Vec<Expression>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     ObjectLiteral {
         properties: Vec<ObjectProperty>,
-        line: usize,
-        column: usize,
+        span: Span,
     },
     Lambda {
         params: Vec<Parameter>,
-        body: Vec<Box<Node>>,
+        body: Vec<Node>,
         return_type: Option<Type>,
-        line: usize,
-        column: usize,
+        span: Span,
+    },
+    /// `"a ${b} c"`: string literal pieces interleaved with the interpolated
+    /// expressions, in source order.
+    InterpolatedString {
+        parts: Vec<Expression>,
+        span: Span,
+    },
+    Error {
+        span: Span,
     },
 }
 
+impl Node {
+    pub fn span(&self) -> Span {
+        match self {
+            Node::VariableDeclaration { span, .. }
+            | Node::FunctionDeclaration { span, .. }
+            | Node::StructDeclaration { span, .. }
+            | Node::ClassDeclaration { span, .. }
+            | Node::ImportDeclaration { span, .. }
+            | Node::ExpressionStatement { span, .. }
+            | Node::ReturnStatement { span, .. }
+            | Node::BreakStatement { span }
+            | Node::ContinueStatement { span }
+            | Node::IfStatement { span, .. }
+            | Node::WhileStatement { span, .. }
+            | Node::ForStatement { span, .. }
+            | Node::ForInStatement { span, .. }
+            | Node::Block { span, .. }
+            | Node::Error { span } => *span,
+        }
+    }
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Literal { span, .. }
+            | Expression::Variable { span, .. }
+            | Expression::BinaryOperation { span, .. }
+            | Expression::UnaryOperation { span, .. }
+            | Expression::Call { span, .. }
+            | Expression::PropertyAccess { span, .. }
+            | Expression::ArrayAccess { span, .. }
+            | Expression::Assignment { span, .. }
+            | Expression::ArrayLiteral { span, .. }
+            | Expression::ObjectLiteral { span, .. }
+            | Expression::Lambda { span, .. }
+            | Expression::InterpolatedString { span, .. }
+            | Expression::Error { span } => *span,
+        }
+    }
+}
+
+/// Moving nodes after an edit: every span in the subtree is offset by `delta`
+/// bytes so a node parsed before the edit can be reused after it.
+impl Node {
+    pub fn shift(&mut self, delta: isize) {
+        match self {
+            Node::VariableDeclaration {
+                name_span,
+                initializer,
+                data_type,
+                span,
+                ..
+            } => {
+                *name_span = name_span.shifted(delta);
+                if let Some(initializer) = initializer {
+                    initializer.shift(delta);
+                }
+                if let Some(data_type) = data_type {
+                    data_type.shift(delta);
+                }
+                *span = span.shifted(delta);
+            }
+            Node::FunctionDeclaration {
+                name_span,
+                params,
+                return_type,
+                body,
+                span,
+                ..
+            } => {
+                *name_span = name_span.shifted(delta);
+                shift_params(params, delta);
+                if let Some(return_type) = return_type {
+                    return_type.shift(delta);
+                }
+                shift_nodes(body, delta);
+                *span = span.shifted(delta);
+            }
+            Node::StructDeclaration {
+                name_span,
+                fields,
+                span,
+                ..
+            } => {
+                *name_span = name_span.shifted(delta);
+                shift_fields(fields, delta);
+                *span = span.shifted(delta);
+            }
+            Node::ClassDeclaration {
+                name_span,
+                methods,
+                properties,
+                span,
+                ..
+            } => {
+                *name_span = name_span.shifted(delta);
+                shift_nodes(methods, delta);
+                shift_fields(properties, delta);
+                *span = span.shifted(delta);
+            }
+            Node::ImportDeclaration {
+                imported_items,
+                span,
+                ..
+            } => {
+                for item in imported_items {
+                    item.span = item.span.shifted(delta);
+                }
+                *span = span.shifted(delta);
+            }
+            Node::BreakStatement { span }
+            | Node::ContinueStatement { span }
+            | Node::Error { span } => {
+                *span = span.shifted(delta);
+            }
+            Node::ExpressionStatement { expression, span } => {
+                expression.shift(delta);
+                *span = span.shifted(delta);
+            }
+            Node::ReturnStatement { expression, span } => {
+                if let Some(expression) = expression {
+                    expression.shift(delta);
+                }
+                *span = span.shifted(delta);
+            }
+            Node::IfStatement {
+                condition,
+                then_branch,
+                then_span,
+                else_branch,
+                else_span,
+                span,
+            } => {
+                condition.shift(delta);
+                shift_nodes(then_branch, delta);
+                *then_span = then_span.shifted(delta);
+                if let Some(else_branch) = else_branch {
+                    shift_nodes(else_branch, delta);
+                }
+                if let Some(else_span) = else_span {
+                    *else_span = else_span.shifted(delta);
+                }
+                *span = span.shifted(delta);
+            }
+            Node::WhileStatement {
+                condition,
+                body,
+                body_span,
+                span,
+            } => {
+                condition.shift(delta);
+                shift_nodes(body, delta);
+                *body_span = body_span.shifted(delta);
+                *span = span.shifted(delta);
+            }
+            Node::ForStatement {
+                initializer,
+                condition,
+                increment,
+                body,
+                span,
+            } => {
+                if let Some(initializer) = initializer {
+                    initializer.shift(delta);
+                }
+                if let Some(condition) = condition {
+                    condition.shift(delta);
+                }
+                if let Some(increment) = increment {
+                    increment.shift(delta);
+                }
+                shift_nodes(body, delta);
+                *span = span.shifted(delta);
+            }
+            Node::ForInStatement {
+                variable_span,
+                iterable,
+                body,
+                span,
+                ..
+            } => {
+                *variable_span = variable_span.shifted(delta);
+                iterable.shift(delta);
+                shift_nodes(body, delta);
+                *span = span.shifted(delta);
+            }
+            Node::Block { statements, span } => {
+                shift_nodes(statements, delta);
+                *span = span.shifted(delta);
+            }
+        }
+    }
+}
+
+impl Expression {
+    pub fn shift(&mut self, delta: isize) {
+        match self {
+            Expression::Literal { span, .. }
+            | Expression::Variable { span, .. }
+            | Expression::Error { span } => {
+                *span = span.shifted(delta);
+            }
+            Expression::BinaryOperation {
+                left, right, span, ..
+            } => {
+                left.shift(delta);
+                right.shift(delta);
+                *span = span.shifted(delta);
+            }
+            Expression::UnaryOperation { operand, span, .. } => {
+                operand.shift(delta);
+                *span = span.shifted(delta);
+            }
+            Expression::Call {
+                callee,
+                arguments,
+                span,
+            } => {
+                callee.shift(delta);
+                for argument in arguments {
+                    argument.shift(delta);
+                }
+                *span = span.shifted(delta);
+            }
+            Expression::PropertyAccess { object, span, .. } => {
+                object.shift(delta);
+                *span = span.shifted(delta);
+            }
+            Expression::ArrayAccess { array, index, span } => {
+                array.shift(delta);
+                index.shift(delta);
+                *span = span.shifted(delta);
+            }
+            Expression::Assignment {
+                target,
+                value,
+                span,
+                ..
+            } => {
+                target.shift(delta);
+                value.shift(delta);
+                *span = span.shifted(delta);
+            }
+            Expression::ArrayLiteral { elements, span }
+            | Expression::InterpolatedString {
+                parts: elements,
+                span,
+            } => {
+                for element in elements {
+                    element.shift(delta);
+                }
+                *span = span.shifted(delta);
+            }
+            Expression::ObjectLiteral { properties, span } => {
+                for property in properties {
+                    property.value.shift(delta);
+                    property.span = property.span.shifted(delta);
+                }
+                *span = span.shifted(delta);
+            }
+            Expression::Lambda {
+                params,
+                body,
+                return_type,
+                span,
+            } => {
+                shift_params(params, delta);
+                if let Some(return_type) = return_type {
+                    return_type.shift(delta);
+                }
+                shift_nodes(body, delta);
+                *span = span.shifted(delta);
+            }
+        }
+    }
+}
+
+fn shift_nodes(nodes: &mut [Node], delta: isize) {
+    for node in nodes {
+        node.shift(delta);
+    }
+}
+
+fn shift_params(params: &mut [Parameter], delta: isize) {
+    for param in params {
+        if let Some(typ) = &mut param.typ {
+            typ.shift(delta);
+        }
+        param.span = param.span.shifted(delta);
+    }
+}
+
+fn shift_fields(fields: &mut [StructField], delta: isize) {
+    for field in fields {
+        if let Some(typ) = &mut field.typ {
+            typ.shift(delta);
+        }
+        if let Some(initializer) = &mut field.initializer {
+            initializer.shift(delta);
+        }
+        field.name_span = field.name_span.shifted(delta);
+        field.span = field.span.shifted(delta);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LiteralValue {
     String(String),
@@ -166,24 +521,38 @@ pub enum LiteralValue {
 pub struct Parameter {
     pub name: String,
     pub typ: Option<Type>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct StructField {
     pub name: String,
+    pub name_span: Span,
     pub typ: Option<Type>,
     pub initializer: Option<Box<Expression>>,
+    pub is_mutable: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedItem {
+    pub name: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct ObjectProperty {
     pub key: String,
     pub value: Box<Expression>,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type {
-    Basic(String),
+    Basic {
+        name: String,
+        span: Span,
+    },
     Array(Box<Type>),
     Function {
         params: Vec<Type>,
@@ -193,26 +562,63 @@ pub enum Type {
     Union(Vec<Type>),
 }
 
-impl ToString for Type {
-    fn to_string(&self) -> String {
+impl Type {
+    pub fn shift(&mut self, delta: isize) {
+        match self {
+            Type::Basic { span, .. } => *span = span.shifted(delta),
+            Type::Array(typ) | Type::Optional(typ) => typ.shift(delta),
+            Type::Function {
+                params,
+                return_type,
+            } => {
+                for param in params {
+                    param.shift(delta);
+                }
+                return_type.shift(delta);
+            }
+            Type::Union(types) => {
+                for typ in types {
+                    typ.shift(delta);
+                }
+            }
+        }
+    }
+
+    /// Formats `self` as the operand of a suffix or a function return type,
+    /// where unions and function types need parentheses to parse back the
+    /// same way.
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, wrap_functions: bool) -> fmt::Result {
+        match self {
+            Type::Union(_) => write!(f, "({})", self),
+            Type::Function { .. } if wrap_functions => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Type::Basic(name) => name.clone(),
-            Type::Array(elem_type) => format!("{}[]", elem_type.to_string()),
+            Type::Basic { name, .. } => write!(f, "{}", name),
+            Type::Array(elem_type) => {
+                elem_type.fmt_operand(f, true)?;
+                write!(f, "[]")
+            }
             Type::Function {
                 params,
                 return_type,
             } => {
                 let param_strs: Vec<String> = params.iter().map(|p| p.to_string()).collect();
-                format!(
-                    "fn({}) -> {}",
-                    param_strs.join(", "),
-                    return_type.to_string()
-                )
+                write!(f, "fn({}) -> ", param_strs.join(", "))?;
+                return_type.fmt_operand(f, false)
+            }
+            Type::Optional(inner) => {
+                inner.fmt_operand(f, true)?;
+                write!(f, "?")
             }
-            Type::Optional(inner) => format!("{}?", inner.to_string()),
             Type::Union(types) => {
                 let type_strs: Vec<String> = types.iter().map(|t| t.to_string()).collect();
-                type_strs.join(" | ")
+                write!(f, "{}", type_strs.join(" | "))
             }
         }
     }
@@ -0,0 +1,375 @@
+use crate::ast::{Expression, LiteralValue, Node, Span};
+
+pub type BlockId = usize;
+
+/// A run of statements that always execute together, from the first to the
+/// last, after which control moves to one of the successors.
+#[derive(Debug, Clone, Default)]
+pub struct BasicBlock {
+    pub statements: Vec<Span>,
+    pub successors: Vec<BlockId>,
+}
+
+/// A statement of the body, nested ones included.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub span: Span,
+    /// The block the statement starts in.
+    pub block: BlockId,
+    /// The statement list it belongs to, numbered in source order.
+    pub list: usize,
+}
+
+/// A statement list guarded by a condition that is always `condition`, so
+/// it never runs.
+#[derive(Debug, Clone)]
+pub struct DeadBranch {
+    pub list: usize,
+    pub condition: bool,
+    pub is_loop: bool,
+}
+
+/// The control-flow graph of one function body. Conditions that are the
+/// literals `true` or `false` only get the edge they take.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    /// Every statement of the body in source order, parents before their
+    /// children.
+    pub statements: Vec<Statement>,
+    pub dead_branches: Vec<DeadBranch>,
+    /// `while true` loops that no reachable `break` or `return` leaves, up to
+    /// the end of their condition.
+    pub infinite_loops: Vec<Span>,
+    /// The block control is in after the last statement of the body.
+    pub end: BlockId,
+}
+
+impl ControlFlowGraph {
+    pub const ENTRY: BlockId = 0;
+    /// Where every `return` and the end of the body lead.
+    pub const EXIT: BlockId = 1;
+
+    pub fn build(body: &[Node]) -> Self {
+        let mut builder = Builder {
+            graph: ControlFlowGraph {
+                blocks: vec![BasicBlock::default(), BasicBlock::default()],
+                statements: Vec::new(),
+                dead_branches: Vec::new(),
+                infinite_loops: Vec::new(),
+                end: Self::ENTRY,
+            },
+            lists: 0,
+            loops: Vec::new(),
+            endless: Vec::new(),
+        };
+        let end = builder.statements(body, Self::ENTRY);
+        builder.edge(end, Self::EXIT);
+        builder.graph.end = end;
+
+        // Only what the loop can actually reach gets it out: a `break` in a
+        // branch that never runs does not.
+        let mut graph = builder.graph;
+        for (span, header, after) in builder.endless {
+            let reached = graph.reachable_from(header);
+            if !reached[after] && !reached[Self::EXIT] {
+                graph.infinite_loops.push(span);
+            }
+        }
+        graph
+    }
+
+    /// Whether control can run past the last statement of the body rather
+    /// than always leaving it early or staying in a loop that never ends.
+    pub fn falls_through(&self) -> bool {
+        self.reachable()[self.end]
+    }
+
+    /// Which blocks some path from the entry reaches, by block.
+    pub fn reachable(&self) -> Vec<bool> {
+        self.reachable_from(Self::ENTRY)
+    }
+
+    fn reachable_from(&self, start: BlockId) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut pending = vec![start];
+        while let Some(block) = pending.pop() {
+            if !reachable[block] {
+                reachable[block] = true;
+                pending.extend(&self.blocks[block].successors);
+            }
+        }
+        reachable
+    }
+}
+
+struct Loop {
+    continue_to: BlockId,
+    break_to: BlockId,
+}
+
+struct Builder {
+    graph: ControlFlowGraph,
+    lists: usize,
+    /// The loops around the statement being added, innermost last.
+    loops: Vec<Loop>,
+    /// The span, header and following block of each `while true` loop.
+    endless: Vec<(Span, BlockId, BlockId)>,
+}
+
+impl Builder {
+    fn new_block(&mut self) -> BlockId {
+        self.graph.blocks.push(BasicBlock::default());
+        self.graph.blocks.len() - 1
+    }
+
+    fn edge(&mut self, from: BlockId, to: BlockId) {
+        self.graph.blocks[from].successors.push(to);
+    }
+
+    /// Adds `nodes` starting in block `current` and returns the block
+    /// control is in after them.
+    fn statements(&mut self, nodes: &[Node], mut current: BlockId) -> BlockId {
+        let list = self.lists;
+        self.lists += 1;
+        for node in nodes {
+            current = self.statement(node, current, list);
+        }
+        current
+    }
+
+    /// Marks the statement list added next as one that never runs.
+    fn dead_branch(&mut self, condition: bool, is_loop: bool) {
+        self.graph.dead_branches.push(DeadBranch {
+            list: self.lists,
+            condition,
+            is_loop,
+        });
+    }
+
+    fn statement(&mut self, node: &Node, current: BlockId, list: usize) -> BlockId {
+        self.graph.statements.push(Statement {
+            span: node.span(),
+            block: current,
+            list,
+        });
+        self.graph.blocks[current].statements.push(node.span());
+
+        match node {
+            Node::ReturnStatement { .. } => {
+                self.edge(current, ControlFlowGraph::EXIT);
+                self.new_block()
+            }
+            Node::BreakStatement { .. } | Node::ContinueStatement { .. } => {
+                let is_break = matches!(node, Node::BreakStatement { .. });
+                if let Some(target) = self.loops.last() {
+                    let to = if is_break {
+                        target.break_to
+                    } else {
+                        target.continue_to
+                    };
+                    self.edge(current, to);
+                }
+                self.new_block()
+            }
+            Node::IfStatement {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let constant = constant_condition(condition);
+
+                let then_start = self.new_block();
+                match constant {
+                    Some(false) => self.dead_branch(false, false),
+                    _ => self.edge(current, then_start),
+                }
+                let then_end = self.statements(then_branch, then_start);
+
+                let join = self.new_block();
+                self.edge(then_end, join);
+                match else_branch {
+                    Some(else_branch) => {
+                        let else_start = self.new_block();
+                        match constant {
+                            Some(true) => self.dead_branch(true, false),
+                            _ => self.edge(current, else_start),
+                        }
+                        let else_end = self.statements(else_branch, else_start);
+                        self.edge(else_end, join);
+                    }
+                    None if constant != Some(true) => self.edge(current, join),
+                    None => {}
+                }
+                join
+            }
+            Node::WhileStatement {
+                condition, body, ..
+            } => {
+                let constant = constant_condition(condition);
+                let header = self.new_block();
+                let body_start = self.new_block();
+                let after = self.new_block();
+                self.edge(current, header);
+                match constant {
+                    Some(false) => self.dead_branch(false, true),
+                    _ => self.edge(header, body_start),
+                }
+                if constant != Some(true) {
+                    self.edge(header, after);
+                }
+
+                self.loop_body(body, body_start, header, after);
+                if constant == Some(true) {
+                    let span = Span::new(node.span().start, condition.span().end);
+                    self.endless.push((span, header, after));
+                }
+                after
+            }
+            Node::ForStatement {
+                condition, body, ..
+            } => {
+                let header = self.new_block();
+                let body_start = self.new_block();
+                let step = self.new_block();
+                let after = self.new_block();
+                self.edge(current, header);
+                self.edge(header, body_start);
+                if condition.is_some() {
+                    self.edge(header, after);
+                }
+                self.edge(step, header);
+                self.loop_body(body, body_start, step, after);
+                after
+            }
+            Node::ForInStatement { body, .. } => {
+                let header = self.new_block();
+                let body_start = self.new_block();
+                let after = self.new_block();
+                self.edge(current, header);
+                self.edge(header, body_start);
+                self.edge(header, after);
+                self.loop_body(body, body_start, header, after);
+                after
+            }
+            Node::Block { statements, .. } => self.statements(statements, current),
+            _ => current,
+        }
+    }
+
+    /// Adds the body of a loop that goes on at `continue_to` after each pass
+    /// and leaves to `break_to`.
+    fn loop_body(
+        &mut self,
+        body: &[Node],
+        start: BlockId,
+        continue_to: BlockId,
+        break_to: BlockId,
+    ) {
+        self.loops.push(Loop {
+            continue_to,
+            break_to,
+        });
+        let end = self.statements(body, start);
+        self.edge(end, continue_to);
+        self.loops.pop();
+    }
+}
+
+/// The value of a condition that is a boolean literal, possibly negated.
+fn constant_condition(condition: &Expression) -> Option<bool> {
+    match condition {
+        Expression::Literal {
+            value: LiteralValue::Boolean(value),
+            ..
+        } => Some(*value),
+        Expression::UnaryOperation {
+            operator, operand, ..
+        } if operator == "!" => constant_condition(operand).map(|value| !value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// The graph of the body of the first function in `source`.
+    fn graph(source: &str) -> ControlFlowGraph {
+        let result = parser::parse(source);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        match &result.ast.nodes[0] {
+            Node::FunctionDeclaration { body, .. } => ControlFlowGraph::build(body),
+            node => panic!("expected a function, found {:?}", node),
+        }
+    }
+
+    #[test]
+    fn bodies_fall_through_unless_every_path_leaves() {
+        let cases = [
+            ("fn f() {}", true),
+            ("fn f(x) { if x { return 1 } }", true),
+            ("fn f(x) { if x { return 1 } else { return 2 } }", false),
+            ("fn f(x) { while x { return 1 } }", true),
+            ("fn f() { while true { print(1) } }", false),
+            ("fn f(x) { while true { if x { break } } }", true),
+            ("fn f() { while true { return 1 } }", false),
+            ("fn f() { if true { return 1 } }", false),
+            ("fn f() { if false { return 1 } }", true),
+            ("fn f(xs) { for x in xs { return x } }", true),
+            ("fn f(x) { { return x } }", false),
+        ];
+        for (source, falls_through) in cases {
+            assert_eq!(graph(source).falls_through(), falls_through, "{}", source);
+        }
+    }
+
+    #[test]
+    fn statements_record_their_block_and_list() {
+        let graph = graph("fn f(x) {\n  if x { return 1 }\n  print(x)\n}");
+        let reachable = graph.reachable();
+        let lists: Vec<(usize, bool)> = graph
+            .statements
+            .iter()
+            .map(|statement| (statement.list, reachable[statement.block]))
+            .collect();
+        assert_eq!(lists, vec![(0, true), (1, true), (0, true)]);
+        assert!(graph.dead_branches.is_empty());
+        assert!(graph.infinite_loops.is_empty());
+    }
+
+    #[test]
+    fn constant_conditions_take_one_edge() {
+        let graph =
+            graph("fn f() {\n  if false { print(1) } else { print(2) }\n  while false {}\n}");
+        let branches: Vec<(usize, bool, bool)> = graph
+            .dead_branches
+            .iter()
+            .map(|branch| (branch.list, branch.condition, branch.is_loop))
+            .collect();
+        assert_eq!(branches, vec![(1, false, false), (3, false, true)]);
+        let reachable = graph.reachable();
+        let printed: Vec<bool> = graph.statements[1..3]
+            .iter()
+            .map(|statement| reachable[statement.block])
+            .collect();
+        assert_eq!(printed, vec![false, true]);
+    }
+
+    #[test]
+    fn only_reachable_exits_end_a_loop() {
+        let cases = [
+            ("fn f() { while true { if false { break } } }", 1),
+            ("fn f() { while true { if false { return 1 } } }", 1),
+            ("fn f() { while true { return 1\n break } }", 0),
+            ("fn f(x) { while true { if x { break } } }", 0),
+            ("fn f() { while true { while true { break } } }", 1),
+            ("fn f() { return 1\n while true { break } }", 0),
+        ];
+        for (source, loops) in cases {
+            assert_eq!(graph(source).infinite_loops.len(), loops, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, Diagnostic, Range, TextEdit, Url,
+    WorkspaceEdit,
+};
+
+use crate::analyzer::Document;
+use crate::ast::Span;
+use crate::resolver::DeclarationKind;
+
+/// Quick fixes for what is wrong in `range`. `diagnostics` are the ones the
+/// client shows there; a fix is linked to those it resolves.
+pub fn get_code_actions(
+    document: &Document,
+    uri: &Url,
+    range: Range,
+    diagnostics: &[Diagnostic],
+) -> Vec<CodeActionOrCommand> {
+    let line_index = &document.line_index;
+    let (Some(start), Some(end)) = (line_index.offset(range.start), line_index.offset(range.end))
+    else {
+        return Vec::new();
+    };
+
+    let mut actions = Vec::new();
+    let mut fixed = Vec::new();
+    for assignment in &document.const_assignments {
+        let touched = assignment.span.start <= end && start <= assignment.span.end;
+        if !touched || fixed.contains(&assignment.declaration) {
+            continue;
+        }
+        fixed.push(assignment.declaration);
+
+        let symbol = &document.resolution.symbols[assignment.declaration];
+        let keyword = Span::new(symbol.span.start, symbol.span.start + "const".len());
+        if document.content.get(keyword.start..keyword.end) != Some("const") {
+            continue;
+        }
+
+        // Variables become `let`; fields are mutable without a keyword.
+        let (title, edit) = match symbol.kind {
+            DeclarationKind::Variable => (
+                format!("Change '{}' to a 'let' variable", symbol.name),
+                TextEdit {
+                    range: line_index.range(keyword),
+                    new_text: "let".to_string(),
+                },
+            ),
+            _ => (
+                format!("Remove 'const' from field '{}'", symbol.name),
+                TextEdit {
+                    range: line_index.range(Span::new(keyword.start, symbol.name_span.start)),
+                    new_text: String::new(),
+                },
+            ),
+        };
+
+        let assignment_range = line_index.range(assignment.span);
+        let resolved: Vec<Diagnostic> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.range == assignment_range)
+            .cloned()
+            .collect();
+
+        actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+            title,
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: (!resolved.is_empty()).then_some(resolved),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                ..WorkspaceEdit::default()
+            }),
+            is_preferred: Some(true),
+            ..CodeAction::default()
+        }));
+    }
+
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::BurnAnalyzer;
+    use crate::typechecker::BurnTypeChecker;
+    use std::sync::Arc;
+    use tower_lsp::lsp_types::Position;
+
+    const URI: &str = "file:///actions.bn";
+
+    /// The quick fixes offered for `line` after analyzing `source`, as their
+    /// titles and the source once each edit is applied.
+    fn fixes(source: &str, line: u32) -> Vec<(String, String)> {
+        let analyzer = BurnAnalyzer::new(Arc::new(BurnTypeChecker::new()));
+        analyzer.open_document(URI, source.to_string());
+        analyzer.analyze_document(URI);
+        let document = analyzer.get_document(URI).unwrap();
+
+        let length = source.lines().nth(line as usize).unwrap().len() as u32;
+        let range = Range::new(Position::new(line, 0), Position::new(line, length));
+        get_code_actions(&document, &Url::parse(URI).unwrap(), range, &[])
+            .into_iter()
+            .map(|action| {
+                let CodeActionOrCommand::CodeAction(action) = action else {
+                    panic!("expected a code action");
+                };
+                let edits = &action.edit.unwrap().changes.unwrap()[&Url::parse(URI).unwrap()];
+                let mut fixed = source.to_string();
+                for edit in edits {
+                    let start = document.line_index.offset(edit.range.start).unwrap();
+                    let end = document.line_index.offset(edit.range.end).unwrap();
+                    fixed.replace_range(start..end, &edit.new_text);
+                }
+                (action.title, fixed)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn constants_that_are_assigned_can_be_made_mutable() {
+        let source = "const a = 1\na += 1\na = 2";
+        let expected = vec![(
+            "Change 'a' to a 'let' variable".to_string(),
+            "let a = 1\na += 1\na = 2".to_string(),
+        )];
+        assert_eq!(fixes(source, 1), expected);
+        assert_eq!(fixes(source, 2), expected);
+        assert_eq!(fixes(source, 0), Vec::new());
+
+        assert_eq!(
+            fixes("struct P { const x: int }\nlet p = P()\np.x = 1", 2),
+            vec![(
+                "Remove 'const' from field 'x'".to_string(),
+                "struct P { x: int }\nlet p = P()\np.x = 1".to_string(),
+            )]
+        );
+    }
+
+    #[test]
+    fn nothing_is_offered_before_the_document_is_analyzed() {
+        let analyzer = BurnAnalyzer::new(Arc::new(BurnTypeChecker::new()));
+        analyzer.open_document(URI, "const a = 1\na = 2".to_string());
+        let document = analyzer.get_document(URI).unwrap();
+        let range = Range::new(Position::new(1, 0), Position::new(1, 5));
+        assert!(get_code_actions(&document, &Url::parse(URI).unwrap(), range, &[]).is_empty());
+    }
+}
@@ -0,0 +1,455 @@
+use crate::ast::Span;
+use crate::lexer::TokenKind;
+
+/// Kinds of interior nodes in the concrete syntax tree. Leaves are tokens and
+/// keep their `TokenKind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+    SourceFile,
+    VariableDeclaration,
+    FunctionDeclaration,
+    ParameterList,
+    Parameter,
+    StructDeclaration,
+    StructField,
+    ClassDeclaration,
+    ImportDeclaration,
+    ExpressionStatement,
+    IfStatement,
+    ElseClause,
+    WhileStatement,
+    ForStatement,
+    ForInStatement,
+    ReturnStatement,
+    BreakStatement,
+    ContinueStatement,
+    Block,
+    Type,
+    Literal,
+    Variable,
+    ParenExpression,
+    BinaryExpression,
+    UnaryExpression,
+    AssignmentExpression,
+    CallExpression,
+    ArgumentList,
+    PropertyAccess,
+    IndexExpression,
+    ArrayLiteral,
+    ObjectLiteral,
+    ObjectProperty,
+    LambdaExpression,
+    Error,
+}
+
+/// A lossless syntax tree: concatenating the text of every token, trivia
+/// included, reproduces the source exactly. Nodes only store their length, so
+/// absolute offsets are computed while walking down from the root and whole
+/// subtrees can be moved around without rewriting them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub children: Vec<SyntaxElement>,
+    len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxElement {
+    pub fn text_len(&self) -> usize {
+        match self {
+            SyntaxElement::Node(node) => node.len,
+            SyntaxElement::Token(token) => token.text.len(),
+        }
+    }
+}
+
+impl SyntaxNode {
+    pub fn new(kind: SyntaxKind, children: Vec<SyntaxElement>) -> Self {
+        let len = children.iter().map(SyntaxElement::text_len).sum();
+        SyntaxNode {
+            kind,
+            children,
+            len,
+        }
+    }
+
+    pub fn text_len(&self) -> usize {
+        self.len
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.len);
+        for (_, token) in self.tokens(0) {
+            text.push_str(&token.text);
+        }
+        text
+    }
+
+    /// Every token below this node, in source order, paired with its absolute
+    /// start offset given that this node starts at `offset`.
+    pub fn tokens(&self, offset: usize) -> Vec<(usize, &SyntaxToken)> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(offset, &mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, mut offset: usize, tokens: &mut Vec<(usize, &'a SyntaxToken)>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(offset, tokens),
+                SyntaxElement::Token(token) => tokens.push((offset, token)),
+            }
+            offset += child.text_len();
+        }
+    }
+
+    /// Direct children paired with their absolute spans, given that this node
+    /// starts at `offset`.
+    pub fn child_spans(&self, offset: usize) -> Vec<Span> {
+        let mut spans = Vec::with_capacity(self.children.len());
+        let mut start = offset;
+        for child in &self.children {
+            spans.push(Span::new(start, start + child.text_len()));
+            start += child.text_len();
+        }
+        spans
+    }
+
+    /// Child nodes paired with their absolute spans, given that this node
+    /// starts at `offset`.
+    pub fn child_nodes(&self, offset: usize) -> Vec<(Span, &SyntaxNode)> {
+        self.child_spans(offset)
+            .into_iter()
+            .zip(&self.children)
+            .filter_map(|(span, child)| match child {
+                SyntaxElement::Node(node) => Some((span, node)),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+
+    /// The outermost node covering exactly `span`, which is how AST nodes are
+    /// mapped back onto the syntax they were built from.
+    pub fn find_node(&self, span: Span) -> Option<&SyntaxNode> {
+        self.find_node_from(0, span)
+    }
+
+    fn find_node_from(&self, offset: usize, span: Span) -> Option<&SyntaxNode> {
+        if offset == span.start && self.len == span.len() {
+            return Some(self);
+        }
+
+        self.child_nodes(offset)
+            .into_iter()
+            .find(|(child_span, _)| child_span.start <= span.start && span.end <= child_span.end)
+            .and_then(|(child_span, node)| node.find_node_from(child_span.start, span))
+    }
+}
+
+/// Opaque marker for wrapping already-built children into a new node.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint(usize);
+
+/// Builds a `SyntaxNode` bottom-up as the parser consumes tokens.
+pub struct CstBuilder {
+    stack: Vec<(SyntaxKind, Vec<SyntaxElement>)>,
+}
+
+impl CstBuilder {
+    pub fn new() -> Self {
+        CstBuilder {
+            stack: vec![(SyntaxKind::SourceFile, Vec::new())],
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn start_node(&mut self, kind: SyntaxKind) {
+        self.stack.push((kind, Vec::new()));
+    }
+
+    pub fn token(&mut self, kind: TokenKind, text: &str) {
+        self.current().push(SyntaxElement::Token(SyntaxToken {
+            kind,
+            text: text.to_string(),
+        }));
+    }
+
+    pub fn finish_node(&mut self) {
+        let (kind, children) = self.stack.pop().expect("unbalanced finish_node");
+        self.current()
+            .push(SyntaxElement::Node(SyntaxNode::new(kind, children)));
+    }
+
+    /// Closes every node opened after the builder was at `depth`.
+    pub fn finish_to(&mut self, depth: usize) {
+        while self.stack.len() > depth {
+            self.finish_node();
+        }
+    }
+
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        Checkpoint(self.current().len())
+    }
+
+    /// Opens a node that adopts everything added since `checkpoint`.
+    pub fn start_node_at(&mut self, checkpoint: Checkpoint, kind: SyntaxKind) {
+        let children = self.current().split_off(checkpoint.0);
+        self.stack.push((kind, children));
+    }
+
+    pub fn finish(mut self) -> SyntaxNode {
+        self.finish_to(1);
+        let (kind, children) = self.stack.pop().expect("builder has no root");
+        SyntaxNode::new(kind, children)
+    }
+
+    fn current(&mut self) -> &mut Vec<SyntaxElement> {
+        &mut self.stack.last_mut().expect("builder has no open node").1
+    }
+}
+
+impl Default for CstBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Expression, Node};
+    use crate::parser;
+
+    const SOURCES: [&str; 6] = [
+        "  // leading\nlet x = 1 + 2 * (3) // tail\nfn f(a: int): int { return a.b }\n\n",
+        "struct P { x: int = 1, const y: int[]? }\nclass C {\n  n: string\n  fn m() { print(n) }\n}\n",
+        "import { a, b } from \"lib\"\nif a { b() } else if !a { } else { }\n\
+         while x < 3 { x += 1 }\nfor i in 0..10 { continue }\nfor (let i = 0; i < 3; i += 1) { break; }\n",
+        "let o = { x: 1, \"y z\": [a[0], -b], w }\nlet f = fn(x: int) -> int { return x * 2 }\n\
+         a.b.c(d)[i] = \"s ${o.x} /* not a comment */\"\n/* block\n comment */\n",
+        "let x = \nlet y = a +\nfoo(a b)\nstruct S { a: , b: int }\n}}",
+        "}}{ fn ( let \"open\n  /* unterminated",
+    ];
+
+    /// Every node of the tree with its absolute span.
+    fn cst_nodes(node: &SyntaxNode, offset: usize, nodes: &mut Vec<(Span, SyntaxKind)>) {
+        nodes.push((Span::new(offset, offset + node.text_len()), node.kind));
+        for (span, child) in node.child_nodes(offset) {
+            cst_nodes(child, span.start, nodes);
+        }
+    }
+
+    /// Every AST node and expression with the CST node kind it was built
+    /// from. Zero-width placeholders for missing expressions and the parts
+    /// of interpolated strings, which live inside a string token, have no
+    /// node of their own.
+    fn ast_nodes(nodes: &[Node], found: &mut Vec<(Span, SyntaxKind)>) {
+        for node in nodes {
+            let kind = match node {
+                Node::VariableDeclaration { .. } => SyntaxKind::VariableDeclaration,
+                Node::FunctionDeclaration { .. } => SyntaxKind::FunctionDeclaration,
+                Node::StructDeclaration { .. } => SyntaxKind::StructDeclaration,
+                Node::ClassDeclaration { .. } => SyntaxKind::ClassDeclaration,
+                Node::ImportDeclaration { .. } => SyntaxKind::ImportDeclaration,
+                Node::ExpressionStatement { .. } => SyntaxKind::ExpressionStatement,
+                Node::ReturnStatement { .. } => SyntaxKind::ReturnStatement,
+                Node::BreakStatement { .. } => SyntaxKind::BreakStatement,
+                Node::ContinueStatement { .. } => SyntaxKind::ContinueStatement,
+                Node::IfStatement { .. } => SyntaxKind::IfStatement,
+                Node::WhileStatement { .. } => SyntaxKind::WhileStatement,
+                Node::ForStatement { .. } => SyntaxKind::ForStatement,
+                Node::ForInStatement { .. } => SyntaxKind::ForInStatement,
+                Node::Block { .. } => SyntaxKind::Block,
+                Node::Error { .. } => SyntaxKind::Error,
+            };
+            found.push((node.span(), kind));
+
+            match node {
+                Node::VariableDeclaration { initializer, .. } => {
+                    ast_expressions(initializer.as_deref(), found)
+                }
+                Node::FunctionDeclaration { body, .. } => ast_nodes(body, found),
+                Node::StructDeclaration { fields, .. } => {
+                    for field in fields {
+                        found.push((field.span, SyntaxKind::StructField));
+                        ast_expressions(field.initializer.as_deref(), found);
+                    }
+                }
+                Node::ClassDeclaration {
+                    methods,
+                    properties,
+                    ..
+                } => {
+                    for property in properties {
+                        found.push((property.span, SyntaxKind::StructField));
+                        ast_expressions(property.initializer.as_deref(), found);
+                    }
+                    ast_nodes(methods, found);
+                }
+                Node::ExpressionStatement { expression, .. } => {
+                    ast_expressions(Some(expression), found)
+                }
+                Node::ReturnStatement { expression, .. } => {
+                    ast_expressions(expression.as_deref(), found)
+                }
+                Node::IfStatement {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    ast_expressions(Some(condition), found);
+                    ast_nodes(then_branch, found);
+                    ast_nodes(else_branch.as_deref().unwrap_or_default(), found);
+                }
+                Node::WhileStatement {
+                    condition, body, ..
+                } => {
+                    ast_expressions(Some(condition), found);
+                    ast_nodes(body, found);
+                }
+                Node::ForStatement {
+                    initializer,
+                    condition,
+                    increment,
+                    body,
+                    ..
+                } => {
+                    ast_nodes(
+                        initializer
+                            .as_deref()
+                            .map(std::slice::from_ref)
+                            .unwrap_or_default(),
+                        found,
+                    );
+                    ast_expressions(condition.as_deref(), found);
+                    ast_expressions(increment.as_deref(), found);
+                    ast_nodes(body, found);
+                }
+                Node::ForInStatement { iterable, body, .. } => {
+                    ast_expressions(Some(iterable), found);
+                    ast_nodes(body, found);
+                }
+                Node::Block { statements, .. } => ast_nodes(statements, found),
+                Node::ImportDeclaration { .. }
+                | Node::BreakStatement { .. }
+                | Node::ContinueStatement { .. }
+                | Node::Error { .. } => {}
+            }
+        }
+    }
+
+    fn ast_expressions(expression: Option<&Expression>, found: &mut Vec<(Span, SyntaxKind)>) {
+        let Some(expression) = expression else {
+            return;
+        };
+        let kind = match expression {
+            Expression::Literal { .. } | Expression::InterpolatedString { .. } => {
+                SyntaxKind::Literal
+            }
+            Expression::Variable { .. } => SyntaxKind::Variable,
+            Expression::BinaryOperation { .. } => SyntaxKind::BinaryExpression,
+            Expression::UnaryOperation { .. } => SyntaxKind::UnaryExpression,
+            Expression::Call { .. } => SyntaxKind::CallExpression,
+            Expression::PropertyAccess { .. } => SyntaxKind::PropertyAccess,
+            Expression::ArrayAccess { .. } => SyntaxKind::IndexExpression,
+            Expression::Assignment { .. } => SyntaxKind::AssignmentExpression,
+            Expression::ArrayLiteral { .. } => SyntaxKind::ArrayLiteral,
+            Expression::ObjectLiteral { .. } => SyntaxKind::ObjectLiteral,
+            Expression::Lambda { .. } => SyntaxKind::LambdaExpression,
+            Expression::Error { span } if span.is_empty() => return,
+            Expression::Error { .. } => SyntaxKind::Error,
+        };
+        found.push((expression.span(), kind));
+
+        match expression {
+            Expression::BinaryOperation { left, right, .. }
+            | Expression::Assignment {
+                target: left,
+                value: right,
+                ..
+            }
+            | Expression::ArrayAccess {
+                array: left,
+                index: right,
+                ..
+            } => {
+                ast_expressions(Some(left), found);
+                ast_expressions(Some(right), found);
+            }
+            Expression::UnaryOperation { operand, .. } => ast_expressions(Some(operand), found),
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                ast_expressions(Some(callee), found);
+                for argument in arguments {
+                    ast_expressions(Some(argument), found);
+                }
+            }
+            Expression::PropertyAccess { object, .. } => ast_expressions(Some(object), found),
+            Expression::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    ast_expressions(Some(element), found);
+                }
+            }
+            Expression::ObjectLiteral { properties, .. } => {
+                for property in properties {
+                    found.push((property.span, SyntaxKind::ObjectProperty));
+                    ast_expressions(Some(&property.value), found);
+                }
+            }
+            Expression::Lambda { body, .. } => ast_nodes(body, found),
+            Expression::Literal { .. }
+            | Expression::Variable { .. }
+            | Expression::InterpolatedString { .. }
+            | Expression::Error { .. } => {}
+        }
+    }
+
+    #[test]
+    fn text_round_trips_to_the_source() {
+        for source in SOURCES {
+            let result = parser::parse(source);
+            assert_eq!(result.cst.text(), source);
+            assert_eq!(result.cst.text_len(), source.len());
+            assert_eq!(result.cst.kind, SyntaxKind::SourceFile);
+        }
+    }
+
+    #[test]
+    fn ast_spans_match_cst_nodes() {
+        for source in SOURCES {
+            let result = parser::parse(source);
+            let mut cst = Vec::new();
+            cst_nodes(&result.cst, 0, &mut cst);
+            let mut ast = Vec::new();
+            ast_nodes(&result.ast.nodes, &mut ast);
+
+            for (span, kind) in ast {
+                assert!(
+                    cst.contains(&(span, kind)),
+                    "no {:?} node at {:?} ({:?}) in {:?}",
+                    kind,
+                    span,
+                    &source[span.start..span.end],
+                    source
+                );
+                assert!(result.cst.find_node(span).is_some());
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use tower_lsp::lsp_types::{FormattingOptions, TextEdit};
+
+use crate::ast::Span;
+use crate::cst::SyntaxNode;
+use crate::lexer::TokenKind;
+use crate::utils::LineIndex;
+
+/// Re-indents every line one level deeper than the line that opened the
+/// innermost enclosing bracket, so brackets opened together on one line
+/// indent their contents once, and strips trailing whitespace.
+/// Only whitespace tokens that contain a line break and the trailing blanks
+/// of `//` comments are ever rewritten, so code and comment text are left
+/// untouched and each edit lies within a single token.
+pub fn format(
+    root: &SyntaxNode,
+    line_index: &LineIndex,
+    options: &FormattingOptions,
+) -> Vec<TextEdit> {
+    let indent_unit = if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".to_string()
+    };
+
+    let tokens = root.tokens(0);
+    // The levels of the lines the open brackets are on, innermost last.
+    let mut open = Vec::new();
+    let mut line_level = 0usize;
+    let mut edits = Vec::new();
+
+    for (i, &(offset, token)) in tokens.iter().enumerate() {
+        let expected = match token.kind {
+            TokenKind::LeftBrace | TokenKind::LeftParen | TokenKind::LeftBracket => {
+                open.push(line_level);
+                continue;
+            }
+            TokenKind::RightBrace | TokenKind::RightParen | TokenKind::RightBracket => {
+                open.pop();
+                continue;
+            }
+            TokenKind::Whitespace => {
+                let newlines = "\n".repeat(token.text.matches('\n').count());
+                match tokens.get(i + 1).map(|(_, next)| next.kind) {
+                    Some(next) if !newlines.is_empty() => {
+                        line_level = match open.last() {
+                            Some(&opened) if is_closing(next) => opened,
+                            Some(&opened) => opened + 1,
+                            None => 0,
+                        };
+                        newlines + &indent_unit.repeat(line_level)
+                    }
+                    Some(_) => continue,
+                    // Trailing whitespace at the end of the file collapses to
+                    // a single final newline.
+                    None if newlines.is_empty() => String::new(),
+                    None => "\n".to_string(),
+                }
+            }
+            TokenKind::Comment if token.text.starts_with("//") => {
+                let kept = token.text.trim_end().len();
+                if kept < token.text.len() {
+                    edits.push(TextEdit {
+                        range: line_index
+                            .range(Span::new(offset + kept, offset + token.text.len())),
+                        new_text: String::new(),
+                    });
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        if token.text != expected {
+            edits.push(TextEdit {
+                range: line_index.range(Span::new(offset, offset + token.text.len())),
+                new_text: expected,
+            });
+        }
+    }
+
+    edits
+}
+
+fn is_closing(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::RightBrace | TokenKind::RightParen | TokenKind::RightBracket
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// `source` with the formatting edits applied, indenting by two spaces.
+    fn formatted(source: &str) -> String {
+        let result = parser::parse(source);
+        let line_index = LineIndex::new(source);
+        let options = FormattingOptions {
+            tab_size: 2,
+            insert_spaces: true,
+            ..FormattingOptions::default()
+        };
+
+        let mut text = source.to_string();
+        for edit in format(&result.cst, &line_index, &options).iter().rev() {
+            let start = line_index.offset(edit.range.start).unwrap();
+            let end = line_index.offset(edit.range.end).unwrap();
+            text.replace_range(start..end, &edit.new_text);
+        }
+        text
+    }
+
+    #[test]
+    fn lines_are_indented_by_nesting() {
+        assert_eq!(
+            formatted("fn f(x) {\nif x {\n      print([\n1,\n    2\n])\n   }\n}\n"),
+            "fn f(x) {\n  if x {\n    print([\n      1,\n      2\n    ])\n  }\n}\n"
+        );
+        assert_eq!(
+            formatted("if a {\nprint(1)\n} else {\nlet b = [1,\n2]\n}\n"),
+            "if a {\n  print(1)\n} else {\n  let b = [1,\n    2]\n}\n"
+        );
+        assert_eq!(
+            formatted("struct P {\n\tx: int,\n\ty: int\n\t}\n"),
+            "struct P {\n  x: int,\n  y: int\n}\n"
+        );
+    }
+
+    #[test]
+    fn blank_lines_are_kept_without_their_blanks() {
+        assert_eq!(
+            formatted("let a = 1   \n   \n\nlet b = 2\n"),
+            "let a = 1\n\n\nlet b = 2\n"
+        );
+    }
+
+    #[test]
+    fn comments_keep_their_text_but_not_trailing_blanks() {
+        assert_eq!(
+            formatted("fn f() {\n    // c   \n  /* keep   \n   as is */\nreturn 1 // done \t\n}\n"),
+            "fn f() {\n  // c\n  /* keep   \n   as is */\n  return 1 // done\n}\n"
+        );
+    }
+
+    #[test]
+    fn files_end_with_a_single_newline() {
+        assert_eq!(formatted("let a = 1\n\n\n"), "let a = 1\n");
+        assert_eq!(formatted("let a = 1  "), "let a = 1");
+        assert_eq!(formatted("let a = 1"), "let a = 1");
+        let formatted_once = formatted("fn f() {\nreturn 1\n}\n");
+        assert_eq!(formatted(&formatted_once), formatted_once);
+    }
+}
@@ -1,24 +1,35 @@
 use std::sync::Arc;
 use tower_lsp::jsonrpc::Result;
-use tower_lsp::lsp_types::{Hover, MarkedString, Position, Range};
+use tower_lsp::lsp_types::{Hover, Position};
 
+use crate::analyzer::Document;
+use crate::ast::Span;
+use crate::resolver::DeclarationKind;
 use crate::typechecker::BurnTypeChecker;
-use crate::utils;
 
 pub fn on_hover(
-    document: &str,
+    document: &Document,
     position: Position,
     type_checker: &Arc<BurnTypeChecker>,
 ) -> Result<Option<Hover>> {
-    let offset = utils::position_to_offset(document, position)?;
-    let text = document;
+    let text = document.content.as_str();
+    let Some(offset) = document.line_index.offset(position) else {
+        return Ok(None);
+    };
+
+    if let Some(hover) = get_symbol_hover(document, offset) {
+        return Ok(Some(hover));
+    }
 
     if let Some((object_name, property_name)) = check_for_dot_access(text, offset) {
-        return get_property_hover(object_name, property_name, type_checker);
+        return get_property_hover(document, offset, object_name, property_name, type_checker);
     }
 
     if let Some(word_range) = get_word_range_at_position(text, offset) {
         let word = &text[word_range.0..word_range.1];
+        let word_range = document
+            .line_index
+            .range(Span::new(word_range.0, word_range.1));
 
         if let Some(var_type) = type_checker.get_variable_type(word) {
             return Ok(Some(Hover {
@@ -28,10 +39,7 @@ pub fn on_hover(
                         value: format!("**{}**: {}", word, var_type),
                     },
                 ),
-                range: Some(utils::create_range(
-                    utils::offset_to_position(text, word_range.0)?,
-                    utils::offset_to_position(text, word_range.1)?,
-                )),
+                range: Some(word_range),
             }));
         }
 
@@ -43,10 +51,7 @@ pub fn on_hover(
                         value: format!("**{}**: {}", word, keyword_info),
                     },
                 ),
-                range: Some(utils::create_range(
-                    utils::offset_to_position(text, word_range.0)?,
-                    utils::offset_to_position(text, word_range.1)?,
-                )),
+                range: Some(word_range),
             }));
         }
 
@@ -58,10 +63,7 @@ pub fn on_hover(
                         value: builtin_info,
                     },
                 ),
-                range: Some(utils::create_range(
-                    utils::offset_to_position(text, word_range.0)?,
-                    utils::offset_to_position(text, word_range.1)?,
-                )),
+                range: Some(word_range),
             }));
         }
     }
@@ -69,6 +71,51 @@ pub fn on_hover(
     Ok(None)
 }
 
+/// Hover for a name bound by the resolver, showing the declaration it
+/// refers to rather than whatever file-level name happens to match.
+fn get_symbol_hover(document: &Document, offset: usize) -> Option<Hover> {
+    let resolution = &document.resolution;
+    let symbol = &resolution.symbols[resolution.symbol_at(offset)?];
+
+    let span = resolution
+        .references
+        .iter()
+        .map(|reference| reference.span)
+        .chain(std::iter::once(symbol.name_span))
+        .find(|span| span.start <= offset && offset <= span.end)?;
+
+    let kind = match symbol.kind {
+        DeclarationKind::Variable if symbol.is_mutable => "variable",
+        DeclarationKind::Variable => "constant",
+        DeclarationKind::Parameter => "parameter",
+        DeclarationKind::Function => "function",
+        DeclarationKind::Struct => "struct",
+        DeclarationKind::Class => "class",
+        DeclarationKind::Property if symbol.is_mutable => "property",
+        DeclarationKind::Property => "constant property",
+        DeclarationKind::Method => "method",
+        DeclarationKind::Import => "import",
+        DeclarationKind::LoopVariable => "loop variable",
+        DeclarationKind::This => "instance",
+    };
+    let value = match symbol.kind {
+        DeclarationKind::Struct | DeclarationKind::Class | DeclarationKind::Import => {
+            format!("({}) **{}**", kind, symbol.name)
+        }
+        _ => format!("({}) **{}**: {}", kind, symbol.name, symbol.ty),
+    };
+
+    Some(Hover {
+        contents: tower_lsp::lsp_types::HoverContents::Markup(
+            tower_lsp::lsp_types::MarkupContent {
+                kind: tower_lsp::lsp_types::MarkupKind::Markdown,
+                value,
+            },
+        ),
+        range: Some(document.line_index.range(span)),
+    })
+}
+
 fn check_for_dot_access(text: &str, offset: usize) -> Option<(String, String)> {
     let text_before = &text[..offset];
     let text_after = &text[offset..];
@@ -87,7 +134,7 @@ fn check_for_dot_access(text: &str, offset: usize) -> Option<(String, String)> {
             let property_end = after_dot
                 + text_after
                     .find(|c: char| !(c.is_alphanumeric() || c == '_'))
-                    .unwrap_or_else(|| text_after.len());
+                    .unwrap_or(text_after.len());
 
             let property_name = text[after_dot..property_end].trim().to_string();
 
@@ -114,7 +161,7 @@ fn get_word_range_at_position(text: &str, offset: usize) -> Option<(usize, usize
     let text_after = &text[offset..];
     let end_offset = text_after
         .find(|c: char| !(c.is_alphanumeric() || c == '_'))
-        .unwrap_or_else(|| text_after.len());
+        .unwrap_or(text_after.len());
     let end = offset + end_offset;
 
     if end > start {
@@ -125,12 +172,17 @@ fn get_word_range_at_position(text: &str, offset: usize) -> Option<(usize, usize
 }
 
 fn get_property_hover(
+    document: &Document,
+    offset: usize,
     object_name: String,
     property_name: String,
     type_checker: &Arc<BurnTypeChecker>,
 ) -> Result<Option<Hover>> {
-    if let Some(object_type) = type_checker.get_variable_type(&object_name) {
-        if let Some(property_info) = type_checker.get_property_type(&object_type, &property_name) {
+    let resolution = &document.resolution;
+    if let Some(object_type) = type_checker.get_type_at(resolution, &object_name, offset) {
+        if let Some(property_info) =
+            type_checker.get_property_type(resolution, object_type, &property_name)
+        {
             return Ok(Some(Hover {
                 contents: tower_lsp::lsp_types::HoverContents::Markup(
                     tower_lsp::lsp_types::MarkupContent {
@@ -188,6 +240,52 @@ fn get_builtin_info(function_name: &str) -> Option<String> {
         "parseFloat" => Some(
             "```burn\nfn parseFloat(str: string) -> number\n```\n\nParses a string into a floating-point number.".to_string()
         ),
+        "Date" => Some(
+            "```burn\nfn Date() -> Date\n```\n\nReturns the current date and time.".to_string()
+        ),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::BurnAnalyzer;
+    use tower_lsp::lsp_types::{HoverContents, Range};
+
+    const URI: &str = "file:///hover.bn";
+
+    fn hover(source: &str, line: u32, character: u32) -> Option<(String, Range)> {
+        let type_checker = Arc::new(BurnTypeChecker::new());
+        let analyzer = BurnAnalyzer::new(type_checker.clone());
+        analyzer.open_document(URI, source.to_string());
+        let document = analyzer.get_document(URI).unwrap();
+
+        let hover = on_hover(&document, Position::new(line, character), &type_checker).unwrap()?;
+        let HoverContents::Markup(contents) = hover.contents else {
+            panic!("expected markup");
+        };
+        Some((contents.value, hover.range.unwrap()))
+    }
+
+    #[test]
+    fn hover_ranges_count_utf16_columns() {
+        let source = "let s = \"héllo😀\"; let n = len(s)\nprint(n)";
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+
+        assert_eq!(
+            hover(source, 0, 23),
+            Some(("(variable) **n**: int".to_string(), range(0, 23, 24)))
+        );
+        assert_eq!(
+            hover(source, 1, 6),
+            Some(("(variable) **n**: int".to_string(), range(1, 6, 7)))
+        );
+        assert_eq!(
+            hover(source, 1, 2).map(|(_, range)| range),
+            Some(range(1, 0, 5))
+        );
+        assert_eq!(hover(source, 5, 0), None);
+    }
+}
@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+
+use crate::ast::{Ast, Expression, LiteralValue, Node, Span, StructField, Type};
+use crate::resolver::{DeclarationKind, Resolution, SymbolId};
+use crate::typechecker;
+use crate::types::{Nominal, Ty, TyKind};
+
+/// Gives every symbol a semantic type: the lowered annotation where there is
+/// one, otherwise what inference finds. Variables and class properties take
+/// the type of their initializer, loop variables the element type of what
+/// they iterate, and functions without a return type the type of what they
+/// return.
+pub fn infer_types(ast: &Ast, resolution: &mut Resolution) {
+    let mut context = TypeContext::new(resolution);
+    // A function may call one declared after it or nested elsewhere, so the
+    // file is visited again until no inferred type changes.
+    for _ in 0..MAX_PASSES {
+        let before = context.symbol_types.clone();
+        context.visit_statements(&ast.nodes);
+        if context.symbol_types == before {
+            break;
+        }
+    }
+
+    let types = context.symbol_types;
+    for (symbol, ty) in resolution.symbols.iter_mut().zip(types) {
+        symbol.ty = ty;
+    }
+}
+
+/// How often a file is visited at most, for types that never settle.
+const MAX_PASSES: usize = 16;
+
+/// Computes expression types bottom-up from literals, operators and the
+/// types of the symbols the resolver bound each name to.
+pub struct TypeContext<'a> {
+    resolution: &'a Resolution,
+    symbol_types: Vec<Ty>,
+    references: HashMap<Span, SymbolId>,
+    declarations: HashMap<Span, SymbolId>,
+    /// Structs and classes by name, for lowering annotations.
+    type_names: HashMap<&'a str, Ty>,
+    type_symbols: HashMap<Ty, SymbolId>,
+    /// Types of variables narrowed by the checks guarding the code being
+    /// looked at, e.g. `x` is not null inside `if x != null { ... }`.
+    narrowed: HashMap<SymbolId, Ty>,
+}
+
+impl<'a> TypeContext<'a> {
+    pub fn new(resolution: &'a Resolution) -> Self {
+        let mut type_names = HashMap::new();
+        let mut type_symbols = HashMap::new();
+        for (id, symbol) in resolution.symbols.iter().enumerate() {
+            let nominal = || Nominal {
+                name: symbol.name.clone(),
+                uri: resolution.uri.clone(),
+                span: symbol.name_span,
+            };
+            let ty = match symbol.kind {
+                DeclarationKind::Struct => Ty::intern(TyKind::Struct(nominal())),
+                DeclarationKind::Class => Ty::intern(TyKind::Class(nominal())),
+                _ => continue,
+            };
+            type_names.entry(symbol.name.as_str()).or_insert(ty);
+            type_symbols.entry(ty).or_insert(id);
+        }
+
+        let mut context = TypeContext {
+            resolution,
+            symbol_types: Vec::new(),
+            references: resolution
+                .references
+                .iter()
+                .filter_map(|reference| Some((reference.span, reference.symbol?)))
+                .collect(),
+            declarations: resolution
+                .symbols
+                .iter()
+                .enumerate()
+                .filter(|(_, symbol)| symbol.kind != DeclarationKind::This)
+                .map(|(id, symbol)| (symbol.name_span, id))
+                .collect(),
+            type_names,
+            type_symbols,
+            narrowed: HashMap::new(),
+        };
+        context.symbol_types = resolution
+            .symbols
+            .iter()
+            .map(|symbol| match symbol.kind {
+                DeclarationKind::Struct | DeclarationKind::Class => {
+                    context.type_names[symbol.name.as_str()]
+                }
+                // Symbols that were already inferred keep their type.
+                _ if symbol.ty != Ty::ANY => symbol.ty,
+                _ => symbol
+                    .typ
+                    .as_ref()
+                    .map_or(Ty::ANY, |typ| context.lower(typ)),
+            })
+            .collect();
+        context
+    }
+
+    /// The semantic type of an annotation written in this file.
+    pub fn lower(&self, typ: &Type) -> Ty {
+        Ty::from_annotation(typ, &|name| self.type_names.get(name).copied())
+    }
+
+    pub fn expression_type(&self, expression: &Expression) -> Ty {
+        match expression {
+            Expression::Literal { value, .. } => literal_type(value),
+            Expression::InterpolatedString { .. } => Ty::STRING,
+            Expression::Variable { name, span } => self.variable_type(name, *span),
+            Expression::BinaryOperation {
+                operator,
+                left,
+                right,
+                ..
+            } => binary_type(
+                operator,
+                self.expression_type(left),
+                self.expression_type(right),
+            ),
+            Expression::UnaryOperation {
+                operator, operand, ..
+            } => match operator.as_str() {
+                "!" => Ty::BOOL,
+                _ => self.expression_type(operand),
+            },
+            Expression::Call { callee, .. } => {
+                if let Some(ty) = self.constructed_type(callee) {
+                    return ty;
+                }
+                match self.expression_type(callee).non_null().kind() {
+                    TyKind::Function { return_type, .. } => return_type,
+                    _ => Ty::ANY,
+                }
+            }
+            Expression::PropertyAccess {
+                object, property, ..
+            } => self
+                .member_type(self.expression_type(object).non_null(), property)
+                .unwrap_or(Ty::ANY),
+            Expression::ArrayAccess { array, .. } => self.expression_type(array).element(),
+            Expression::Assignment { value, .. } => self.expression_type(value),
+            Expression::ArrayLiteral { elements, .. } => {
+                let ty = Ty::union(elements.iter().map(|element| self.expression_type(element)));
+                Ty::array(if ty == Ty::NEVER { Ty::ANY } else { ty })
+            }
+            Expression::ObjectLiteral { .. } => Ty::OBJECT,
+            Expression::Lambda {
+                params,
+                body,
+                return_type,
+                ..
+            } => Ty::function(
+                params
+                    .iter()
+                    .map(|param| param.typ.as_ref().map_or(Ty::ANY, |typ| self.lower(typ)))
+                    .collect(),
+                return_type
+                    .as_ref()
+                    .map_or_else(|| self.returned_type(body), |typ| self.lower(typ)),
+            ),
+            Expression::Error { .. } => Ty::ANY,
+        }
+    }
+
+    /// The type of a member of a struct, class, builtin or standard library
+    /// type, if it has one called `name`. A union has the members every one
+    /// of its variants has.
+    pub fn member_type(&self, object: Ty, name: &str) -> Option<Ty> {
+        match object.kind() {
+            TyKind::Union(members) => {
+                let types: Option<Vec<Ty>> = members
+                    .iter()
+                    .map(|&member| self.member_type(member, name))
+                    .collect();
+                Some(Ty::union(types?))
+            }
+            TyKind::Struct(_) | TyKind::Class(_) => {
+                let member = self
+                    .members(object)
+                    .into_iter()
+                    .find(|&member| self.resolution.symbols[member].name == name)?;
+                Some(self.symbol_types[member])
+            }
+            _ => typechecker::builtin_member(object, name),
+        }
+    }
+
+    /// The members of a struct or class type declared in this file.
+    pub fn members(&self, object: Ty) -> Vec<SymbolId> {
+        self.type_symbols
+            .get(&object)
+            .map_or_else(Vec::new, |&symbol| self.resolution.members(symbol))
+    }
+
+    /// The symbol a variable expression refers to.
+    pub fn symbol_of(&self, expression: &Expression) -> Option<SymbolId> {
+        match expression {
+            Expression::Variable { span, .. } => self.references.get(span).copied(),
+            _ => None,
+        }
+    }
+
+    /// The type `symbol` was declared with, ignoring any narrowing.
+    pub fn symbol_type(&self, symbol: SymbolId) -> Ty {
+        self.symbol_types[symbol]
+    }
+
+    pub fn narrow(&mut self, symbol: SymbolId, ty: Ty) {
+        self.narrowed.insert(symbol, ty);
+    }
+
+    pub fn forget_narrowing(&mut self, symbol: SymbolId) {
+        self.narrowed.remove(&symbol);
+    }
+
+    pub fn narrowing(&self) -> HashMap<SymbolId, Ty> {
+        self.narrowed.clone()
+    }
+
+    pub fn restore_narrowing(&mut self, narrowed: HashMap<SymbolId, Ty>) {
+        self.narrowed = narrowed;
+    }
+
+    fn variable_type(&self, name: &str, span: Span) -> Ty {
+        match self.references.get(&span) {
+            Some(&symbol) => self
+                .narrowed
+                .get(&symbol)
+                .copied()
+                .unwrap_or(self.symbol_types[symbol]),
+            None => typechecker::builtin_type(name).unwrap_or(Ty::ANY),
+        }
+    }
+
+    /// The struct or class instantiated when `callee` names one.
+    pub fn constructed_type(&self, callee: &Expression) -> Option<Ty> {
+        let Expression::Variable { span, .. } = callee else {
+            return None;
+        };
+        let symbol = *self.references.get(span)?;
+        matches!(
+            self.resolution.symbols[symbol].kind,
+            DeclarationKind::Struct | DeclarationKind::Class
+        )
+        .then_some(self.symbol_types[symbol])
+    }
+
+    /// The joined type of every `return` in a function body, not counting
+    /// nested functions; `void` when nothing is returned.
+    pub fn returned_type(&self, body: &[Node]) -> Ty {
+        let mut types = Vec::new();
+        self.collect_return_types(body, &mut types);
+        if types.is_empty() {
+            return Ty::VOID;
+        }
+        Ty::union(types)
+    }
+
+    fn collect_return_types(&self, nodes: &[Node], types: &mut Vec<Ty>) {
+        for node in nodes {
+            match node {
+                Node::ReturnStatement { expression, .. } => types.push(
+                    expression
+                        .as_ref()
+                        .map_or(Ty::VOID, |e| self.expression_type(e)),
+                ),
+                Node::IfStatement {
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    self.collect_return_types(then_branch, types);
+                    if let Some(else_branch) = else_branch {
+                        self.collect_return_types(else_branch, types);
+                    }
+                }
+                Node::WhileStatement { body, .. }
+                | Node::ForStatement { body, .. }
+                | Node::ForInStatement { body, .. }
+                | Node::Block {
+                    statements: body, ..
+                } => self.collect_return_types(body, types),
+                _ => {}
+            }
+        }
+    }
+
+    /// The type a declaration without annotation takes from its initializer.
+    /// Starting out as `null` says nothing about what is stored later.
+    fn initializer_type(&self, initializer: &Expression) -> Ty {
+        match self.expression_type(initializer) {
+            Ty::NULL => Ty::ANY,
+            ty => ty,
+        }
+    }
+
+    fn set_declared_type(&mut self, name_span: Span, ty: Ty) {
+        if let Some(&symbol) = self.declarations.get(&name_span) {
+            self.symbol_types[symbol] = ty;
+        }
+    }
+
+    /// Visits function declarations before the other statements, so calls
+    /// anywhere in the list see their inferred return types.
+    fn visit_statements(&mut self, nodes: &[Node]) {
+        let (functions, rest): (Vec<&Node>, Vec<&Node>) = nodes
+            .iter()
+            .partition(|node| matches!(node, Node::FunctionDeclaration { .. }));
+
+        for node in functions.into_iter().chain(rest) {
+            self.visit_node(node);
+        }
+    }
+
+    fn visit_node(&mut self, node: &Node) {
+        match node {
+            Node::VariableDeclaration {
+                name_span,
+                initializer,
+                data_type,
+                ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.visit_expression(initializer);
+                    if data_type.is_none() {
+                        let ty = self.initializer_type(initializer);
+                        self.set_declared_type(*name_span, ty);
+                    }
+                }
+            }
+            Node::FunctionDeclaration {
+                name_span,
+                body,
+                return_type,
+                ..
+            } => {
+                self.visit_statements(body);
+                if return_type.is_none() {
+                    let returned = self.returned_type(body);
+                    if let Some(&symbol) = self.declarations.get(name_span) {
+                        if let TyKind::Function { params, .. } = self.symbol_types[symbol].kind() {
+                            self.symbol_types[symbol] = Ty::function(params, returned);
+                        }
+                    }
+                }
+            }
+            Node::StructDeclaration { fields, .. } => self.visit_fields(fields),
+            Node::ClassDeclaration {
+                methods,
+                properties,
+                ..
+            } => {
+                self.visit_fields(properties);
+                self.visit_statements(methods);
+            }
+            Node::ExpressionStatement { expression, .. } => self.visit_expression(expression),
+            Node::ReturnStatement { expression, .. } => {
+                if let Some(expression) = expression {
+                    self.visit_expression(expression);
+                }
+            }
+            Node::IfStatement {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.visit_expression(condition);
+                self.visit_statements(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.visit_statements(else_branch);
+                }
+            }
+            Node::WhileStatement {
+                condition, body, ..
+            } => {
+                self.visit_expression(condition);
+                self.visit_statements(body);
+            }
+            Node::ForStatement {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.visit_node(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.visit_expression(condition);
+                }
+                if let Some(increment) = increment {
+                    self.visit_expression(increment);
+                }
+                self.visit_statements(body);
+            }
+            Node::ForInStatement {
+                variable_span,
+                iterable,
+                body,
+                ..
+            } => {
+                self.visit_expression(iterable);
+                let ty = self.expression_type(iterable).element();
+                self.set_declared_type(*variable_span, ty);
+                self.visit_statements(body);
+            }
+            Node::Block { statements, .. } => self.visit_statements(statements),
+            Node::ImportDeclaration { .. }
+            | Node::BreakStatement { .. }
+            | Node::ContinueStatement { .. }
+            | Node::Error { .. } => {}
+        }
+    }
+
+    fn visit_fields(&mut self, fields: &[StructField]) {
+        for field in fields {
+            if let Some(initializer) = &field.initializer {
+                self.visit_expression(initializer);
+                if field.typ.is_none() {
+                    let ty = self.initializer_type(initializer);
+                    self.set_declared_type(field.name_span, ty);
+                }
+            }
+        }
+    }
+
+    /// Only lambdas contain declarations, but they can appear anywhere.
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Literal { .. } | Expression::Variable { .. } | Expression::Error { .. } => {
+            }
+            Expression::BinaryOperation { left, right, .. } => {
+                self.visit_expression(left);
+                self.visit_expression(right);
+            }
+            Expression::UnaryOperation { operand, .. } => self.visit_expression(operand),
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                self.visit_expression(callee);
+                for argument in arguments {
+                    self.visit_expression(argument);
+                }
+            }
+            Expression::PropertyAccess { object, .. } => self.visit_expression(object),
+            Expression::ArrayAccess { array, index, .. } => {
+                self.visit_expression(array);
+                self.visit_expression(index);
+            }
+            Expression::Assignment { target, value, .. } => {
+                self.visit_expression(target);
+                self.visit_expression(value);
+            }
+            Expression::ArrayLiteral { elements, .. }
+            | Expression::InterpolatedString {
+                parts: elements, ..
+            } => {
+                for element in elements {
+                    self.visit_expression(element);
+                }
+            }
+            Expression::ObjectLiteral { properties, .. } => {
+                for property in properties {
+                    self.visit_expression(&property.value);
+                }
+            }
+            Expression::Lambda { body, .. } => self.visit_statements(body),
+        }
+    }
+}
+
+fn literal_type(value: &LiteralValue) -> Ty {
+    match value {
+        LiteralValue::String(_) => Ty::STRING,
+        LiteralValue::Number(_) => Ty::FLOAT,
+        LiteralValue::Integer(_) => Ty::INT,
+        LiteralValue::Boolean(_) => Ty::BOOL,
+        LiteralValue::Null => Ty::NULL,
+    }
+}
+
+pub fn binary_type(operator: &str, left: Ty, right: Ty) -> Ty {
+    match operator {
+        "==" | "!=" | "<" | "<=" | ">" | ">=" | "&&" | "||" => Ty::BOOL,
+        ".." => Ty::array(Ty::INT),
+        "+" if left == Ty::STRING || right == Ty::STRING => Ty::STRING,
+        "+" | "-" | "*" | "/" | "%" if left.is_numeric() && right.is_numeric() => {
+            if left == Ty::INT && right == Ty::INT {
+                Ty::INT
+            } else {
+                Ty::FLOAT
+            }
+        }
+        _ => Ty::ANY,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, resolver};
+
+    /// The inferred type of the first symbol called `name` in `source`.
+    fn type_of(source: &str, name: &str) -> String {
+        let ast = parser::parse(source).ast;
+        let mut resolution = resolver::resolve(&ast, "file:///inference.bn");
+        infer_types(&ast, &mut resolution);
+        let symbol = resolution
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .unwrap_or_else(|| panic!("no symbol '{}'", name));
+        symbol.ty.to_string()
+    }
+
+    #[test]
+    fn declarations_take_the_type_of_their_initializer() {
+        let cases = [
+            ("let a = 1 + 2", "int"),
+            ("let a = 1 + 2.5", "float"),
+            ("let a = \"x\" + 1", "string"),
+            ("let a = -1.5", "float"),
+            ("let a = !0", "bool"),
+            ("let a = 1 < 2", "bool"),
+            ("let a = [1, 2]", "int[]"),
+            ("let a = [1, \"x\"]", "(int | string)[]"),
+            ("let a = [[1]][0]", "int[]"),
+            ("let a = null", "any"),
+            ("let a: int? = null", "int?"),
+            ("let a = \"${1}\"", "string"),
+            ("let a = fn(x: int) -> int { return x }", "fn(int) -> int"),
+            ("struct P { x: int }\nlet a = P()", "P"),
+            ("struct P { x: int }\nlet a = P().x", "int"),
+            ("let a = \"abc\".length", "int"),
+            ("for a in \"ab\" { }", "string"),
+            ("for a in 0..3 { }", "int"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(type_of(source, "a"), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn functions_return_what_they_return() {
+        assert_eq!(type_of("fn f() { return 1 }", "f"), "fn() -> int");
+        assert_eq!(type_of("fn f() { }", "f"), "fn() -> void");
+        assert_eq!(
+            type_of("fn f(x: int) { if x > 0 { return 1 } return null }", "f"),
+            "fn(int) -> (int | null)"
+        );
+        assert_eq!(type_of("fn f(): float { return 1 }", "f"), "fn() -> float");
+    }
+
+    #[test]
+    fn calls_see_functions_declared_later() {
+        // Each function only learns its return type once the next one has.
+        let source = "let a = f1()\n\
+                      fn f1() { return f2() }\n\
+                      fn f2() { return f3() }\n\
+                      fn f3() { return f4() }\n\
+                      fn f4() { return f5() }\n\
+                      fn f5() { return f6() }\n\
+                      fn f6() { return \"done\" }";
+        assert_eq!(type_of(source, "f1"), "fn() -> string");
+        assert_eq!(type_of(source, "a"), "string");
+    }
+
+    #[test]
+    fn recursive_calls_add_nothing_to_the_return_type() {
+        let source = "fn f(n: int) { if n > 0 { return 1 }\n return f(n - 1) }\nlet x = f(3)";
+        assert_eq!(type_of(source, "f"), "fn(int) -> int");
+        assert_eq!(type_of(source, "x"), "int");
+
+        let source = "fn even(n: int) { if n == 0 { return \"yes\" }\n return odd(n - 1) }\n\
+                      fn odd(n: int) { if n == 0 { return \"no\" }\n return even(n - 1) }";
+        assert_eq!(type_of(source, "even"), "fn(int) -> string");
+        assert_eq!(type_of(source, "odd"), "fn(int) -> string");
+
+        let source = "fn f(n: int) { if n > 0 { f(n - 1) } }";
+        assert_eq!(type_of(source, "f"), "fn(int) -> void");
+    }
+
+    #[test]
+    fn deeply_nested_functions_are_inferred() {
+        let depth = 40;
+        let mut source = String::from("let a = f0()\n");
+        for level in 0..depth {
+            source += &format!("fn f{}() {{\n", level);
+        }
+        source += "return 1\n";
+        for level in (0..depth).rev() {
+            source += "}\n";
+            if level > 0 {
+                source += &format!("return f{}()\n", level);
+            }
+        }
+        assert_eq!(type_of(&source, "a"), "int");
+    }
+}
@@ -0,0 +1,613 @@
+use crate::ast::{LiteralValue, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier,
+    Integer,
+    Float,
+    String,
+
+    Fn,
+    Let,
+    Var,
+    Const,
+    Struct,
+    Class,
+    Import,
+    From,
+    If,
+    Else,
+    While,
+    For,
+    In,
+    Return,
+    Break,
+    Continue,
+    True,
+    False,
+    Null,
+
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Colon,
+    Semicolon,
+    Dot,
+    DotDot,
+    Arrow,
+    Question,
+
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Bang,
+    Equal,
+    EqualEqual,
+    BangEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    AmpAmp,
+    PipePipe,
+    Pipe,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+
+    Whitespace,
+    Comment,
+    Unknown,
+    Eof,
+}
+
+impl TokenKind {
+    /// Whitespace and comments: kept in the syntax tree, skipped by the parser.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Comment)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.span.start..self.span.end]
+    }
+}
+
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut lexer = Lexer {
+        source,
+        bytes: source.as_bytes(),
+        pos: 0,
+    };
+    let mut tokens = Vec::new();
+
+    loop {
+        let token = lexer.next_token();
+        let is_eof = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if is_eof {
+            break;
+        }
+    }
+
+    tokens
+}
+
+/// The value of an `Integer` or `Float` token, negated when it follows a
+/// unary minus, or a description of why the literal is malformed. Integers
+/// must fit in `i64` once negated, so `-9223372036854775808` is accepted, and
+/// floats must be finite.
+pub fn number_value(text: &str, negated: bool) -> Result<LiteralValue, String> {
+    let (radix, digits, base_name) = match text.get(..2) {
+        Some("0x" | "0X") => (16, &text[2..], "hexadecimal"),
+        Some("0o" | "0O") => (8, &text[2..], "octal"),
+        Some("0b" | "0B") => (2, &text[2..], "binary"),
+        _ => (10, text, "decimal"),
+    };
+
+    if digits.is_empty() {
+        return Err(format!(
+            "Missing digits in {} literal '{}'",
+            base_name, text
+        ));
+    }
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(format!("Misplaced digit separator in '{}'", text));
+    }
+
+    if radix == 10 && text.contains(['.', 'e', 'E']) {
+        let cleaned = text.replace('_', "");
+        return match cleaned.parse::<f64>() {
+            Ok(value) if value.is_finite() && negated => Ok(LiteralValue::Number(-value)),
+            Ok(value) if value.is_finite() => Ok(LiteralValue::Number(value)),
+            Ok(_) => Err(format!("Number literal '{}' is out of range", text)),
+            Err(_) => Err(format!("Invalid number literal '{}'", text)),
+        };
+    }
+
+    if let Some(invalid) = digits.chars().find(|&c| c != '_' && !c.is_digit(radix)) {
+        return Err(format!(
+            "Invalid digit '{}' in {} literal '{}'",
+            invalid, base_name, text
+        ));
+    }
+
+    let cleaned = digits.replace('_', "");
+    let magnitude = u64::from_str_radix(&cleaned, radix).ok();
+    let value = if negated {
+        magnitude.and_then(|magnitude| 0i64.checked_sub_unsigned(magnitude))
+    } else {
+        magnitude.and_then(|magnitude| i64::try_from(magnitude).ok())
+    };
+    value.map(LiteralValue::Integer).ok_or_else(|| {
+        let sign = if negated { "-" } else { "" };
+        format!("Integer literal '{}{}' does not fit in 64 bits", sign, text)
+    })
+}
+
+/// Offset of the `}` closing the interpolation whose expression starts at
+/// `start` inside a string token's text.
+pub fn interpolation_end(text: &str, start: usize) -> Option<usize> {
+    let mut lexer = Lexer {
+        source: text,
+        bytes: text.as_bytes(),
+        pos: start,
+    };
+    lexer.interpolation().then(|| lexer.pos - 1)
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, distance: usize) -> Option<u8> {
+        self.bytes.get(self.pos + distance).copied()
+    }
+
+    fn whitespace(&mut self) -> TokenKind {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        TokenKind::Whitespace
+    }
+
+    fn line_comment(&mut self) -> TokenKind {
+        while self.peek().is_some_and(|c| c != b'\n') {
+            self.pos += 1;
+        }
+        TokenKind::Comment
+    }
+
+    /// `/* ... */`, which may span lines. An unterminated comment runs to the
+    /// end of the file; the parser reports it.
+    fn block_comment(&mut self) -> TokenKind {
+        self.pos += 2;
+        while let Some(c) = self.peek() {
+            if c == b'*' && self.peek_at(1) == Some(b'/') {
+                self.pos += 2;
+                break;
+            }
+            self.pos += 1;
+        }
+        TokenKind::Comment
+    }
+
+    fn next_token(&mut self) -> Token {
+        let start = self.pos;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => {
+                return Token {
+                    kind: TokenKind::Eof,
+                    span: Span::new(start, start),
+                }
+            }
+        };
+
+        let kind = if c.is_ascii_whitespace() {
+            self.whitespace()
+        } else if c == b'/' && self.peek_at(1) == Some(b'/') {
+            self.line_comment()
+        } else if c == b'/' && self.peek_at(1) == Some(b'*') {
+            self.block_comment()
+        } else if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 {
+            self.identifier_or_keyword()
+        } else if c.is_ascii_digit() {
+            self.number()
+        } else if c == b'"' || c == b'\'' {
+            self.string(c)
+        } else {
+            self.punctuation(c)
+        };
+
+        Token {
+            kind,
+            span: Span::new(start, self.pos),
+        }
+    }
+
+    fn identifier_or_keyword(&mut self) -> TokenKind {
+        let start = self.pos;
+        let rest = &self.source[start..];
+        let len = rest
+            .char_indices()
+            .find(|&(_, c)| !(c.is_alphanumeric() || c == '_'))
+            .map(|(i, _)| i)
+            .unwrap_or(rest.len());
+        // A lone non-ASCII symbol that is not alphanumeric still has to make progress.
+        let len = if len == 0 {
+            rest.chars().next().map_or(1, |c| c.len_utf8())
+        } else {
+            len
+        };
+        self.pos += len;
+
+        match &self.source[start..self.pos] {
+            "fn" => TokenKind::Fn,
+            "let" => TokenKind::Let,
+            "var" => TokenKind::Var,
+            "const" => TokenKind::Const,
+            "struct" => TokenKind::Struct,
+            "class" => TokenKind::Class,
+            "import" => TokenKind::Import,
+            "from" => TokenKind::From,
+            "if" => TokenKind::If,
+            "else" => TokenKind::Else,
+            "while" => TokenKind::While,
+            "for" => TokenKind::For,
+            "in" => TokenKind::In,
+            "return" => TokenKind::Return,
+            "break" => TokenKind::Break,
+            "continue" => TokenKind::Continue,
+            "true" => TokenKind::True,
+            "false" => TokenKind::False,
+            "null" => TokenKind::Null,
+            text if text.starts_with(|c: char| c.is_alphabetic() || c == '_') => {
+                TokenKind::Identifier
+            }
+            _ => TokenKind::Unknown,
+        }
+    }
+
+    /// Lexes the shape of a number only: prefixed literals take every
+    /// alphanumeric that follows, and letters glued to a decimal literal are
+    /// kept in the token, so `number_value` can report what is wrong with it.
+    fn number(&mut self) -> TokenKind {
+        if self.peek() == Some(b'0')
+            && matches!(
+                self.peek_at(1),
+                Some(b'x' | b'X' | b'o' | b'O' | b'b' | b'B')
+            )
+        {
+            self.pos += 2;
+            self.word();
+            return TokenKind::Integer;
+        }
+
+        let mut kind = TokenKind::Integer;
+        self.digits();
+
+        // `1..2` is a range, not the float `1.` followed by `.2`.
+        if self.peek() == Some(b'.') && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+            self.digits();
+            kind = TokenKind::Float;
+        }
+
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            let sign = usize::from(matches!(self.peek_at(1), Some(b'+' | b'-')));
+            if self.peek_at(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1 + sign;
+                self.digits();
+                kind = TokenKind::Float;
+            }
+        }
+
+        self.word();
+        kind
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == b'_') {
+            self.pos += 1;
+        }
+    }
+
+    fn word(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// A string literal, including any `${...}` interpolations. Strings end at
+    /// the closing quote or, when unterminated, at the end of the line; the
+    /// parser decodes escapes and reports the missing quote.
+    fn string(&mut self, quote: u8) -> TokenKind {
+        self.pos += 1;
+
+        while let Some(c) = self.peek() {
+            match c {
+                _ if c == quote => {
+                    self.pos += 1;
+                    break;
+                }
+                b'\n' => break,
+                b'\\' if self.peek_at(1) != Some(b'\n') => self.pos += 2,
+                b'$' if self.peek_at(1) == Some(b'{') => {
+                    self.pos += 2;
+                    self.interpolation();
+                }
+                _ => self.pos += 1,
+            }
+        }
+
+        self.pos = self.pos.min(self.bytes.len());
+        TokenKind::String
+    }
+
+    /// Skips the expression of an interpolation up to and including its
+    /// closing brace, stepping over nested braces and strings. Returns false
+    /// if the line or file ends first.
+    fn interpolation(&mut self) -> bool {
+        let mut depth = 0usize;
+        while let Some(c) = self.peek() {
+            match c {
+                b'\n' => return false,
+                b'{' => depth += 1,
+                b'}' if depth == 0 => {
+                    self.pos += 1;
+                    return true;
+                }
+                b'}' => depth -= 1,
+                b'"' | b'\'' => {
+                    self.string(c);
+                    continue;
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        false
+    }
+
+    fn punctuation(&mut self, c: u8) -> TokenKind {
+        let next = self.peek_at(1);
+        let (kind, len) = match (c, next) {
+            (b'.', Some(b'.')) => (TokenKind::DotDot, 2),
+            (b'-', Some(b'>')) => (TokenKind::Arrow, 2),
+            (b'=', Some(b'=')) => (TokenKind::EqualEqual, 2),
+            (b'!', Some(b'=')) => (TokenKind::BangEqual, 2),
+            (b'<', Some(b'=')) => (TokenKind::LessEqual, 2),
+            (b'>', Some(b'=')) => (TokenKind::GreaterEqual, 2),
+            (b'&', Some(b'&')) => (TokenKind::AmpAmp, 2),
+            (b'|', Some(b'|')) => (TokenKind::PipePipe, 2),
+            (b'+', Some(b'=')) => (TokenKind::PlusEqual, 2),
+            (b'-', Some(b'=')) => (TokenKind::MinusEqual, 2),
+            (b'*', Some(b'=')) => (TokenKind::StarEqual, 2),
+            (b'/', Some(b'=')) => (TokenKind::SlashEqual, 2),
+            (b'%', Some(b'=')) => (TokenKind::PercentEqual, 2),
+            (b'(', _) => (TokenKind::LeftParen, 1),
+            (b')', _) => (TokenKind::RightParen, 1),
+            (b'{', _) => (TokenKind::LeftBrace, 1),
+            (b'}', _) => (TokenKind::RightBrace, 1),
+            (b'[', _) => (TokenKind::LeftBracket, 1),
+            (b']', _) => (TokenKind::RightBracket, 1),
+            (b',', _) => (TokenKind::Comma, 1),
+            (b':', _) => (TokenKind::Colon, 1),
+            (b';', _) => (TokenKind::Semicolon, 1),
+            (b'.', _) => (TokenKind::Dot, 1),
+            (b'?', _) => (TokenKind::Question, 1),
+            (b'+', _) => (TokenKind::Plus, 1),
+            (b'-', _) => (TokenKind::Minus, 1),
+            (b'*', _) => (TokenKind::Star, 1),
+            (b'/', _) => (TokenKind::Slash, 1),
+            (b'%', _) => (TokenKind::Percent, 1),
+            (b'!', _) => (TokenKind::Bang, 1),
+            (b'=', _) => (TokenKind::Equal, 1),
+            (b'<', _) => (TokenKind::Less, 1),
+            (b'>', _) => (TokenKind::Greater, 1),
+            (b'|', _) => (TokenKind::Pipe, 1),
+            _ => (TokenKind::Unknown, 1),
+        };
+
+        self.pos += len;
+        kind
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source)
+            .into_iter()
+            .map(|token| token.kind)
+            .filter(|kind| !kind.is_trivia())
+            .collect()
+    }
+
+    #[test]
+    fn keywords_identifiers_and_punctuation() {
+        assert_eq!(
+            kinds("fn add(a: int) -> int { return a }"),
+            vec![
+                TokenKind::Fn,
+                TokenKind::Identifier,
+                TokenKind::LeftParen,
+                TokenKind::Identifier,
+                TokenKind::Colon,
+                TokenKind::Identifier,
+                TokenKind::RightParen,
+                TokenKind::Arrow,
+                TokenKind::Identifier,
+                TokenKind::LeftBrace,
+                TokenKind::Return,
+                TokenKind::Identifier,
+                TokenKind::RightBrace,
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(
+            kinds("a += b .. c != d"),
+            vec![
+                TokenKind::Identifier,
+                TokenKind::PlusEqual,
+                TokenKind::Identifier,
+                TokenKind::DotDot,
+                TokenKind::Identifier,
+                TokenKind::BangEqual,
+                TokenKind::Identifier,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_cover_the_source() {
+        let source = "let größe = 1 // comment\n  print(größe) § ";
+        let tokens = tokenize(source);
+        let mut end = 0;
+        for token in &tokens {
+            assert_eq!(token.span.start, end);
+            end = token.span.end;
+        }
+        assert_eq!(end, source.len());
+
+        let identifiers: Vec<&str> = tokens
+            .iter()
+            .filter(|token| token.kind == TokenKind::Identifier)
+            .map(|token| token.text(source))
+            .collect();
+        assert_eq!(identifiers, vec!["größe", "print", "größe"]);
+        assert!(tokens.iter().any(|token| token.kind == TokenKind::Unknown));
+    }
+
+    #[test]
+    fn strings_and_comments() {
+        let source = "\"a // b\" /* x\n \"y\" */ 'c\\'' \"${ f(\"}\") } d\" // e";
+        let tokens: Vec<(TokenKind, &str)> = tokenize(source)
+            .into_iter()
+            .filter(|token| token.kind != TokenKind::Whitespace)
+            .map(|token| (token.kind, token.text(source)))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::String, "\"a // b\""),
+                (TokenKind::Comment, "/* x\n \"y\" */"),
+                (TokenKind::String, "'c\\''"),
+                (TokenKind::String, "\"${ f(\"}\") } d\""),
+                (TokenKind::Comment, "// e"),
+                (TokenKind::Eof, ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_strings_stop_at_the_line_end() {
+        assert_eq!(
+            kinds("\"abc\nlet \"${x\n"),
+            vec![
+                TokenKind::String,
+                TokenKind::Let,
+                TokenKind::String,
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(kinds("/* open\n let"), vec![TokenKind::Eof]);
+    }
+
+    #[test]
+    fn number_literals() {
+        let cases = [
+            ("0x1F", LiteralValue::Integer(31)),
+            ("0o17", LiteralValue::Integer(15)),
+            ("0b1010_0101", LiteralValue::Integer(165)),
+            ("1_000_000", LiteralValue::Integer(1_000_000)),
+            ("9223372036854775807", LiteralValue::Integer(i64::MAX)),
+            ("1.5e3", LiteralValue::Number(1500.0)),
+            ("2E-2", LiteralValue::Number(0.02)),
+            ("1_0.2_5", LiteralValue::Number(10.25)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(kinds(text).len(), 2, "{}", text);
+            assert_eq!(
+                format!("{:?}", number_value(text, false)),
+                format!("{:?}", Ok::<_, String>(expected)),
+                "{}",
+                text
+            );
+        }
+
+        let negated = |text| format!("{:?}", number_value(text, true));
+        assert_eq!(
+            negated("9223372036854775808"),
+            "Ok(Integer(-9223372036854775808))"
+        );
+        assert_eq!(
+            negated("0x8000_0000_0000_0000"),
+            "Ok(Integer(-9223372036854775808))"
+        );
+        assert_eq!(negated("2.5"), "Ok(Number(-2.5))");
+    }
+
+    #[test]
+    fn malformed_number_literals() {
+        let cases = [
+            ("0x", false, "Missing digits in hexadecimal literal '0x'"),
+            ("1__0", false, "Misplaced digit separator in '1__0'"),
+            ("0b_1", false, "Misplaced digit separator in '0b_1'"),
+            (
+                "0b102",
+                false,
+                "Invalid digit '2' in binary literal '0b102'",
+            ),
+            ("0o8", false, "Invalid digit '8' in octal literal '0o8'"),
+            (
+                "9223372036854775808",
+                false,
+                "Integer literal '9223372036854775808' does not fit in 64 bits",
+            ),
+            (
+                "9223372036854775809",
+                true,
+                "Integer literal '-9223372036854775809' does not fit in 64 bits",
+            ),
+            ("1e999", false, "Number literal '1e999' is out of range"),
+        ];
+        for (text, negated, expected) in cases {
+            assert_eq!(
+                number_value(text, negated).err().as_deref(),
+                Some(expected),
+                "{}",
+                text
+            );
+        }
+    }
+}
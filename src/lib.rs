@@ -3,5 +3,14 @@ pub mod server;
 pub mod typechecker;
 pub mod utils;
 pub mod hover;
+pub mod code_actions;
+pub mod lints;
+pub mod cfg;
 pub mod ast;
 pub mod analyzer;
+pub mod resolver;
+pub mod inference;
+pub mod types;
+pub mod cst;
+pub mod formatter;
+pub mod lexer;
@@ -0,0 +1,348 @@
+use crate::ast::{Ast, Node, Span};
+use crate::cfg::ControlFlowGraph;
+use crate::resolver::{DeclarationKind, Resolution, SymbolId};
+
+/// Code that is legal but most likely a mistake or left over, reported as a
+/// warning.
+pub struct LintWarning {
+    pub message: String,
+    pub span: Span,
+    /// Whether the code does nothing, so editors can grey it out.
+    pub unnecessary: bool,
+}
+
+/// Reports locals, parameters, imported items and top-level functions that
+/// nothing refers to. Names starting with `_` are exempt, as is `main`.
+/// Burn has no export list, so a top-level function counts as private
+/// unless `is_imported_elsewhere` says another file imports its name.
+pub fn check_unused(
+    resolution: &Resolution,
+    is_imported_elsewhere: impl Fn(&str) -> bool,
+) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+
+    for (id, symbol) in resolution.symbols.iter().enumerate() {
+        if symbol.name.starts_with('_') || is_used(resolution, id) {
+            continue;
+        }
+
+        let top_level = symbol.scope == 0;
+        let what = match symbol.kind {
+            DeclarationKind::Variable | DeclarationKind::LoopVariable if !top_level => "Variable",
+            DeclarationKind::Parameter => "Parameter",
+            DeclarationKind::Import => "Imported item",
+            DeclarationKind::Function
+                if symbol.name != "main" && !(top_level && is_imported_elsewhere(&symbol.name)) =>
+            {
+                "Function"
+            }
+            _ => continue,
+        };
+        warnings.push(LintWarning {
+            message: format!("{} '{}' is never used", what, symbol.name),
+            span: symbol.name_span,
+            unnecessary: true,
+        });
+    }
+
+    warnings
+}
+
+/// Whether anything refers to `symbol`. A function calling itself does not
+/// count as using it.
+fn is_used(resolution: &Resolution, symbol: SymbolId) -> bool {
+    let declaration = &resolution.symbols[symbol];
+    resolution.references.iter().any(|reference| {
+        reference.symbol == Some(symbol)
+            && !(declaration.kind == DeclarationKind::Function
+                && declaration.span.contains(reference.span.start))
+    })
+}
+
+/// Reports code in function bodies that never runs: statements after a
+/// `return`, `break`, `continue` or endless loop, and branches behind a
+/// condition that is always `true` or `false`. Loops that never end are
+/// reported too.
+pub fn check_unreachable(ast: &Ast) -> Vec<LintWarning> {
+    let mut bodies = Vec::new();
+    function_bodies(&ast.nodes, &mut bodies);
+
+    let mut warnings = Vec::new();
+    for body in bodies {
+        let graph = ControlFlowGraph::build(body);
+        let reachable = graph.reachable();
+
+        for &span in &graph.infinite_loops {
+            warnings.push(LintWarning {
+                message: "This loop never ends: its condition is always true and nothing \
+                          breaks out of it"
+                    .to_string(),
+                span,
+                unnecessary: false,
+            });
+        }
+
+        // Once a statement is unreachable, so is the rest of its list and
+        // everything nested in it; each such run is reported once.
+        let mut runs: Vec<(usize, Span)> = Vec::new();
+        for statement in &graph.statements {
+            if reachable[statement.block] {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((_, run))
+                    if run.start <= statement.span.start && statement.span.end <= run.end => {}
+                Some((list, run)) if *list == statement.list => *run = run.to(statement.span),
+                _ => runs.push((statement.list, statement.span)),
+            }
+        }
+
+        for (list, span) in runs {
+            let dead_branch = graph
+                .dead_branches
+                .iter()
+                .find(|branch| branch.list == list);
+            let message = match dead_branch {
+                Some(branch) if branch.is_loop => {
+                    "This loop body never runs: its condition is always false".to_string()
+                }
+                Some(branch) => format!(
+                    "This branch never runs: its condition is always {}",
+                    branch.condition
+                ),
+                None => "Unreachable code".to_string(),
+            };
+            warnings.push(LintWarning {
+                message,
+                span,
+                unnecessary: true,
+            });
+        }
+    }
+
+    warnings
+}
+
+/// The bodies of every function and method in `nodes`, nested ones included.
+fn function_bodies<'a>(nodes: &'a [Node], bodies: &mut Vec<&'a [Node]>) {
+    for node in nodes {
+        match node {
+            Node::FunctionDeclaration { body, .. } => {
+                bodies.push(body);
+                function_bodies(body, bodies);
+            }
+            Node::ClassDeclaration { methods, .. } => function_bodies(methods, bodies),
+            Node::IfStatement {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                function_bodies(then_branch, bodies);
+                if let Some(else_branch) = else_branch {
+                    function_bodies(else_branch, bodies);
+                }
+            }
+            Node::WhileStatement { body, .. }
+            | Node::ForStatement { body, .. }
+            | Node::ForInStatement { body, .. }
+            | Node::Block {
+                statements: body, ..
+            } => function_bodies(body, bodies),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, resolver};
+
+    fn unused(source: &str) -> Vec<String> {
+        let result = parser::parse(source);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        let resolution = resolver::resolve(&result.ast, "file:///lints.bn");
+        check_unused(&resolution, |name| name == "shared")
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    #[test]
+    fn unused_declarations_are_reported() {
+        let source = "import { helper, other } from \"lib\"\n\
+                      let top = 1\n\
+                      fn f(a, b) {\n\
+                      let c = a\n\
+                      let _ignored = 1\n\
+                      }\n\
+                      fn countdown(n) { if n > 0 { countdown(n - 1) } }\n\
+                      fn main() { print(helper) }\n\
+                      fn shared() {}";
+        assert_eq!(
+            unused(source),
+            vec![
+                "Imported item 'other' is never used",
+                "Function 'f' is never used",
+                "Function 'countdown' is never used",
+                "Parameter 'b' is never used",
+                "Variable 'c' is never used",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_used_in_annotations_are_used() {
+        let source = "import { Point, Shape, Line } from \"geo\"\n\
+                      let p: Point = origin()\n\
+                      fn area(s: Shape[]): fn() -> Line? { return s }\n\
+                      print(area([]))";
+        assert_eq!(unused(source), Vec::<String>::new());
+
+        let source = "import { Point } from \"geo\"\n\
+                      struct Segment { start: Point, end: Point? }\n\
+                      class Path {\n\
+                      points: (Point | null)[]\n\
+                      }";
+        assert_eq!(unused(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn annotations_refer_to_the_type_they_name() {
+        let source = "struct Point { x: int }\nlet p: Point? = null\nfn f(q: Point[]) {}";
+        let result = parser::parse(source);
+        let resolution = resolver::resolve(&result.ast, "file:///lints.bn");
+        let point = resolution
+            .symbols
+            .iter()
+            .position(|symbol| symbol.name == "Point")
+            .unwrap();
+        let uses: Vec<usize> = resolution
+            .references_to(point)
+            .into_iter()
+            .map(|span| span.start)
+            .collect();
+        assert_eq!(uses, vec![31, 53]);
+        assert!(resolution
+            .references
+            .iter()
+            .all(|reference| reference.name != "int"));
+    }
+
+    /// The unreachable-code warnings for `source`, with the text each covers.
+    fn unreachable(source: &str) -> Vec<(String, &str)> {
+        let result = parser::parse(source);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        check_unreachable(&result.ast)
+            .into_iter()
+            .map(|warning| {
+                (
+                    warning.message,
+                    &source[warning.span.start..warning.span.end],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn statements_after_jumps_are_unreachable() {
+        let source = "fn f(x) {\n\
+                      return 1\n\
+                      print(1)\n\
+                      print(2)\n\
+                      }\n\
+                      fn g(xs) {\n\
+                      for x in xs {\n\
+                      if x { continue } else { break }\n\
+                      print(x)\n\
+                      }\n\
+                      print(xs)\n\
+                      }";
+        assert_eq!(
+            unreachable(source),
+            vec![
+                ("Unreachable code".to_string(), "print(1)\nprint(2)"),
+                ("Unreachable code".to_string(), "print(x)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn constant_conditions_leave_dead_branches() {
+        let source = "fn f() {\n\
+                      if true { print(1) } else { print(2) }\n\
+                      if false { print(3) }\n\
+                      while false { print(4) }\n\
+                      }";
+        assert_eq!(
+            unreachable(source),
+            vec![
+                (
+                    "This branch never runs: its condition is always true".to_string(),
+                    "print(2)"
+                ),
+                (
+                    "This branch never runs: its condition is always false".to_string(),
+                    "print(3)"
+                ),
+                (
+                    "This loop body never runs: its condition is always false".to_string(),
+                    "print(4)"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn endless_loops_are_reported_with_what_follows() {
+        let source = "fn f() {\n\
+                      while true { print(1) }\n\
+                      print(2)\n\
+                      }";
+        assert_eq!(
+            unreachable(source),
+            vec![
+                (
+                    "This loop never ends: its condition is always true and nothing \
+                     breaks out of it"
+                        .to_string(),
+                    "while true"
+                ),
+                ("Unreachable code".to_string(), "print(2)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn breaks_that_never_run_do_not_end_a_loop() {
+        let source = "fn f() {\n\
+                      while true { if false { break } }\n\
+                      print(1)\n\
+                      }";
+        let messages: Vec<String> = unreachable(source)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "This loop never ends: its condition is always true and nothing \
+                 breaks out of it",
+                "This branch never runs: its condition is always false",
+                "Unreachable code",
+            ]
+        );
+    }
+
+    #[test]
+    fn reachable_code_is_not_reported() {
+        let source = "fn f(x) {\n\
+                      while true { if x { break } }\n\
+                      if x { return 1 }\n\
+                      for i in [1] { print(i) }\n\
+                      return 2\n\
+                      }\n\
+                      class C { fn m() { if true { return } } }";
+        assert_eq!(unreachable(source), Vec::new());
+    }
+}
@@ -1,5 +1,4 @@
 use burn_analyzer::server::BurnLanguageServer;
-use std::env;
 use tower_lsp::{LspService, Server};
 
 #[tokio::main]
@@ -9,7 +8,7 @@ async fn main() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(|client| BurnLanguageServer::new(client));
+    let (service, socket) = LspService::new(BurnLanguageServer::new);
 
     log::info!("Starting Burn language server");
     Server::new(stdin, stdout, socket).serve(service).await;
@@ -1,385 +1,2242 @@
-use crate::ast::{Ast, Expression, LiteralValue, Node, Parameter, StructField, Type};
-use log::error;
+use crate::ast::{
+    Ast, Expression, ImportedItem, LiteralValue, Node, ObjectProperty, Parameter, Span,
+    StructField, Type,
+};
+use crate::cst::{CstBuilder, SyntaxElement, SyntaxKind, SyntaxNode};
+use crate::lexer::{self, Token, TokenKind};
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct ParseError {
     pub message: String,
-    pub line: usize,
-    pub column: usize,
+    pub span: Span,
+    /// Index of the top-level item whose parse reported the error, which
+    /// is not always the item its span falls in. `None` for an unterminated
+    /// block comment, which belongs to no item.
+    pub item: Option<usize>,
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Parse error at {}:{}: {}",
-            self.line, self.column, self.message
+            "Parse error at {}..{}: {}",
+            self.span.start, self.span.end, self.message
         )
     }
 }
 
-pub fn parse(source: &str) -> Result<Ast, Vec<ParseError>> {
+/// Result of parsing a document. The AST is always produced; anything that
+/// could not be parsed shows up as `Error` nodes alongside an entry in `errors`.
+/// The CST covers the same text losslessly and every AST node's span matches
+/// a CST node of the corresponding kind, see `SyntaxNode::find_node`; only
+/// zero-width placeholders for missing expressions have none.
+#[derive(Debug, Clone)]
+pub struct ParseResult {
+    pub ast: Ast,
+    pub cst: SyntaxNode,
+    pub errors: Vec<ParseError>,
+}
+
+pub fn parse(source: &str) -> ParseResult {
+    let mut parser = Parser::new(source);
     let mut nodes = Vec::new();
-    let mut errors = Vec::new();
-
-    let lines: Vec<&str> = source.lines().collect();
-
-    for (line_idx, line) in lines.iter().enumerate() {
-        let line_num = line_idx + 1;
-        let trimmed = line.trim();
-
-        if trimmed.is_empty() || trimmed.starts_with("//") {
-            continue;
-        }
-
-        if let Some(var_decl) = parse_variable_declaration(trimmed, line_num) {
-            nodes.push(var_decl);
-        } else if let Some(fn_decl) =
-            parse_function_declaration(trimmed, line_num, &lines[line_idx..])
-        {
-            nodes.push(fn_decl);
-        } else if let Some(struct_decl) =
-            parse_struct_declaration(trimmed, line_num, &lines[line_idx..])
-        {
-            nodes.push(struct_decl);
-        } else if let Some(import_decl) = parse_import_declaration(trimmed, line_num) {
-            nodes.push(import_decl);
+
+    while !parser.at(TokenKind::Eof) {
+        parser.item = Some(nodes.len());
+        nodes.push(parser.parse_statement_recovering());
+    }
+    parser.flush_trivia_to(parser.tokens.len() - 1);
+
+    ParseResult {
+        ast: Ast { nodes },
+        cst: parser.builder.finish(),
+        errors: parser.errors,
+    }
+}
+
+/// Reparses `source` after the bytes in `edit` of the previously parsed text
+/// were replaced by `inserted_len` new bytes.
+///
+/// Only the top-level items touching the edit (plus one neighbour on each
+/// side, since statements are not newline terminated) are parsed again; the
+/// CST subtrees and AST nodes of every other item are reused, with spans
+/// after the edit shifted. If the reparsed region does not parse cleanly on
+/// its own, the edit may have changed how the surrounding items nest, so the
+/// whole document is parsed from scratch instead.
+pub fn reparse(
+    previous: ParseResult,
+    source: &str,
+    edit: Span,
+    inserted_len: usize,
+) -> ParseResult {
+    let delta = inserted_len as isize - edit.len() as isize;
+    let spans = previous.cst.child_spans(0);
+    let is_node = |index: usize| matches!(previous.cst.children[index], SyntaxElement::Node(_));
+
+    let (Some(first), Some(last)) = (
+        spans.iter().position(|span| span.end >= edit.start),
+        spans.iter().rposition(|span| span.start <= edit.end),
+    ) else {
+        return parse(source);
+    };
+    let first = (0..first).rev().find(|&i| is_node(i)).unwrap_or(0);
+    let last = (last + 1..spans.len())
+        .find(|&i| is_node(i))
+        .unwrap_or(spans.len() - 1);
+
+    let region = Span::new(spans[first].start, spans[last].end);
+    let new_region = Span::new(region.start, region.end.saturating_add_signed(delta));
+    if new_region.end > source.len() || !source.is_char_boundary(new_region.end) {
+        return parse(source);
+    }
+
+    let reparsed = parse(&source[new_region.start..new_region.end]);
+    if !reparsed.errors.is_empty() {
+        return parse(source);
+    }
+
+    // Top-level items are the node children of the root, in order, so the
+    // items in the region are those from `first_item` up to `end_item`.
+    let first_item = (0..first).filter(|&i| is_node(i)).count();
+    let end_item = first_item + (first..=last).filter(|&i| is_node(i)).count();
+    let reparsed_items = reparsed.ast.nodes.len();
+    let item_delta = reparsed_items as isize - (end_item - first_item) as isize;
+
+    let mut nodes = previous.ast.nodes;
+    let mut after = nodes.split_off(end_item);
+    nodes.truncate(first_item);
+    for node in &mut after {
+        node.shift(delta);
+    }
+    nodes.extend(reparsed.ast.nodes.into_iter().map(|mut node| {
+        node.shift(new_region.start as isize);
+        node
+    }));
+    nodes.extend(after);
+
+    let mut children = previous.cst.children;
+    let tail = children.split_off(last + 1);
+    children.truncate(first);
+    children.extend(reparsed.cst.children);
+    children.extend(tail);
+
+    // Errors go with the item that reported them, wherever their span is.
+    // The reparsed items have none, and an unterminated comment runs to the
+    // end of the file, so it is after the region or was reparsed with it.
+    let errors = previous
+        .errors
+        .into_iter()
+        .filter_map(|err| match err.item {
+            Some(item) if item < first_item => Some(err),
+            Some(item) if item >= end_item => Some(ParseError {
+                span: err.span.shifted(delta),
+                item: Some(item.saturating_add_signed(item_delta)),
+                ..err
+            }),
+            None if err.span.start >= region.end => Some(ParseError {
+                span: err.span.shifted(delta),
+                ..err
+            }),
+            _ => None,
+        })
+        .collect();
+
+    ParseResult {
+        ast: Ast { nodes },
+        cst: SyntaxNode::new(SyntaxKind::SourceFile, children),
+        errors,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+const ASSIGNMENT_PRECEDENCE: u8 = 1;
+
+/// Binding strength of every binary operator; higher binds tighter.
+fn binary_precedence(kind: TokenKind) -> Option<(u8, Associativity)> {
+    let entry = match kind {
+        TokenKind::Equal
+        | TokenKind::PlusEqual
+        | TokenKind::MinusEqual
+        | TokenKind::StarEqual
+        | TokenKind::SlashEqual
+        | TokenKind::PercentEqual => (ASSIGNMENT_PRECEDENCE, Associativity::Right),
+        TokenKind::PipePipe => (2, Associativity::Left),
+        TokenKind::AmpAmp => (3, Associativity::Left),
+        TokenKind::EqualEqual | TokenKind::BangEqual => (4, Associativity::Left),
+        TokenKind::Less | TokenKind::LessEqual | TokenKind::Greater | TokenKind::GreaterEqual => {
+            (5, Associativity::Left)
+        }
+        TokenKind::DotDot => (6, Associativity::Left),
+        TokenKind::Plus | TokenKind::Minus => (7, Associativity::Left),
+        TokenKind::Star | TokenKind::Slash | TokenKind::Percent => (8, Associativity::Left),
+        _ => return None,
+    };
+    Some(entry)
+}
+
+fn starts_statement(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Let
+            | TokenKind::Var
+            | TokenKind::Const
+            | TokenKind::Fn
+            | TokenKind::Struct
+            | TokenKind::Class
+            | TokenKind::Import
+            | TokenKind::If
+            | TokenKind::While
+            | TokenKind::For
+            | TokenKind::Return
+            | TokenKind::Break
+            | TokenKind::Continue
+    )
+}
+
+fn starts_expression(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Identifier
+            | TokenKind::Integer
+            | TokenKind::Float
+            | TokenKind::String
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Null
+            | TokenKind::LeftParen
+            | TokenKind::LeftBracket
+            | TokenKind::LeftBrace
+            | TokenKind::Fn
+            | TokenKind::Bang
+            | TokenKind::Minus
+    )
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    /// Every token including trivia; the parser itself only looks at the
+    /// non-trivia ones and hands the rest straight to the CST.
+    tokens: Vec<Token>,
+    pos: usize,
+    emitted: usize,
+    previous: Option<Token>,
+    builder: CstBuilder,
+    errors: Vec<ParseError>,
+    /// Index of the top-level item being parsed.
+    item: Option<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut parser = Parser {
+            source,
+            tokens: lexer::tokenize(source),
+            pos: 0,
+            emitted: 0,
+            previous: None,
+            builder: CstBuilder::new(),
+            errors: Vec::new(),
+            item: None,
+        };
+        parser.skip_trivia();
+        parser
+    }
+
+    fn skip_trivia(&mut self) {
+        while self.tokens[self.pos].kind.is_trivia() {
+            let token = self.tokens[self.pos];
+            let text = self.text(token);
+            if text.starts_with("/*") && (text.len() < 4 || !text.ends_with("*/")) {
+                self.errors.push(ParseError {
+                    message: "Unterminated block comment".to_string(),
+                    span: token.span,
+                    item: None,
+                });
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Adds the not yet emitted tokens before `index` to the current node.
+    fn flush_trivia_to(&mut self, index: usize) {
+        while self.emitted < index {
+            let token = self.tokens[self.emitted];
+            self.builder.token(token.kind, token.text(self.source));
+            self.emitted += 1;
+        }
+    }
+
+    fn start_node(&mut self, kind: SyntaxKind) {
+        self.flush_trivia_to(self.pos);
+        self.builder.start_node(kind);
+    }
+
+    fn finish_node(&mut self) {
+        self.builder.finish_node();
+    }
+
+    fn checkpoint(&mut self) -> crate::cst::Checkpoint {
+        self.flush_trivia_to(self.pos);
+        self.builder.checkpoint()
+    }
+
+    fn peek(&self) -> Token {
+        self.tokens[self.pos]
+    }
+
+    /// Kind of the `n`th non-trivia token after the current one.
+    fn nth(&self, n: usize) -> TokenKind {
+        self.tokens[self.pos..]
+            .iter()
+            .filter(|token| !token.kind.is_trivia())
+            .nth(n)
+            .map_or(TokenKind::Eof, |token| token.kind)
+    }
+
+    fn at(&self, kind: TokenKind) -> bool {
+        self.peek().kind == kind
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.peek();
+        if token.kind != TokenKind::Eof {
+            self.flush_trivia_to(self.pos);
+            self.builder.token(token.kind, self.text(token));
+            self.pos += 1;
+            self.emitted = self.pos;
+            self.previous = Some(token);
+            self.skip_trivia();
+        }
+        token
+    }
+
+    fn eat(&mut self, kind: TokenKind) -> bool {
+        if self.at(kind) {
+            self.bump();
+            true
         } else {
-            if !trimmed.starts_with('}') && !trimmed.starts_with(')') && !trimmed.starts_with(']') {
-                match parse_expression(trimmed, line_num, 0) {
-                    Ok(expr) => {
-                        nodes.push(Node::ExpressionStatement {
-                            expression: Box::new(expr),
-                            line: line_num,
-                            column: 0,
-                        });
-                    }
-                    Err(err) => {
-                        if !trimmed
-                            .chars()
-                            .all(|c| c.is_whitespace() || c == '{' || c == '}')
-                        {
-                            errors.push(err);
-                        }
-                    }
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<Token, ParseError> {
+        if self.at(kind) {
+            Ok(self.bump())
+        } else {
+            Err(self.error_at_current(&format!("Expected {}", what)))
+        }
+    }
+
+    fn expect_identifier(&mut self, what: &str) -> Result<(String, Span), ParseError> {
+        let token = self.expect(TokenKind::Identifier, what)?;
+        Ok((self.text(token).to_string(), token.span))
+    }
+
+    /// Whether a line break separates the current token from the previous one.
+    fn at_line_start(&self) -> bool {
+        self.previous.is_none()
+            || self.source[self.previous_end()..self.current_start()].contains('\n')
+    }
+
+    fn current_start(&self) -> usize {
+        self.peek().span.start
+    }
+
+    fn previous_end(&self) -> usize {
+        self.previous.map_or(0, |token| token.span.end)
+    }
+
+    /// Span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.previous_end().max(start))
+    }
+
+    fn text(&self, token: Token) -> &'a str {
+        token.text(self.source)
+    }
+
+    fn error_at_current(&self, message: &str) -> ParseError {
+        let token = self.peek();
+        let found = if token.kind == TokenKind::Eof {
+            "end of file".to_string()
+        } else {
+            format!("'{}'", self.text(token))
+        };
+        // Something missing at the end of a line is reported where it was
+        // expected rather than on whatever starts the next line.
+        let span = if self.previous.is_some() && self.at_line_start() {
+            Span::new(self.previous_end(), self.previous_end())
+        } else {
+            token.span
+        };
+
+        self.error(format!("{}, found {}", message, found), span)
+    }
+
+    /// An error reported while parsing the current top-level item.
+    fn error(&self, message: impl Into<String>, span: Span) -> ParseError {
+        ParseError {
+            message: message.into(),
+            span,
+            item: self.item,
+        }
+    }
+
+    /// Parses a statement, turning a failure into a `Node::Error` and skipping
+    /// ahead to the next statement boundary so parsing can continue.
+    fn parse_statement_recovering(&mut self) -> Node {
+        let start_pos = self.pos;
+        let start = self.current_start();
+        let checkpoint = self.checkpoint();
+        let depth = self.builder.depth();
+
+        match self.parse_statement() {
+            Ok(node) => node,
+            Err(err) => {
+                self.errors.push(err);
+                self.builder.finish_to(depth);
+                self.builder.start_node_at(checkpoint, SyntaxKind::Error);
+                self.synchronize(self.pos == start_pos);
+                self.finish_node();
+                Node::Error {
+                    span: self.span_from(start),
                 }
             }
         }
     }
 
-    if errors.is_empty() {
-        Ok(Ast { nodes })
-    } else {
-        Err(errors)
+    /// Parses an expression, or records the error and yields an
+    /// `Expression::Error` placeholder so the enclosing node survives.
+    fn parse_expression_recovering(&mut self) -> Expression {
+        // Nothing to skip: keep the placeholder inside its parent, without
+        // a syntax node that would pull the trivia before it into the parent.
+        if !starts_expression(self.peek().kind) {
+            let err = self.error_at_current("Expected expression");
+            self.errors.push(err);
+            return Expression::Error {
+                span: Span::new(self.previous_end(), self.previous_end()),
+            };
+        }
+
+        let start = self.current_start();
+        let checkpoint = self.checkpoint();
+        let depth = self.builder.depth();
+
+        match self.parse_expression() {
+            Ok(expression) => expression,
+            Err(err) => {
+                self.errors.push(err);
+                self.builder.finish_to(depth);
+                self.builder.start_node_at(checkpoint, SyntaxKind::Error);
+                self.synchronize(false);
+                self.finish_node();
+                Expression::Error {
+                    span: self.span_from(start),
+                }
+            }
+        }
     }
-}
 
-fn parse_variable_declaration(line: &str, line_num: usize) -> Option<Node> {
-    let mut parts = line.split_whitespace();
+    /// Parses a struct field or class member with `parse`. On failure the
+    /// error is recorded and the rest of the member skipped, so one bad
+    /// member does not cost the whole declaration.
+    fn parse_member_recovering<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Option<T> {
+        let start_pos = self.pos;
+        let checkpoint = self.checkpoint();
+        let depth = self.builder.depth();
+
+        match parse(self) {
+            Ok(member) => Some(member),
+            Err(err) => {
+                self.errors.push(err);
+                self.builder.finish_to(depth);
+                self.builder.start_node_at(checkpoint, SyntaxKind::Error);
+                self.synchronize_past(
+                    self.pos == start_pos,
+                    &[TokenKind::Comma, TokenKind::Semicolon],
+                );
+                self.finish_node();
+                None
+            }
+        }
+    }
 
-    let keyword = parts.next()?;
-    if keyword != "var" && keyword != "let" && keyword != "const" {
-        return None;
+    /// Skips tokens up to the next statement boundary: a `;`, a closing brace
+    /// belonging to an enclosing block, a token that starts a statement, or
+    /// a new line outside of any brackets. `skip_current` forces progress when
+    /// the failure happened on the very first token of a statement.
+    fn synchronize(&mut self, skip_current: bool) {
+        self.synchronize_past(skip_current, &[TokenKind::Semicolon]);
     }
 
-    let name = parts.next()?.trim_end_matches(':');
+    /// Like `synchronize`, with `separators` ending the skipped run when met
+    /// outside of any brackets; the separator itself is consumed.
+    fn synchronize_past(&mut self, skip_current: bool, separators: &[TokenKind]) {
+        let mut depth = 0usize;
 
-    let mut data_type = None;
-    let mut current = parts.next()?;
+        if skip_current {
+            match self.peek().kind {
+                TokenKind::Eof => return,
+                TokenKind::LeftBrace | TokenKind::LeftParen | TokenKind::LeftBracket => depth += 1,
+                _ => {}
+            }
+            self.bump();
+        }
 
-    if current == ":" {
-        let type_name = parts.next()?;
-        data_type = Some(Type::Basic(type_name.to_string()));
-        current = parts.next()?;
+        loop {
+            let kind = self.peek().kind;
+
+            match kind {
+                TokenKind::Eof => break,
+                _ if depth == 0 && separators.contains(&kind) => {
+                    self.bump();
+                    break;
+                }
+                TokenKind::RightBrace if depth == 0 => break,
+                TokenKind::LeftBrace | TokenKind::LeftParen | TokenKind::LeftBracket => depth += 1,
+                TokenKind::RightBrace | TokenKind::RightParen | TokenKind::RightBracket => {
+                    depth = depth.saturating_sub(1)
+                }
+                _ if depth == 0 && (starts_statement(kind) || self.at_line_start()) => break,
+                _ => {}
+            }
+            self.bump();
+        }
     }
 
-    let initializer = if current == "=" {
-        let value_str = parts.collect::<Vec<&str>>().join(" ");
-        let value_str = value_str.trim_end_matches(';');
+    fn parse_statement(&mut self) -> Result<Node, ParseError> {
+        let node = match self.peek().kind {
+            TokenKind::Let | TokenKind::Var | TokenKind::Const => {
+                self.parse_variable_declaration()?
+            }
+            TokenKind::Fn if self.nth(1) != TokenKind::LeftParen => {
+                self.parse_function_declaration()?
+            }
+            TokenKind::Struct => self.parse_struct_declaration()?,
+            TokenKind::Class => self.parse_class_declaration()?,
+            TokenKind::Import => self.parse_import_declaration()?,
+            TokenKind::If => self.parse_if_statement()?,
+            TokenKind::While => self.parse_while_statement()?,
+            TokenKind::For => self.parse_for_statement()?,
+            TokenKind::Return => self.parse_return_statement(),
+            TokenKind::Break => {
+                self.start_node(SyntaxKind::BreakStatement);
+                let span = self.bump().span;
+                self.finish_node();
+                Node::BreakStatement { span }
+            }
+            TokenKind::Continue => {
+                self.start_node(SyntaxKind::ContinueStatement);
+                let span = self.bump().span;
+                self.finish_node();
+                Node::ContinueStatement { span }
+            }
+            TokenKind::LeftBrace => {
+                let start = self.current_start();
+                let statements = self.parse_block()?;
+                Node::Block {
+                    statements,
+                    span: self.span_from(start),
+                }
+            }
+            _ => {
+                let start = self.current_start();
+                self.start_node(SyntaxKind::ExpressionStatement);
+                let expression = self.parse_expression()?;
+                self.finish_node();
+                Node::ExpressionStatement {
+                    expression: Box::new(expression),
+                    span: self.span_from(start),
+                }
+            }
+        };
 
-        match parse_expression(value_str, line_num, line.find('=').unwrap_or(0) + 1) {
-            Ok(expr) => Some(Box::new(expr)),
-            Err(_) => None,
+        self.eat(TokenKind::Semicolon);
+        Ok(node)
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Node>, ParseError> {
+        self.start_node(SyntaxKind::Block);
+        self.expect(TokenKind::LeftBrace, "'{'")?;
+
+        let mut statements = Vec::new();
+        while !self.at(TokenKind::RightBrace) {
+            if self.at(TokenKind::Eof) {
+                let err = self.error_at_current("Expected '}'");
+                self.errors.push(err);
+                self.finish_node();
+                return Ok(statements);
+            }
+            statements.push(self.parse_statement_recovering());
         }
-    } else {
-        None
-    };
+        self.bump();
+        self.finish_node();
 
-    Some(Node::VariableDeclaration {
-        name: name.to_string(),
-        initializer,
-        data_type,
-        is_mutable: keyword != "const",
-        line: line_num,
-        column: 0,
-    })
-}
+        Ok(statements)
+    }
+
+    fn parse_if_statement(&mut self) -> Result<Node, ParseError> {
+        self.start_node(SyntaxKind::IfStatement);
+        let keyword = self.bump();
+        let condition = self.parse_condition();
+        let then_start = self.current_start();
+        let then_branch = self.parse_block()?;
+        let then_span = self.span_from(then_start);
+
+        let (else_branch, else_span) = if self.at(TokenKind::Else) {
+            self.start_node(SyntaxKind::ElseClause);
+            self.bump();
+            let else_start = self.current_start();
+            let branch = if self.at(TokenKind::If) {
+                vec![self.parse_if_statement()?]
+            } else {
+                self.parse_block()?
+            };
+            self.finish_node();
+            (Some(branch), Some(self.span_from(else_start)))
+        } else {
+            (None, None)
+        };
+        self.finish_node();
 
-fn parse_function_declaration(line: &str, line_num: usize, all_lines: &[&str]) -> Option<Node> {
-    if !line.trim().starts_with("fn ") {
-        return None;
+        Ok(Node::IfStatement {
+            condition: Box::new(condition),
+            then_branch,
+            then_span,
+            else_branch,
+            else_span,
+            span: self.span_from(keyword.span.start),
+        })
     }
 
-    let fn_decl_pattern = regex::Regex::new(
-        r"fn\s+([a-zA-Z_][a-zA-Z0-9_]*)\s*\((.*?)\)(?:\s*:\s*([a-zA-Z_][a-zA-Z0-9_]*))?\s*\{",
-    )
-    .ok()?;
+    /// A missing condition is reported without consuming the block after it,
+    /// which recovery would otherwise skip entirely.
+    fn parse_condition(&mut self) -> Expression {
+        if self.at(TokenKind::LeftBrace) {
+            let err = self.error_at_current("Expected condition");
+            self.errors.push(err);
+            return Expression::Error {
+                span: Span::new(self.previous_end(), self.previous_end()),
+            };
+        }
+        self.parse_expression_recovering()
+    }
 
-    if let Some(captures) = fn_decl_pattern.captures(line) {
-        let name = captures.get(1)?.as_str().to_string();
-        let params_str = captures.get(2)?.as_str();
+    fn parse_while_statement(&mut self) -> Result<Node, ParseError> {
+        self.start_node(SyntaxKind::WhileStatement);
+        let keyword = self.bump();
+        let condition = self.parse_condition();
+        let body_start = self.current_start();
+        let body = self.parse_block()?;
+        let body_span = self.span_from(body_start);
+        self.finish_node();
 
-        let params = parse_parameters(params_str);
+        Ok(Node::WhileStatement {
+            condition: Box::new(condition),
+            body,
+            body_span,
+            span: self.span_from(keyword.span.start),
+        })
+    }
 
-        let return_type = captures
-            .get(3)
-            .map(|rt| Type::Basic(rt.as_str().to_string()));
+    /// `for x in items { }` or `for init; condition; increment { }`; either
+    /// header may also be wrapped in parentheses.
+    fn parse_for_statement(&mut self) -> Result<Node, ParseError> {
+        let start = self.current_start();
+        let checkpoint = self.checkpoint();
+        self.bump();
+        let parenthesized = self.eat(TokenKind::LeftParen);
 
-        Some(Node::FunctionDeclaration {
-            name,
-            params,
-            return_type,
-            body: Vec::new(),
-            line: line_num,
-            column: line.find("fn")? + 1,
+        if self.at(TokenKind::Identifier) && self.nth(1) == TokenKind::In {
+            self.builder
+                .start_node_at(checkpoint, SyntaxKind::ForInStatement);
+            let (variable, variable_span) = self.expect_identifier("loop variable")?;
+            self.bump();
+            let iterable = self.parse_expression_recovering();
+            if parenthesized {
+                self.expect(TokenKind::RightParen, "')'")?;
+            }
+            let body = self.parse_block()?;
+            self.finish_node();
+
+            return Ok(Node::ForInStatement {
+                variable,
+                variable_span,
+                iterable: Box::new(iterable),
+                body,
+                span: self.span_from(start),
+            });
+        }
+
+        self.builder
+            .start_node_at(checkpoint, SyntaxKind::ForStatement);
+        let initializer = match self.peek().kind {
+            TokenKind::Semicolon => None,
+            TokenKind::Let | TokenKind::Var | TokenKind::Const => {
+                Some(Box::new(self.parse_variable_declaration()?))
+            }
+            _ => {
+                let start = self.current_start();
+                self.start_node(SyntaxKind::ExpressionStatement);
+                let expression = self.parse_expression()?;
+                self.finish_node();
+                Some(Box::new(Node::ExpressionStatement {
+                    expression: Box::new(expression),
+                    span: self.span_from(start),
+                }))
+            }
+        };
+        self.expect(TokenKind::Semicolon, "';'")?;
+
+        let condition = if self.at(TokenKind::Semicolon) {
+            None
+        } else {
+            Some(Box::new(self.parse_expression()?))
+        };
+        self.expect(TokenKind::Semicolon, "';'")?;
+
+        let closing = if parenthesized {
+            TokenKind::RightParen
+        } else {
+            TokenKind::LeftBrace
+        };
+        let increment = if self.at(closing) {
+            None
+        } else {
+            Some(Box::new(self.parse_expression()?))
+        };
+        if parenthesized {
+            self.expect(TokenKind::RightParen, "')'")?;
+        }
+        let body = self.parse_block()?;
+        self.finish_node();
+
+        Ok(Node::ForStatement {
+            initializer,
+            condition,
+            increment,
+            body,
+            span: self.span_from(start),
         })
-    } else {
-        None
     }
-}
 
-fn parse_parameters(params_str: &str) -> Vec<Parameter> {
-    let mut params = Vec::new();
+    /// The value is optional: a `return` directly followed by `;`, `}` or a
+    /// line break returns nothing.
+    fn parse_return_statement(&mut self) -> Node {
+        self.start_node(SyntaxKind::ReturnStatement);
+        let keyword = self.bump();
 
-    for param in params_str.split(',') {
-        let param = param.trim();
-        if param.is_empty() {
-            continue;
+        let ends_statement = matches!(
+            self.peek().kind,
+            TokenKind::Semicolon | TokenKind::RightBrace | TokenKind::Eof
+        ) || self.at_line_start();
+        let expression = if ends_statement {
+            None
+        } else {
+            Some(Box::new(self.parse_expression_recovering()))
+        };
+        self.finish_node();
+
+        Node::ReturnStatement {
+            expression,
+            span: self.span_from(keyword.span.start),
         }
+    }
 
-        let parts: Vec<&str> = param.split(':').collect();
-        let name = parts[0].trim().to_string();
+    fn parse_variable_declaration(&mut self) -> Result<Node, ParseError> {
+        self.start_node(SyntaxKind::VariableDeclaration);
+        let keyword = self.bump();
+        let (name, name_span) = self.expect_identifier("variable name")?;
 
-        let typ = if parts.len() > 1 {
-            let type_name = parts[1].trim();
-            Some(Type::Basic(type_name.to_string()))
+        let data_type = if self.eat(TokenKind::Colon) {
+            Some(self.parse_type()?)
         } else {
             None
         };
 
-        params.push(Parameter { name, typ });
+        let initializer = if self.eat(TokenKind::Equal) {
+            Some(Box::new(self.parse_expression_recovering()))
+        } else {
+            None
+        };
+        self.finish_node();
+
+        Ok(Node::VariableDeclaration {
+            name,
+            name_span,
+            initializer,
+            data_type,
+            is_mutable: keyword.kind != TokenKind::Const,
+            span: self.span_from(keyword.span.start),
+        })
     }
 
-    params
-}
+    fn parse_function_declaration(&mut self) -> Result<Node, ParseError> {
+        self.start_node(SyntaxKind::FunctionDeclaration);
+        let keyword = self.bump();
+        let (name, name_span) = self.expect_identifier("function name")?;
+        let params = self.parse_parameters()?;
 
-fn parse_struct_declaration(line: &str, line_num: usize, all_lines: &[&str]) -> Option<Node> {
-    if !line.trim().starts_with("struct ") {
-        return None;
+        let return_type = if self.eat(TokenKind::Colon) || self.eat(TokenKind::Arrow) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        let body = self.parse_block()?;
+        self.finish_node();
+
+        Ok(Node::FunctionDeclaration {
+            name,
+            name_span,
+            params,
+            return_type,
+            body,
+            span: self.span_from(keyword.span.start),
+        })
     }
 
-    let struct_decl_pattern = regex::Regex::new(r"struct\s+([a-zA-Z_][a-zA-Z0-9_]*)\s*\{").ok()?;
+    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, ParseError> {
+        self.start_node(SyntaxKind::ParameterList);
+        self.expect(TokenKind::LeftParen, "'('")?;
 
-    if let Some(captures) = struct_decl_pattern.captures(line) {
-        let name = captures.get(1)?.as_str().to_string();
+        let mut params = Vec::new();
+        while !self.at(TokenKind::RightParen) {
+            self.start_node(SyntaxKind::Parameter);
+            let (name, span) = self.expect_identifier("parameter name")?;
+            let typ = if self.eat(TokenKind::Colon) {
+                Some(self.parse_type()?)
+            } else {
+                None
+            };
+            self.finish_node();
+            params.push(Parameter { name, typ, span });
 
-        Some(Node::StructDeclaration {
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightParen, "')'")?;
+        self.finish_node();
+
+        Ok(params)
+    }
+
+    fn parse_struct_declaration(&mut self) -> Result<Node, ParseError> {
+        self.start_node(SyntaxKind::StructDeclaration);
+        let keyword = self.bump();
+        let (name, name_span) = self.expect_identifier("struct name")?;
+
+        self.expect(TokenKind::LeftBrace, "'{'")?;
+        let mut fields = Vec::new();
+        while !self.at_body_end() {
+            fields.extend(self.parse_member_recovering(Self::parse_field));
+            if !self.eat(TokenKind::Comma) {
+                self.eat(TokenKind::Semicolon);
+            }
+        }
+        self.eat(TokenKind::RightBrace);
+        self.finish_node();
+
+        Ok(Node::StructDeclaration {
             name,
-            fields: Vec::new(),
-            line: line_num,
-            column: line.find("struct")? + 1,
+            name_span,
+            fields,
+            span: self.span_from(keyword.span.start),
         })
-    } else {
-        None
     }
-}
 
-fn parse_import_declaration(line: &str, line_num: usize) -> Option<Node> {
-    if !line.trim().starts_with("import ") {
-        return None;
+    fn parse_class_declaration(&mut self) -> Result<Node, ParseError> {
+        self.start_node(SyntaxKind::ClassDeclaration);
+        let keyword = self.bump();
+        let (name, name_span) = self.expect_identifier("class name")?;
+
+        self.expect(TokenKind::LeftBrace, "'{'")?;
+        let mut methods = Vec::new();
+        let mut properties = Vec::new();
+        while !self.at_body_end() {
+            if self.at(TokenKind::Fn) {
+                methods.extend(self.parse_member_recovering(Self::parse_function_declaration));
+            } else {
+                properties.extend(self.parse_member_recovering(Self::parse_field));
+                if !self.eat(TokenKind::Comma) {
+                    self.eat(TokenKind::Semicolon);
+                }
+            }
+        }
+        self.eat(TokenKind::RightBrace);
+        self.finish_node();
+
+        Ok(Node::ClassDeclaration {
+            name,
+            name_span,
+            methods,
+            properties,
+            span: self.span_from(keyword.span.start),
+        })
     }
 
-    let path_pattern = regex::Regex::new(r#"import\s+(?:\{(.*?)\}\s+from\s+)?"(.+?)""#).ok()?;
+    /// True at the `}` closing a struct or class body. A missing brace at the
+    /// end of the file is reported here so the members parsed so far survive.
+    fn at_body_end(&mut self) -> bool {
+        if self.at(TokenKind::Eof) {
+            let err = self.error_at_current("Expected '}'");
+            self.errors.push(err);
+            return true;
+        }
+        self.at(TokenKind::RightBrace)
+    }
+
+    /// A struct field or class property: `const name: type = initializer`,
+    /// where the keyword, the type and the initializer are optional.
+    fn parse_field(&mut self) -> Result<StructField, ParseError> {
+        let start = self.current_start();
+        self.start_node(SyntaxKind::StructField);
+        let is_mutable = !self.eat(TokenKind::Const);
+        let (name, name_span) = self.expect_identifier("field name")?;
 
-    if let Some(captures) = path_pattern.captures(line) {
-        let path = captures.get(2)?.as_str().to_string();
+        let typ = if self.eat(TokenKind::Colon) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
 
-        let imported_items = if let Some(items_match) = captures.get(1) {
-            items_match
-                .as_str()
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .collect()
+        let initializer = if self.eat(TokenKind::Equal) {
+            Some(Box::new(self.parse_expression_recovering()))
         } else {
-            Vec::new()
+            None
         };
+        self.finish_node();
+
+        Ok(StructField {
+            name,
+            name_span,
+            typ,
+            initializer,
+            is_mutable,
+            span: self.span_from(start),
+        })
+    }
 
-        Some(Node::ImportDeclaration {
+    fn parse_import_declaration(&mut self) -> Result<Node, ParseError> {
+        self.start_node(SyntaxKind::ImportDeclaration);
+        let keyword = self.bump();
+
+        let mut imported_items = Vec::new();
+        if self.eat(TokenKind::LeftBrace) {
+            while !self.at(TokenKind::RightBrace) {
+                let (name, span) = self.expect_identifier("imported name")?;
+                imported_items.push(ImportedItem { name, span });
+                if !self.eat(TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(TokenKind::RightBrace, "'}'")?;
+            self.expect(TokenKind::From, "'from'")?;
+        }
+
+        let path_token = self.expect(TokenKind::String, "import path")?;
+        let path = self.string_value(path_token);
+        self.finish_node();
+
+        Ok(Node::ImportDeclaration {
             path,
             imported_items,
-            line: line_num,
-            column: line.find("import")? + 1,
+            span: self.span_from(keyword.span.start),
         })
-    } else {
-        None
     }
-}
 
-fn parse_expression(
-    expr_str: &str,
-    line_num: usize,
-    column_offset: usize,
-) -> Result<Expression, ParseError> {
-    let trimmed = expr_str.trim();
-    if trimmed.is_empty() {
-        return Err(ParseError {
-            message: "Empty expression".to_string(),
-            line: line_num,
-            column: column_offset,
-        });
-    }
-
-    if let Some(value) = parse_literal(trimmed) {
-        return Ok(Expression::Literal {
-            value,
-            line: line_num,
-            column: column_offset,
-        });
-    }
-
-    if let Some(dot_idx) = trimmed.find('.') {
-        let object_str = &trimmed[..dot_idx].trim();
-        let property = &trimmed[dot_idx + 1..].trim();
-
-        if !property.contains(' ') && !property.contains('.') && !property.contains('(') {
-            if let Ok(object) = parse_expression(object_str, line_num, column_offset) {
-                return Ok(Expression::PropertyAccess {
-                    object: Box::new(object),
-                    property: property.to_string(),
-                    line: line_num,
-                    column: column_offset + dot_idx + 1,
-                });
+    fn parse_type(&mut self) -> Result<Type, ParseError> {
+        self.start_node(SyntaxKind::Type);
+        let typ = self.parse_union_type()?;
+        self.finish_node();
+        Ok(typ)
+    }
+
+    /// `A | B | C`; nested unions are flattened into a single list.
+    fn parse_union_type(&mut self) -> Result<Type, ParseError> {
+        let first = self.parse_postfix_type()?;
+        if !self.at(TokenKind::Pipe) {
+            return Ok(first);
+        }
+
+        let mut types = Vec::new();
+        let mut member = first;
+        loop {
+            match member {
+                Type::Union(members) => types.extend(members),
+                typ => types.push(typ),
+            }
+            if !self.eat(TokenKind::Pipe) {
+                return Ok(Type::Union(types));
             }
+            member = self.parse_postfix_type()?;
         }
     }
 
-    if let Some(paren_idx) = trimmed.find('(') {
-        let callee_str = &trimmed[..paren_idx].trim();
+    /// A primary type followed by any number of `[]` and `?` suffixes.
+    fn parse_postfix_type(&mut self) -> Result<Type, ParseError> {
+        let mut typ = self.parse_primary_type()?;
+        loop {
+            if self.eat(TokenKind::LeftBracket) {
+                self.expect(TokenKind::RightBracket, "']'")?;
+                typ = Type::Array(Box::new(typ));
+            } else if self.eat(TokenKind::Question) {
+                typ = Type::Optional(Box::new(typ));
+            } else {
+                return Ok(typ);
+            }
+        }
+    }
 
-        let args_str = if let Some(end_paren_idx) = find_matching_paren(trimmed, paren_idx) {
-            &trimmed[paren_idx + 1..end_paren_idx]
-        } else {
-            return Err(ParseError {
-                message: "Unmatched parenthesis in function call".to_string(),
-                line: line_num,
-                column: column_offset + paren_idx,
+    fn parse_primary_type(&mut self) -> Result<Type, ParseError> {
+        match self.peek().kind {
+            TokenKind::Identifier => {
+                let (name, span) = self.expect_identifier("type name")?;
+                Ok(Type::Basic { name, span })
+            }
+            TokenKind::Null => {
+                let token = self.bump();
+                Ok(Type::Basic {
+                    name: "null".to_string(),
+                    span: token.span,
+                })
+            }
+            TokenKind::LeftParen => {
+                self.bump();
+                let typ = self.parse_union_type()?;
+                self.expect(TokenKind::RightParen, "')'")?;
+                Ok(typ)
+            }
+            TokenKind::Fn => {
+                self.bump();
+                self.expect(TokenKind::LeftParen, "'('")?;
+                let mut params = Vec::new();
+                while !self.at(TokenKind::RightParen) {
+                    params.push(self.parse_union_type()?);
+                    if !self.eat(TokenKind::Comma) {
+                        break;
+                    }
+                }
+                self.expect(TokenKind::RightParen, "')'")?;
+                self.expect(TokenKind::Arrow, "'->'")?;
+                // The return type binds tighter than `|`, so
+                // `fn() -> int | string` is a union containing a function.
+                let return_type = self.parse_postfix_type()?;
+                Ok(Type::Function {
+                    params,
+                    return_type: Box::new(return_type),
+                })
+            }
+            _ => Err(self.error_at_current("Expected type")),
+        }
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        self.parse_binary(0)
+    }
+
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expression, ParseError> {
+        let start = self.current_start();
+        let checkpoint = self.checkpoint();
+        let mut left = self.parse_unary()?;
+
+        loop {
+            let operator_token = self.peek();
+            let (precedence, associativity) = match binary_precedence(operator_token.kind) {
+                Some(entry) => entry,
+                None => break,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+
+            let is_assignment = precedence == ASSIGNMENT_PRECEDENCE;
+            self.builder.start_node_at(
+                checkpoint,
+                if is_assignment {
+                    SyntaxKind::AssignmentExpression
+                } else {
+                    SyntaxKind::BinaryExpression
+                },
+            );
+            self.bump();
+
+            let next_precedence = match associativity {
+                Associativity::Left => precedence + 1,
+                Associativity::Right => precedence,
+            };
+            let right = self.parse_binary(next_precedence)?;
+            self.finish_node();
+            let operator = self.text(operator_token).to_string();
+
+            left = if is_assignment {
+                if !matches!(
+                    left,
+                    Expression::Variable { .. }
+                        | Expression::PropertyAccess { .. }
+                        | Expression::ArrayAccess { .. }
+                ) {
+                    return Err(self.error(
+                        format!("Invalid left-hand side in '{}' assignment", operator),
+                        left.span(),
+                    ));
+                }
+                Expression::Assignment {
+                    operator,
+                    target: Box::new(left),
+                    value: Box::new(right),
+                    span: self.span_from(start),
+                }
+            } else {
+                Expression::BinaryOperation {
+                    operator,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    span: self.span_from(start),
+                }
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        let token = self.peek();
+        if !matches!(token.kind, TokenKind::Bang | TokenKind::Minus) {
+            return self.parse_postfix();
+        }
+
+        if token.kind == TokenKind::Minus && self.negative_number_ahead() {
+            self.start_node(SyntaxKind::Literal);
+            self.bump();
+            let number = self.bump();
+            self.finish_node();
+            let span = self.span_from(token.span.start);
+            return Ok(Expression::Literal {
+                value: self.number_value(number, true, span),
+                span,
             });
+        }
+
+        self.start_node(SyntaxKind::UnaryExpression);
+        self.bump();
+        let operand = self.parse_unary()?;
+        self.finish_node();
+
+        Ok(Expression::UnaryOperation {
+            operator: self.text(token).to_string(),
+            operand: Box::new(operand),
+            span: self.span_from(token.span.start),
+        })
+    }
+
+    /// Whether the current `-` and the number after it form a negative
+    /// literal, which is folded so that `i64::MIN` can be written. A number
+    /// that is called, indexed or accessed is negated as an operand instead.
+    fn negative_number_ahead(&self) -> bool {
+        let mut tokens = self.tokens[self.pos + 1..]
+            .iter()
+            .filter(|token| !token.kind.is_trivia());
+        let Some(number) = tokens.next() else {
+            return false;
         };
+        if !matches!(number.kind, TokenKind::Integer | TokenKind::Float) {
+            return false;
+        }
+        match tokens.next() {
+            Some(next) => match next.kind {
+                TokenKind::Dot => false,
+                TokenKind::LeftParen | TokenKind::LeftBracket => {
+                    self.source[number.span.end..next.span.start].contains('\n')
+                }
+                _ => true,
+            },
+            None => true,
+        }
+    }
 
-        let callee = parse_expression(callee_str, line_num, column_offset)?;
+    fn parse_postfix(&mut self) -> Result<Expression, ParseError> {
+        let start = self.current_start();
+        let checkpoint = self.checkpoint();
+        let mut expression = self.parse_primary()?;
 
-        let mut arguments = Vec::new();
+        loop {
+            match self.peek().kind {
+                // A `(` or `[` on a new line starts the next statement rather
+                // than calling or indexing the previous one; `.` still chains.
+                TokenKind::LeftParen | TokenKind::LeftBracket if self.at_line_start() => break,
+                TokenKind::Dot => {
+                    self.builder
+                        .start_node_at(checkpoint, SyntaxKind::PropertyAccess);
+                    self.bump();
+                    // A missing name is reported but keeps the access, so that
+                    // completion after `object.` still sees the object.
+                    let property = if self.at(TokenKind::Identifier) {
+                        self.expect_identifier("property name")?.0
+                    } else {
+                        let err = self.error_at_current("Expected property name");
+                        self.errors.push(err);
+                        String::new()
+                    };
+                    self.finish_node();
+                    expression = Expression::PropertyAccess {
+                        object: Box::new(expression),
+                        property,
+                        span: self.span_from(start),
+                    };
+                }
+                TokenKind::LeftBracket => {
+                    self.builder
+                        .start_node_at(checkpoint, SyntaxKind::IndexExpression);
+                    self.bump();
+                    let index = self.parse_expression()?;
+                    self.expect(TokenKind::RightBracket, "']'")?;
+                    self.finish_node();
+                    expression = Expression::ArrayAccess {
+                        array: Box::new(expression),
+                        index: Box::new(index),
+                        span: self.span_from(start),
+                    };
+                }
+                TokenKind::LeftParen => {
+                    self.builder
+                        .start_node_at(checkpoint, SyntaxKind::CallExpression);
+                    let arguments = self.parse_arguments()?;
+                    self.finish_node();
+                    expression = Expression::Call {
+                        callee: Box::new(expression),
+                        arguments,
+                        span: self.span_from(start),
+                    };
+                }
+                _ => break,
+            }
+        }
 
-        for (i, arg_str) in args_str.split(',').enumerate() {
-            let arg_offset = column_offset
-                + paren_idx
-                + 1
-                + args_str[..args_str.find(arg_str).unwrap_or(0)].len();
-            if let Ok(arg) = parse_expression(arg_str, line_num, arg_offset) {
-                arguments.push(arg);
+        Ok(expression)
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Expression>, ParseError> {
+        self.start_node(SyntaxKind::ArgumentList);
+        self.expect(TokenKind::LeftParen, "'('")?;
+
+        let mut arguments = Vec::new();
+        while !self.at(TokenKind::RightParen) {
+            arguments.push(self.parse_expression()?);
+            if !self.eat(TokenKind::Comma) {
+                break;
             }
         }
+        self.expect(TokenKind::RightParen, "')'")?;
+        self.finish_node();
 
-        return Ok(Expression::Call {
-            callee: Box::new(callee),
-            arguments,
-            line: line_num,
-            column: column_offset,
-        });
+        Ok(arguments)
     }
 
-    if is_valid_identifier(trimmed) {
-        return Ok(Expression::Variable {
-            name: trimmed.to_string(),
-            line: line_num,
-            column: column_offset,
-        });
+    /// The decoded text of a string that may not contain interpolations,
+    /// such as an import path or an object key.
+    fn string_value(&mut self, token: Token) -> String {
+        let mut value = String::new();
+        for part in self.parse_string_parts(token) {
+            match part {
+                Expression::Literal {
+                    value: LiteralValue::String(text),
+                    ..
+                } => value.push_str(&text),
+                part => self
+                    .errors
+                    .push(self.error("String interpolation is not allowed here", part.span())),
+            }
+        }
+        value
     }
 
-    Err(ParseError {
-        message: format!("Failed to parse expression: {}", trimmed),
-        line: line_num,
-        column: column_offset,
-    })
-}
+    /// Splits a string token into literal pieces, with escapes decoded, and
+    /// the expressions of its `${...}` interpolations. Bad escapes, broken
+    /// interpolations and a missing closing quote are reported precisely.
+    fn parse_string_parts(&mut self, token: Token) -> Vec<Expression> {
+        let text = self.text(token);
+        let base = token.span.start;
+        let quote = text.chars().next().unwrap_or('"');
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut literal_start = base + 1;
+        let mut end = None;
+        let mut chars = text.char_indices().skip(1).peekable();
 
-fn parse_literal(text: &str) -> Option<LiteralValue> {
-    if (text.starts_with('"') && text.ends_with('"'))
-        || (text.starts_with('\'') && text.ends_with('\''))
-    {
-        let content = &text[1..text.len() - 1];
-        return Some(LiteralValue::String(content.to_string()));
+        while let Some((i, c)) = chars.next() {
+            match c {
+                _ if c == quote => {
+                    end = Some(i);
+                    break;
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => literal.push('\n'),
+                    Some((_, 't')) => literal.push('\t'),
+                    Some((_, 'r')) => literal.push('\r'),
+                    Some((_, escaped @ ('"' | '\'' | '\\' | '$'))) => literal.push(escaped),
+                    Some((j, other)) => {
+                        self.errors.push(self.error(
+                            format!("Unknown escape sequence '\\{}'", other),
+                            Span::new(base + i, base + j + other.len_utf8()),
+                        ));
+                        literal.push(other);
+                    }
+                    None => {}
+                },
+                '$' if chars.peek().is_some_and(|&(_, next)| next == '{') => {
+                    chars.next();
+                    if !literal.is_empty() {
+                        parts.push(Expression::Literal {
+                            value: LiteralValue::String(std::mem::take(&mut literal)),
+                            span: Span::new(literal_start, base + i),
+                        });
+                    }
+
+                    let Some(close) = lexer::interpolation_end(text, i + 2) else {
+                        self.errors.push(self.error(
+                            "Unterminated string interpolation, expected '}'",
+                            Span::new(base + i, token.span.end),
+                        ));
+                        return parts;
+                    };
+                    parts.push(self.parse_interpolation(&text[i + 2..close], base + i + 2));
+                    while chars.peek().is_some_and(|&(j, _)| j <= close) {
+                        chars.next();
+                    }
+                    literal_start = base + close + 1;
+                }
+                _ => literal.push(c),
+            }
+        }
+
+        let literal_end = match end {
+            Some(end) => base + end,
+            None => {
+                self.errors
+                    .push(self.error("Unterminated string literal", token.span));
+                token.span.end
+            }
+        };
+        if !literal.is_empty() || parts.is_empty() {
+            parts.push(Expression::Literal {
+                value: LiteralValue::String(literal),
+                span: Span::new(literal_start, literal_end),
+            });
+        }
+
+        parts
     }
 
-    if text == "true" {
-        return Some(LiteralValue::Boolean(true));
-    } else if text == "false" {
-        return Some(LiteralValue::Boolean(false));
+    /// Parses the expression inside `${...}`, which starts at `offset` in the
+    /// file. Its tokens stay inside the string token in the CST.
+    fn parse_interpolation(&mut self, source: &str, offset: usize) -> Expression {
+        if source.trim().is_empty() {
+            let span = Span::new(offset, offset + source.len());
+            self.errors
+                .push(self.error("Expected expression in string interpolation", span));
+            return Expression::Error { span };
+        }
+
+        let mut parser = Parser::new(source);
+        let mut expression = parser.parse_expression_recovering();
+        if !parser.at(TokenKind::Eof) {
+            let err = parser.error_at_current("Expected '}'");
+            parser.errors.push(err);
+        }
+
+        expression.shift(offset as isize);
+        self.errors
+            .extend(parser.errors.into_iter().map(|err| ParseError {
+                span: err.span.shifted(offset as isize),
+                item: self.item,
+                ..err
+            }));
+        expression
     }
 
-    if text == "null" {
-        return Some(LiteralValue::Null);
+    fn parse_array_literal(&mut self) -> Result<Expression, ParseError> {
+        self.start_node(SyntaxKind::ArrayLiteral);
+        let open = self.bump();
+
+        let mut elements = Vec::new();
+        while !self.at(TokenKind::RightBracket) {
+            elements.push(self.parse_expression()?);
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightBracket, "']'")?;
+        self.finish_node();
+
+        Ok(Expression::ArrayLiteral {
+            elements,
+            span: self.span_from(open.span.start),
+        })
     }
 
-    if let Ok(int_val) = text.parse::<i64>() {
-        return Some(LiteralValue::Integer(int_val));
+    /// `{ key: value, "quoted key": value, shorthand }`
+    fn parse_object_literal(&mut self) -> Result<Expression, ParseError> {
+        self.start_node(SyntaxKind::ObjectLiteral);
+        let open = self.bump();
+
+        let mut properties = Vec::new();
+        while !self.at(TokenKind::RightBrace) {
+            self.start_node(SyntaxKind::ObjectProperty);
+            let key_token = self.peek();
+            let key = match key_token.kind {
+                TokenKind::Identifier => self.text(key_token).to_string(),
+                TokenKind::String => self.string_value(key_token),
+                _ => return Err(self.error_at_current("Expected property name")),
+            };
+            let key_checkpoint = self.checkpoint();
+            self.bump();
+
+            let value = if self.eat(TokenKind::Colon) {
+                self.parse_expression()?
+            } else if key_token.kind == TokenKind::Identifier {
+                // The shorthand key doubles as the variable it reads.
+                self.builder
+                    .start_node_at(key_checkpoint, SyntaxKind::Variable);
+                self.finish_node();
+                Expression::Variable {
+                    name: key.clone(),
+                    span: key_token.span,
+                }
+            } else {
+                return Err(self.error_at_current("Expected ':'"));
+            };
+            self.finish_node();
+
+            properties.push(ObjectProperty {
+                key,
+                value: Box::new(value),
+                span: self.span_from(key_token.span.start),
+            });
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightBrace, "'}'")?;
+        self.finish_node();
+
+        Ok(Expression::ObjectLiteral {
+            properties,
+            span: self.span_from(open.span.start),
+        })
     }
 
-    if let Ok(float_val) = text.parse::<f64>() {
-        return Some(LiteralValue::Number(float_val));
+    /// An anonymous function: `fn(a: int, b: int): int { return a + b }`.
+    fn parse_lambda(&mut self) -> Result<Expression, ParseError> {
+        self.start_node(SyntaxKind::LambdaExpression);
+        let keyword = self.bump();
+        let params = self.parse_parameters()?;
+
+        let return_type = if self.eat(TokenKind::Colon) || self.eat(TokenKind::Arrow) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        let body = self.parse_block()?;
+        self.finish_node();
+
+        Ok(Expression::Lambda {
+            params,
+            body,
+            return_type,
+            span: self.span_from(keyword.span.start),
+        })
     }
 
-    None
-}
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let token = self.peek();
+
+        let value = match token.kind {
+            TokenKind::Identifier => {
+                self.start_node(SyntaxKind::Variable);
+                self.bump();
+                self.finish_node();
+                return Ok(Expression::Variable {
+                    name: self.text(token).to_string(),
+                    span: token.span,
+                });
+            }
+            TokenKind::LeftParen => {
+                self.start_node(SyntaxKind::ParenExpression);
+                self.bump();
+                let expression = self.parse_expression()?;
+                self.expect(TokenKind::RightParen, "')'")?;
+                self.finish_node();
+                return Ok(expression);
+            }
+            TokenKind::LeftBracket => return self.parse_array_literal(),
+            TokenKind::LeftBrace => return self.parse_object_literal(),
+            TokenKind::Fn => return self.parse_lambda(),
+            TokenKind::String => {
+                self.start_node(SyntaxKind::Literal);
+                self.bump();
+                self.finish_node();
+
+                let mut parts = self.parse_string_parts(token);
+                return Ok(match parts.pop() {
+                    None => Expression::Literal {
+                        value: LiteralValue::String(String::new()),
+                        span: token.span,
+                    },
+                    // A lone interpolation such as `"${n}"` is still a string.
+                    Some(Expression::Literal {
+                        value: value @ LiteralValue::String(_),
+                        ..
+                    }) if parts.is_empty() => Expression::Literal {
+                        value,
+                        span: token.span,
+                    },
+                    Some(last) => {
+                        parts.push(last);
+                        Expression::InterpolatedString {
+                            parts,
+                            span: token.span,
+                        }
+                    }
+                });
+            }
+            TokenKind::Integer | TokenKind::Float => self.number_value(token, false, token.span),
+            TokenKind::True => LiteralValue::Boolean(true),
+            TokenKind::False => LiteralValue::Boolean(false),
+            TokenKind::Null => LiteralValue::Null,
+            _ => return Err(self.error_at_current("Expected expression")),
+        };
+        self.start_node(SyntaxKind::Literal);
+        self.bump();
+        self.finish_node();
 
-fn find_matching_paren(text: &str, open_idx: usize) -> Option<usize> {
-    let mut depth = 0;
-    let chars: Vec<char> = text.chars().collect();
-
-    for (i, &c) in chars.iter().enumerate().skip(open_idx) {
-        match c {
-            '(' => depth += 1,
-            ')' => {
-                depth -= 1;
-                if depth == 0 {
-                    return Some(i);
+        Ok(Expression::Literal {
+            value,
+            span: token.span,
+        })
+    }
+
+    /// A malformed number is still a number: report it and keep going with a
+    /// placeholder value rather than discarding the statement.
+    fn number_value(&mut self, token: Token, negated: bool, span: Span) -> LiteralValue {
+        match lexer::number_value(self.text(token), negated) {
+            Ok(value) => value,
+            Err(message) => {
+                self.errors.push(self.error(message, span));
+                if token.kind == TokenKind::Integer {
+                    LiteralValue::Integer(0)
+                } else {
+                    LiteralValue::Number(0.0)
                 }
             }
-            _ => {}
         }
     }
-
-    None
 }
 
-fn is_valid_identifier(text: &str) -> bool {
-    if text.is_empty() {
-        return false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_clean(source: &str) -> Ast {
+        let result = parse(source);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        result.ast
+    }
+
+    #[test]
+    fn multi_line_declarations_keep_their_bodies() {
+        let ast = parse_clean(
+            "fn greet(name: string,\n         times: int) {\n    print(name)\n    print(times)\n}\n\
+             struct Point {\n    x: int\n    y: int\n}\n",
+        );
+        assert_eq!(ast.nodes.len(), 2);
+
+        let Node::FunctionDeclaration {
+            name, params, body, ..
+        } = &ast.nodes[0]
+        else {
+            panic!("expected a function, found {:?}", ast.nodes[0]);
+        };
+        assert_eq!(name, "greet");
+        assert_eq!(params.len(), 2);
+        assert_eq!(body.len(), 2);
+
+        let Node::StructDeclaration { name, fields, .. } = &ast.nodes[1] else {
+            panic!("expected a struct, found {:?}", ast.nodes[1]);
+        };
+        assert_eq!(name, "Point");
+        let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+        assert_eq!(names, vec!["x", "y"]);
     }
 
-    let mut chars = text.chars();
+    #[test]
+    fn multi_line_call_is_one_statement() {
+        let ast = parse_clean("print(\n  1,\n  2\n)\nlet x = 3");
+        assert_eq!(ast.nodes.len(), 2);
+        let Node::ExpressionStatement { expression, .. } = &ast.nodes[0] else {
+            panic!("expected a call, found {:?}", ast.nodes[0]);
+        };
+        assert!(matches!(
+            expression.as_ref(),
+            Expression::Call { arguments, .. } if arguments.len() == 2
+        ));
+    }
 
-    let first = chars.next().unwrap();
-    if !first.is_alphabetic() && first != '_' {
-        return false;
+    /// The first statement of `source` as an expression.
+    fn expression(source: &str) -> Expression {
+        let mut ast = parse_clean(source);
+        match ast.nodes.remove(0) {
+            Node::ExpressionStatement { expression, .. } => *expression,
+            node => panic!("expected an expression, found {:?}", node),
+        }
     }
 
-    for c in chars {
-        if !c.is_alphanumeric() && c != '_' {
-            return false;
+    /// `expression` with every operator application in parentheses.
+    fn grouped(expression: &Expression) -> String {
+        match expression {
+            Expression::Literal {
+                value: LiteralValue::Integer(value),
+                ..
+            } => value.to_string(),
+            Expression::Variable { name, .. } => name.clone(),
+            Expression::BinaryOperation {
+                operator,
+                left,
+                right,
+                ..
+            }
+            | Expression::Assignment {
+                operator,
+                target: left,
+                value: right,
+                ..
+            } => format!("({} {} {})", grouped(left), operator, grouped(right)),
+            Expression::UnaryOperation {
+                operator, operand, ..
+            } => format!("({}{})", operator, grouped(operand)),
+            Expression::ArrayAccess { array, index, .. } => {
+                format!("{}[{}]", grouped(array), grouped(index))
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    #[test]
+    fn binary_operators_follow_precedence() {
+        let cases = [
+            ("a + b * c", "(a + (b * c))"),
+            ("a * b + c", "((a * b) + c)"),
+            ("a - b - c", "((a - b) - c)"),
+            ("a / b % c", "((a / b) % c)"),
+            ("a < b == c > d", "((a < b) == (c > d))"),
+            ("a || b && c", "(a || (b && c))"),
+            ("a && b || c && d", "((a && b) || (c && d))"),
+            ("0..n + 1", "(0 .. (n + 1))"),
+            ("a < 0..n", "(a < (0 .. n))"),
+            ("(a + b) * c", "((a + b) * c)"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(grouped(&expression(source)), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn assignment_is_right_associative() {
+        assert_eq!(grouped(&expression("a = b = c")), "(a = (b = c))");
+        assert_eq!(grouped(&expression("a += b * 2")), "(a += (b * 2))");
+        assert_eq!(grouped(&expression("a = b || c")), "(a = (b || c))");
+    }
+
+    #[test]
+    fn unary_operators_bind_tightest() {
+        assert_eq!(grouped(&expression("!done")), "(!done)");
+        assert_eq!(grouped(&expression("-a * b")), "((-a) * b)");
+        assert_eq!(grouped(&expression("!!a && b")), "((!(!a)) && b)");
+        assert_eq!(grouped(&expression("a - -b")), "(a - (-b))");
+    }
+
+    #[test]
+    fn invalid_assignment_target_is_an_error() {
+        let result = parse("a + b = c");
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0]
+            .message
+            .contains("Invalid left-hand side in '=' assignment"));
+    }
+
+    fn messages(result: &ParseResult) -> Vec<&str> {
+        result
+            .errors
+            .iter()
+            .map(|err| err.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn statements_after_an_error_are_kept() {
+        let result = parse("let x = \nlet y = 2\nfoo(a b)\nprint(y)\n");
+        assert_eq!(
+            messages(&result),
+            vec![
+                "Expected expression, found 'let'",
+                "Expected ')', found 'b'"
+            ]
+        );
+
+        let nodes = &result.ast.nodes;
+        assert_eq!(nodes.len(), 4);
+        assert!(matches!(
+            &nodes[0],
+            Node::VariableDeclaration { name, initializer: Some(initializer), .. }
+                if name == "x" && matches!(initializer.as_ref(), Expression::Error { .. })
+        ));
+        assert!(matches!(&nodes[1], Node::VariableDeclaration { name, .. } if name == "y"));
+        assert!(matches!(&nodes[2], Node::Error { .. }));
+        assert!(matches!(&nodes[3], Node::ExpressionStatement { .. }));
+    }
+
+    #[test]
+    fn unclosed_block_keeps_its_statements() {
+        let result = parse("fn f() {\n  let z = 1\n  print(z)");
+        assert_eq!(messages(&result), vec!["Expected '}', found end of file"]);
+        let Node::FunctionDeclaration { body, .. } = &result.ast.nodes[0] else {
+            panic!("expected a function, found {:?}", result.ast.nodes[0]);
+        };
+        assert_eq!(body.len(), 2);
+    }
+
+    #[test]
+    fn bad_field_keeps_the_struct() {
+        let result = parse("struct S { a: int, b: , c: string }\nlet after = 1");
+        assert_eq!(messages(&result), vec!["Expected type, found ','"]);
+        assert_eq!(result.ast.nodes.len(), 2);
+
+        let Node::StructDeclaration { fields, .. } = &result.ast.nodes[0] else {
+            panic!("expected a struct, found {:?}", result.ast.nodes[0]);
+        };
+        let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn bad_members_keep_the_class() {
+        let result = parse(
+            "class C {\n  x: = 1\n  fn (a) {}\n  fn ok() { print(1) }\n  y: int\n}\nlet after = 1",
+        );
+        assert_eq!(
+            messages(&result),
+            vec![
+                "Expected type, found '='",
+                "Expected function name, found '('"
+            ]
+        );
+        assert_eq!(result.ast.nodes.len(), 2);
+
+        let Node::ClassDeclaration {
+            methods,
+            properties,
+            ..
+        } = &result.ast.nodes[0]
+        else {
+            panic!("expected a class, found {:?}", result.ast.nodes[0]);
+        };
+        assert_eq!(methods.len(), 1);
+        let names: Vec<&str> = properties.iter().map(|field| field.name.as_str()).collect();
+        assert_eq!(names, vec!["y"]);
+    }
+
+    /// Replaces `edit` in `source` and checks that reparsing from the
+    /// previous result gives exactly what a full parse does.
+    fn assert_reparse_matches(source: &str, edit: Span, inserted: &str) {
+        let mut edited = source.to_string();
+        edited.replace_range(edit.start..edit.end, inserted);
+
+        let incremental = reparse(parse(source), &edited, edit, inserted.len());
+        let full = parse(&edited);
+        let context = format!("{:?} with {:?} replaced by {:?}", source, edit, inserted);
+        assert_eq!(incremental.cst.text(), edited, "{}", context);
+        assert_eq!(incremental.cst, full.cst, "{}", context);
+        assert_eq!(
+            format!("{:?}", incremental.ast),
+            format!("{:?}", full.ast),
+            "{}",
+            context
+        );
+        assert_eq!(
+            format!("{:?}", incremental.errors),
+            format!("{:?}", full.errors),
+            "{}",
+            context
+        );
+    }
+
+    #[test]
+    fn reparse_matches_a_full_parse() {
+        let source = "import {a} from \"x\"\nlet x = 1\n// c\nfn f(a: int) { print(a) }\n\n\
+                      struct S { a: int }\nlet y = x + 2\n";
+        let edits = [
+            (0, 0, "let z = 3\n"),
+            (26, 27, "42"),
+            (30, 30, "fn g() {"),
+            (source.len(), source.len(), "foo()"),
+            (10, 20, ""),
+            (40, 45, "\n\n"),
+            (0, source.len(), "x"),
+            (35, 35, "\"unterminated"),
+        ];
+        for (start, end, inserted) in edits {
+            assert_reparse_matches(source, Span::new(start, end), inserted);
         }
     }
 
-    true
+    #[test]
+    fn reparse_keeps_errors_reported_next_to_their_item() {
+        // The stray `}` is reported at the end of the line before it.
+        let source = "let a = 1\nlet b = 2\n}\nlet c = 3\nlet d = 4\n";
+        assert_reparse_matches(source, Span::new(8, 9), "5");
+        assert_reparse_matches(source, Span::new(18, 19), "6");
+        assert_reparse_matches(source, Span::new(38, 39), "7");
+    }
+
+    #[test]
+    fn reparse_matches_a_full_parse_for_random_edits() {
+        let sources = [
+            "let a = 1\nfn f(x: int): int {\n  return x * 2\n}\n}\nstruct S { a: int, b }\nlet c = f(a)\n",
+            "class C {\n  n: string\n  fn m() { print(n) }\n}\nlet o = { x: 1 }\nif o { } else { }\n",
+            "let x = \nlet y = 2\nfoo(a b)\nwhile x < 3 { x += 1 }\n/* c */ let s = \"a ${x} b\"\n",
+        ];
+        let pieces = [
+            "", "x", " ", "\n", "}", "{", "(", ")", "let ", "fn g() ", "\"", "/*", "1", ";", ",",
+        ];
+
+        // A small linear congruential generator keeps the test deterministic.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: usize| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 33) as usize % bound
+        };
+
+        for _ in 0..3000 {
+            let source = sources[next(sources.len())];
+            let start = next(source.len() + 1);
+            let end = (start + next(4)).min(source.len());
+            let inserted = pieces[next(pieces.len())];
+            assert_reparse_matches(source, Span::new(start, end), inserted);
+        }
+    }
+
+    #[test]
+    fn class_members_and_methods() {
+        let ast = parse_clean(
+            "class Counter {
+  const label: string
+  count: int = 0
+
+  fn add(by: int, again: bool): int {
+    return by
+  }
+  fn reset() {}
+}",
+        );
+        let Node::ClassDeclaration {
+            name,
+            methods,
+            properties,
+            ..
+        } = &ast.nodes[0]
+        else {
+            panic!("expected a class, found {:?}", ast.nodes[0]);
+        };
+        assert_eq!(name, "Counter");
+
+        let fields: Vec<(&str, bool, bool)> = properties
+            .iter()
+            .map(|field| {
+                (
+                    field.name.as_str(),
+                    field.is_mutable,
+                    field.initializer.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(fields, vec![("label", false, false), ("count", true, true)]);
+
+        let Node::FunctionDeclaration {
+            name,
+            params,
+            return_type,
+            body,
+            ..
+        } = &methods[0]
+        else {
+            panic!("expected a method, found {:?}", methods[0]);
+        };
+        assert_eq!(name, "add");
+        let params: Vec<String> = params
+            .iter()
+            .map(|param| format!("{}: {}", param.name, param.typ.as_ref().unwrap()))
+            .collect();
+        assert_eq!(params, vec!["by: int", "again: bool"]);
+        assert_eq!(return_type.as_ref().unwrap().to_string(), "int");
+        assert_eq!(body.len(), 1);
+        assert!(matches!(&methods[1], Node::FunctionDeclaration { name, .. } if name == "reset"));
+    }
+
+    /// The statement kinds of `nodes`, with nested bodies in brackets.
+    fn outline(nodes: &[Node]) -> String {
+        let body = |nodes: &[Node]| format!("[{}]", outline(nodes));
+        nodes
+            .iter()
+            .map(|node| match node {
+                Node::IfStatement {
+                    then_branch,
+                    else_branch,
+                    ..
+                } => match else_branch {
+                    Some(else_branch) => {
+                        format!("if{} else{}", body(then_branch), body(else_branch))
+                    }
+                    None => format!("if{}", body(then_branch)),
+                },
+                Node::WhileStatement { body: nodes, .. } => format!("while{}", body(nodes)),
+                Node::ForStatement {
+                    initializer,
+                    condition,
+                    increment,
+                    body: nodes,
+                    ..
+                } => format!(
+                    "for({} {} {}){}",
+                    initializer.is_some(),
+                    condition.is_some(),
+                    increment.is_some(),
+                    body(nodes)
+                ),
+                Node::ForInStatement {
+                    variable,
+                    body: nodes,
+                    ..
+                } => format!("for {} in{}", variable, body(nodes)),
+                Node::Block { statements, .. } => body(statements),
+                Node::ReturnStatement { expression, .. } => match expression {
+                    Some(_) => "return value".to_string(),
+                    None => "return".to_string(),
+                },
+                Node::BreakStatement { .. } => "break".to_string(),
+                Node::ContinueStatement { .. } => "continue".to_string(),
+                Node::ExpressionStatement { .. } => "expr".to_string(),
+                Node::VariableDeclaration { .. } => "let".to_string(),
+                other => panic!("unexpected statement {:?}", other),
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    #[test]
+    fn control_flow_statements() {
+        let cases = [
+            ("if a { b() }", "if[expr]"),
+            (
+                "if a { return } else if b { return 1 } else { }",
+                "if[return] else[if[return value] else[]]",
+            ),
+            ("while x < 3 { x += 1\n continue }", "while[expr; continue]"),
+            (
+                "for (let i = 0; i < n; i += 1) { break }",
+                "for(true true true)[break]",
+            ),
+            ("for ;; { }", "for(false false false)[]"),
+            ("for (item in items) { print(item) }", "for item in[expr]"),
+            ("for c in \"abc\" { }", "for c in[]"),
+            ("{ let a = 1\n { a } }", "[let; [expr]]"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(outline(&parse_clean(source).nodes), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn return_value_must_be_on_the_same_line() {
+        let ast = parse_clean("fn f() {\n  return\n  1\n}");
+        let Node::FunctionDeclaration { body, .. } = &ast.nodes[0] else {
+            panic!("expected a function, found {:?}", ast.nodes[0]);
+        };
+        assert_eq!(outline(body), "return; expr");
+    }
+
+    /// The annotation of `let x: <source>`.
+    fn annotation(source: &str) -> Type {
+        let mut ast = parse_clean(&format!("let x: {}", source));
+        match ast.nodes.remove(0) {
+            Node::VariableDeclaration {
+                data_type: Some(typ),
+                ..
+            } => typ,
+            node => panic!("expected an annotated declaration, found {:?}", node),
+        }
+    }
+
+    #[test]
+    fn type_annotations_print_back_the_same() {
+        let cases = [
+            ("int", "int"),
+            ("int[][]", "int[][]"),
+            ("string?", "string?"),
+            ("int | string[] | null", "int | string[] | null"),
+            ("(int | string)[]", "(int | string)[]"),
+            ("(a | b) | (c | d)", "a | b | c | d"),
+            ("fn(int, string?) -> bool", "fn(int, string?) -> bool"),
+            ("(fn() -> int)[]", "(fn() -> int)[]"),
+            ("fn() -> (int | string)", "fn() -> (int | string)"),
+            ("fn(fn(int) -> int) -> int[]", "fn(fn(int) -> int) -> int[]"),
+        ];
+        for (source, expected) in cases {
+            let typ = annotation(source);
+            assert_eq!(typ.to_string(), expected, "{}", source);
+            assert_eq!(annotation(expected).to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn function_return_type_binds_tighter_than_union() {
+        let Type::Union(members) = annotation("fn() -> int | string") else {
+            panic!("expected a union");
+        };
+        assert!(matches!(&members[0], Type::Function { .. }));
+        assert!(matches!(&members[1], Type::Basic { name, .. } if name == "string"));
+        assert!(
+            matches!(annotation("int?[]"), Type::Array(inner) if matches!(*inner, Type::Optional(_)))
+        );
+    }
+
+    #[test]
+    fn bad_type_annotation_is_an_error() {
+        assert_eq!(
+            messages(&parse("let x: int[ = 1")),
+            vec!["Expected ']', found '='"]
+        );
+        assert_eq!(
+            messages(&parse("let f: fn(int) int = g")),
+            vec!["Expected '->', found 'int'"]
+        );
+    }
+
+    #[test]
+    fn bracket_on_a_new_line_starts_a_statement() {
+        let ast = parse_clean("let a = b\n(c)\nlet d = e\n[1, 2]\nf\n  .g(1)[0]\n");
+        assert_eq!(ast.nodes.len(), 5);
+        assert!(matches!(
+            &ast.nodes[0],
+            Node::VariableDeclaration { initializer: Some(value), .. }
+                if matches!(value.as_ref(), Expression::Variable { .. })
+        ));
+        assert!(matches!(
+            &ast.nodes[1],
+            Node::ExpressionStatement { expression, .. }
+                if matches!(expression.as_ref(), Expression::Variable { name, .. } if name == "c")
+        ));
+        assert!(matches!(
+            &ast.nodes[3],
+            Node::ExpressionStatement { expression, .. }
+                if matches!(expression.as_ref(), Expression::ArrayLiteral { .. })
+        ));
+        assert!(matches!(
+            &ast.nodes[4],
+            Node::ExpressionStatement { expression, .. }
+                if matches!(expression.as_ref(), Expression::ArrayAccess { .. })
+        ));
+    }
+
+    #[test]
+    fn literals_and_lambdas() {
+        let Expression::ArrayLiteral { elements, .. } = expression("[1, [2], {},]") else {
+            panic!("expected an array");
+        };
+        assert_eq!(elements.len(), 3);
+        assert!(
+            matches!(&elements[2], Expression::ObjectLiteral { properties, .. } if properties.is_empty())
+        );
+
+        let ast = parse_clean("let o = { x: 1, \"y z\": [], w }");
+        let Node::VariableDeclaration {
+            initializer: Some(initializer),
+            ..
+        } = &ast.nodes[0]
+        else {
+            panic!("expected a declaration, found {:?}", ast.nodes[0]);
+        };
+        let Expression::ObjectLiteral { properties, .. } = initializer.as_ref() else {
+            panic!("expected an object, found {:?}", initializer);
+        };
+        let keys: Vec<&str> = properties.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, vec!["x", "y z", "w"]);
+        assert!(
+            matches!(properties[2].value.as_ref(), Expression::Variable { name, .. } if name == "w")
+        );
+
+        let Expression::Call { arguments, .. } =
+            expression("map(xs, fn(x: int) -> int { return x * 2 })")
+        else {
+            panic!("expected a call");
+        };
+        assert!(matches!(
+            &arguments[1],
+            Expression::Lambda { params, return_type: Some(_), body, .. }
+                if params.len() == 1 && body.len() == 1
+        ));
+
+        assert_eq!(grouped(&expression("a[i + 1]")), "a[(i + 1)]");
+    }
+
+    /// The literal pieces of a string expression, with interpolations shown
+    /// as `{}`.
+    fn string_parts(expression: &Expression) -> Vec<String> {
+        let parts = match expression {
+            Expression::InterpolatedString { parts, .. } => parts.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        parts
+            .iter()
+            .map(|part| match part {
+                Expression::Literal {
+                    value: LiteralValue::String(text),
+                    ..
+                } => text.clone(),
+                _ => "{}".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn string_escapes_are_decoded() {
+        assert_eq!(
+            string_parts(&expression(r#""a\tb\n\"q\" \\ \$x 'c'""#)),
+            vec!["a\tb\n\"q\" \\ $x 'c'"]
+        );
+        assert_eq!(string_parts(&expression(r#"'it\'s'"#)), vec!["it's"]);
+        assert_eq!(string_parts(&expression(r#""""#)), vec![""]);
+    }
+
+    #[test]
+    fn lone_interpolation_is_a_string() {
+        let string = expression(r#""${1}""#);
+        assert!(
+            matches!(&string, Expression::InterpolatedString { parts, .. } if parts.len() == 1),
+            "{:?}",
+            string
+        );
+    }
+
+    #[test]
+    fn interpolations_are_parsed_in_place() {
+        let source = r#""sum: ${a + b}!${f("}")}""#;
+        let string = expression(source);
+        assert_eq!(string_parts(&string), vec!["sum: ", "{}", "!", "{}"]);
+
+        let Expression::InterpolatedString { parts, .. } = &string else {
+            panic!("expected an interpolated string, found {:?}", string);
+        };
+        let span = parts[1].span();
+        assert_eq!(&source[span.start..span.end], "a + b");
+        assert_eq!(grouped(&parts[1]), "(a + b)");
+        assert!(matches!(&parts[3], Expression::Call { .. }));
+    }
+
+    #[test]
+    fn string_errors_point_at_the_problem() {
+        let source = "let s = \"a\\qb\"";
+        let result = parse(source);
+        assert_eq!(messages(&result), vec!["Unknown escape sequence '\\q'"]);
+        let span = result.errors[0].span;
+        assert_eq!(&source[span.start..span.end], "\\q");
+
+        let cases = [
+            ("let s = \"abc\nlet t = 1", "Unterminated string literal"),
+            (
+                "let s = \"${a\nlet t = 1",
+                "Unterminated string interpolation, expected '}'",
+            ),
+            (
+                "let s = \"${ }\"",
+                "Expected expression in string interpolation",
+            ),
+            ("let s = \"${a b}\"", "Expected '}', found 'b'"),
+            ("let s = 1 /* open", "Unterminated block comment"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(messages(&parse(source)), vec![expected], "{}", source);
+        }
+    }
+
+    #[test]
+    fn missing_property_name_keeps_the_access() {
+        let result = parse("a.b.\nlet x = 1");
+        assert_eq!(
+            messages(&result),
+            vec!["Expected property name, found 'let'"]
+        );
+        match &result.ast.nodes[..] {
+            [Node::ExpressionStatement { expression, .. }, Node::VariableDeclaration { .. }] => {
+                match &**expression {
+                    Expression::PropertyAccess {
+                        object,
+                        property,
+                        span,
+                    } => {
+                        assert!(matches!(
+                            &**object,
+                            Expression::PropertyAccess { property, .. } if property == "b"
+                        ));
+                        assert_eq!(property, "");
+                        assert_eq!(*span, Span::new(0, 4));
+                    }
+                    expression => panic!("expected a property access, found {:?}", expression),
+                }
+            }
+            nodes => panic!("unexpected nodes {:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn negative_number_literals_are_folded() {
+        assert!(matches!(
+            expression("-9223372036854775808"),
+            Expression::Literal {
+                value: LiteralValue::Integer(i64::MIN),
+                span: Span { start: 0, end: 20 },
+            }
+        ));
+        assert_eq!(grouped(&expression("-1 - -2 * 3")), "(-1 - (-2 * 3))");
+        assert_eq!(grouped(&expression("--1")), "(--1)");
+        assert_eq!(grouped(&expression("-a[0]")), "(-a[0])");
+        assert!(matches!(
+            expression("-1.5.floor()"),
+            Expression::UnaryOperation { .. }
+        ));
+        assert_eq!(
+            messages(&parse(
+                "let a = -9223372036854775809\nlet b = 9223372036854775808"
+            )),
+            vec![
+                "Integer literal '-9223372036854775809' does not fit in 64 bits",
+                "Integer literal '9223372036854775808' does not fit in 64 bits"
+            ]
+        );
+        let result = parse("let a = -0x8000_0000_0000_0001");
+        assert_eq!(result.errors[0].span, Span::new(8, 30));
+    }
 }
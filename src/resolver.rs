@@ -0,0 +1,854 @@
+use std::collections::HashMap;
+
+use crate::ast::{Ast, Expression, Node, Parameter, Span, StructField, Type};
+use crate::types::Ty;
+
+pub type ScopeId = usize;
+pub type SymbolId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+    File,
+    Function,
+    Class,
+    Block,
+    Loop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclarationKind {
+    Variable,
+    Parameter,
+    Function,
+    Struct,
+    Class,
+    Property,
+    Method,
+    Import,
+    LoopVariable,
+    /// The implicit `this` of a method, declared at the name of its class.
+    This,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub kind: ScopeKind,
+    pub parent: Option<ScopeId>,
+    pub span: Span,
+    names: HashMap<String, SymbolId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: DeclarationKind,
+    pub name_span: Span,
+    /// The whole declaration, e.g. the full `let` statement.
+    pub span: Span,
+    pub scope: ScopeId,
+    /// The type written in the source, if any.
+    pub typ: Option<Type>,
+    /// The semantic type, filled in by `inference::infer_types`.
+    pub ty: Ty,
+    pub is_mutable: bool,
+}
+
+/// A use of a name in an expression or a type annotation and the declaration
+/// it resolved to, if any. Unresolved references may still name a builtin.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub name: String,
+    pub span: Span,
+    pub scope: ScopeId,
+    pub symbol: Option<SymbolId>,
+    /// Whether the name is called directly, as in `name(...)`.
+    pub is_callee: bool,
+}
+
+/// Lexical scopes of one file with every declaration and every variable
+/// reference bound to the declaration it names.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    /// The document resolved, which owns the struct and class types.
+    pub uri: String,
+    pub scopes: Vec<Scope>,
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+}
+
+impl Resolution {
+    /// The symbol declared or referenced at `offset`. A cursor placed right
+    /// after an identifier still counts as being on it.
+    pub fn symbol_at(&self, offset: usize) -> Option<SymbolId> {
+        let on = |span: Span| span.start <= offset && offset <= span.end;
+
+        self.references
+            .iter()
+            .find(|reference| on(reference.span))
+            .and_then(|reference| reference.symbol)
+            .or_else(|| self.symbols.iter().position(|symbol| on(symbol.name_span)))
+    }
+
+    /// Spans of every reference bound to `symbol`, in source order.
+    pub fn references_to(&self, symbol: SymbolId) -> Vec<Span> {
+        let mut spans: Vec<Span> = self
+            .references
+            .iter()
+            .filter(|reference| reference.symbol == Some(symbol))
+            .map(|reference| reference.span)
+            .collect();
+        spans.sort_by_key(|span| span.start);
+        spans
+    }
+
+    /// The innermost scope containing `offset`.
+    pub fn scope_at(&self, offset: usize) -> ScopeId {
+        self.scopes
+            .iter()
+            .enumerate()
+            .filter(|(_, scope)| scope.span.start <= offset && offset <= scope.span.end)
+            .min_by_key(|(_, scope)| scope.span.len())
+            .map_or(0, |(id, _)| id)
+    }
+
+    /// Every symbol visible from `scope`, innermost first. Shadowed names
+    /// only appear once.
+    pub fn visible_symbols(&self, scope: ScopeId) -> Vec<SymbolId> {
+        let mut seen = HashMap::new();
+        let mut current = Some(scope);
+
+        while let Some(id) = current {
+            let scope = &self.scopes[id];
+            for (name, &symbol) in &scope.names {
+                seen.entry(name.as_str()).or_insert(symbol);
+            }
+            current = scope.parent;
+        }
+
+        let mut symbols: Vec<SymbolId> = seen.into_values().collect();
+        symbols.sort_unstable();
+        symbols
+    }
+
+    /// The properties and methods declared in the body of the struct or
+    /// class `symbol`, in source order.
+    pub fn members(&self, symbol: SymbolId) -> Vec<SymbolId> {
+        let declaration = &self.symbols[symbol];
+        let Some(body) = self.scopes.iter().position(|scope| {
+            scope.kind == ScopeKind::Class
+                && scope.parent == Some(declaration.scope)
+                && scope.span == declaration.span
+        }) else {
+            return Vec::new();
+        };
+
+        let mut members: Vec<SymbolId> = (0..self.symbols.len())
+            .filter(|&member| self.symbols[member].scope == body)
+            .collect();
+        members.sort_by_key(|&member| self.symbols[member].name_span.start);
+        members
+    }
+
+    /// The struct or class declaring the type `ty`.
+    pub fn type_declaration(&self, ty: Ty) -> Option<SymbolId> {
+        self.symbols.iter().position(|symbol| {
+            matches!(
+                symbol.kind,
+                DeclarationKind::Struct | DeclarationKind::Class
+            ) && symbol.ty == ty
+        })
+    }
+
+    /// The member called `name` of the struct or class type `ty`.
+    pub fn member(&self, ty: Ty, name: &str) -> Option<SymbolId> {
+        self.members(self.type_declaration(ty)?)
+            .into_iter()
+            .find(|&member| self.symbols[member].name == name)
+    }
+}
+
+pub fn resolve(ast: &Ast, uri: &str) -> Resolution {
+    let mut resolver = Resolver {
+        resolution: Resolution {
+            uri: uri.to_string(),
+            scopes: Vec::new(),
+            symbols: Vec::new(),
+            references: Vec::new(),
+        },
+        current: 0,
+    };
+    // The file scope covers everything, including trailing whitespace.
+    resolver.push_scope(ScopeKind::File, Span::new(0, usize::MAX));
+    resolver.resolve_statements(&ast.nodes);
+    resolver.resolution
+}
+
+struct Resolver {
+    resolution: Resolution,
+    current: ScopeId,
+}
+
+impl Resolver {
+    fn push_scope(&mut self, kind: ScopeKind, span: Span) {
+        let parent = if self.resolution.scopes.is_empty() {
+            None
+        } else {
+            Some(self.current)
+        };
+        self.resolution.scopes.push(Scope {
+            kind,
+            parent,
+            span,
+            names: HashMap::new(),
+        });
+        self.current = self.resolution.scopes.len() - 1;
+    }
+
+    fn pop_scope(&mut self) {
+        if let Some(parent) = self.resolution.scopes[self.current].parent {
+            self.current = parent;
+        }
+    }
+
+    fn declare(
+        &mut self,
+        name: &str,
+        kind: DeclarationKind,
+        name_span: Span,
+        span: Span,
+        typ: Option<Type>,
+        is_mutable: bool,
+    ) -> SymbolId {
+        let id = self.resolution.symbols.len();
+        self.resolution.symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            name_span,
+            span,
+            scope: self.current,
+            typ,
+            ty: Ty::ANY,
+            is_mutable,
+        });
+        self.resolution.scopes[self.current]
+            .names
+            .insert(name.to_string(), id);
+        id
+    }
+
+    fn lookup(&self, name: &str) -> Option<SymbolId> {
+        let mut current = Some(self.current);
+        while let Some(id) = current {
+            let scope = &self.resolution.scopes[id];
+            if let Some(&symbol) = scope.names.get(name) {
+                return Some(symbol);
+            }
+            current = scope.parent;
+        }
+        None
+    }
+
+    fn reference(&mut self, name: &str, span: Span, is_callee: bool) {
+        let symbol = self.lookup(name);
+        self.resolution.references.push(Reference {
+            name: name.to_string(),
+            span,
+            scope: self.current,
+            symbol,
+            is_callee,
+        });
+    }
+
+    /// Records the struct, class and imported names an annotation uses. Other
+    /// names are builtin types, or unknown ones that become named types, and
+    /// are not references.
+    fn reference_type(&mut self, typ: &Type) {
+        match typ {
+            Type::Basic { name, span } => {
+                let names_type = self.lookup(name).is_some_and(|symbol| {
+                    matches!(
+                        self.resolution.symbols[symbol].kind,
+                        DeclarationKind::Struct | DeclarationKind::Class | DeclarationKind::Import
+                    )
+                });
+                if names_type {
+                    self.reference(name, *span, false);
+                }
+            }
+            Type::Array(typ) | Type::Optional(typ) => self.reference_type(typ),
+            Type::Function {
+                params,
+                return_type,
+            } => {
+                for param in params {
+                    self.reference_type(param);
+                }
+                self.reference_type(return_type);
+            }
+            Type::Union(types) => {
+                for typ in types {
+                    self.reference_type(typ);
+                }
+            }
+        }
+    }
+
+    /// Resolves a statement list in the current scope. Functions, types and
+    /// imports are visible throughout the list; variables only after their
+    /// declaration. Function bodies are resolved last, so they can use
+    /// variables declared further down in the enclosing scope.
+    fn resolve_statements(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            self.hoist(node);
+        }
+
+        let mut deferred = Vec::new();
+        for node in nodes {
+            match node {
+                Node::FunctionDeclaration {
+                    params,
+                    return_type,
+                    body,
+                    span,
+                    ..
+                } => deferred.push((params, return_type, body, *span)),
+                _ => self.resolve_node(node),
+            }
+        }
+
+        for (params, return_type, body, span) in deferred {
+            self.resolve_function(params, return_type, body, span);
+        }
+    }
+
+    fn hoist(&mut self, node: &Node) {
+        match node {
+            Node::FunctionDeclaration {
+                name,
+                name_span,
+                params,
+                return_type,
+                span,
+                ..
+            } => {
+                let typ = function_type(params, return_type);
+                self.declare(
+                    name,
+                    DeclarationKind::Function,
+                    *name_span,
+                    *span,
+                    Some(typ),
+                    false,
+                );
+            }
+            Node::StructDeclaration {
+                name,
+                name_span,
+                span,
+                ..
+            } => {
+                self.declare(
+                    name,
+                    DeclarationKind::Struct,
+                    *name_span,
+                    *span,
+                    None,
+                    false,
+                );
+            }
+            Node::ClassDeclaration {
+                name,
+                name_span,
+                span,
+                ..
+            } => {
+                self.declare(name, DeclarationKind::Class, *name_span, *span, None, false);
+            }
+            Node::ImportDeclaration {
+                imported_items,
+                span,
+                ..
+            } => {
+                for item in imported_items {
+                    self.declare(
+                        &item.name,
+                        DeclarationKind::Import,
+                        item.span,
+                        *span,
+                        None,
+                        false,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn resolve_function(
+        &mut self,
+        params: &[Parameter],
+        return_type: &Option<Type>,
+        body: &[Node],
+        span: Span,
+    ) {
+        self.push_scope(ScopeKind::Function, span);
+        self.declare_params(params, return_type);
+        self.resolve_statements(body);
+        self.pop_scope();
+    }
+
+    /// Like `resolve_function`, with `this` bound to an instance of the
+    /// class `class_name`.
+    fn resolve_method(
+        &mut self,
+        (class_name, class_span): (&str, Span),
+        params: &[Parameter],
+        return_type: &Option<Type>,
+        body: &[Node],
+        span: Span,
+    ) {
+        self.push_scope(ScopeKind::Function, span);
+        self.declare(
+            "this",
+            DeclarationKind::This,
+            class_span,
+            span,
+            Some(Type::Basic {
+                name: class_name.to_string(),
+                span: class_span,
+            }),
+            false,
+        );
+        self.declare_params(params, return_type);
+        self.resolve_statements(body);
+        self.pop_scope();
+    }
+
+    /// Declares the parameters of a function, after the names of types its
+    /// signature uses.
+    fn declare_params(&mut self, params: &[Parameter], return_type: &Option<Type>) {
+        let annotations = params.iter().filter_map(|param| param.typ.as_ref());
+        for typ in annotations.chain(return_type) {
+            self.reference_type(typ);
+        }
+        for param in params {
+            self.declare(
+                &param.name,
+                DeclarationKind::Parameter,
+                param.span,
+                param.span,
+                param.typ.clone(),
+                true,
+            );
+        }
+    }
+
+    fn declare_fields(&mut self, fields: &[StructField]) {
+        for typ in fields.iter().filter_map(|field| field.typ.as_ref()) {
+            self.reference_type(typ);
+        }
+        for field in fields {
+            self.declare(
+                &field.name,
+                DeclarationKind::Property,
+                field.name_span,
+                field.span,
+                field.typ.clone(),
+                field.is_mutable,
+            );
+        }
+    }
+
+    /// Resolves the statements of a block in a scope of its own, spanning
+    /// its braces so that blank lines inside them are in it too.
+    fn resolve_block(&mut self, kind: ScopeKind, nodes: &[Node], span: Span) {
+        self.push_scope(kind, span);
+        self.resolve_statements(nodes);
+        self.pop_scope();
+    }
+
+    fn resolve_node(&mut self, node: &Node) {
+        match node {
+            Node::VariableDeclaration {
+                name,
+                name_span,
+                initializer,
+                data_type,
+                is_mutable,
+                span,
+            } => {
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                if let Some(data_type) = data_type {
+                    self.reference_type(data_type);
+                }
+                self.declare(
+                    name,
+                    DeclarationKind::Variable,
+                    *name_span,
+                    *span,
+                    data_type.clone(),
+                    *is_mutable,
+                );
+            }
+            Node::FunctionDeclaration {
+                params,
+                return_type,
+                body,
+                span,
+                ..
+            } => self.resolve_function(params, return_type, body, *span),
+            Node::StructDeclaration { fields, span, .. } => {
+                self.push_scope(ScopeKind::Class, *span);
+                self.declare_fields(fields);
+                for field in fields {
+                    if let Some(initializer) = &field.initializer {
+                        self.resolve_expression(initializer);
+                    }
+                }
+                self.pop_scope();
+            }
+            Node::ClassDeclaration {
+                name,
+                name_span,
+                methods,
+                properties,
+                span,
+            } => {
+                self.push_scope(ScopeKind::Class, *span);
+                self.declare_fields(properties);
+                for method in methods {
+                    if let Node::FunctionDeclaration {
+                        name,
+                        name_span,
+                        params,
+                        return_type,
+                        span,
+                        ..
+                    } = method
+                    {
+                        let typ = function_type(params, return_type);
+                        self.declare(
+                            name,
+                            DeclarationKind::Method,
+                            *name_span,
+                            *span,
+                            Some(typ),
+                            false,
+                        );
+                    }
+                }
+
+                for property in properties {
+                    if let Some(initializer) = &property.initializer {
+                        self.resolve_expression(initializer);
+                    }
+                }
+                for method in methods {
+                    if let Node::FunctionDeclaration {
+                        params,
+                        return_type,
+                        body,
+                        span,
+                        ..
+                    } = method
+                    {
+                        self.resolve_method((name, *name_span), params, return_type, body, *span);
+                    }
+                }
+                self.pop_scope();
+            }
+            Node::ImportDeclaration { .. }
+            | Node::BreakStatement { .. }
+            | Node::ContinueStatement { .. }
+            | Node::Error { .. } => {}
+            Node::ExpressionStatement { expression, .. } => self.resolve_expression(expression),
+            Node::ReturnStatement { expression, .. } => {
+                if let Some(expression) = expression {
+                    self.resolve_expression(expression);
+                }
+            }
+            Node::IfStatement {
+                condition,
+                then_branch,
+                then_span,
+                else_branch,
+                else_span,
+                ..
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_block(ScopeKind::Block, then_branch, *then_span);
+                if let (Some(else_branch), Some(else_span)) = (else_branch, else_span) {
+                    self.resolve_block(ScopeKind::Block, else_branch, *else_span);
+                }
+            }
+            Node::WhileStatement {
+                condition,
+                body,
+                body_span,
+                ..
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_block(ScopeKind::Loop, body, *body_span);
+            }
+            Node::ForStatement {
+                initializer,
+                condition,
+                increment,
+                body,
+                span,
+            } => {
+                self.push_scope(ScopeKind::Loop, *span);
+                if let Some(initializer) = initializer {
+                    self.resolve_node(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.resolve_expression(condition);
+                }
+                if let Some(increment) = increment {
+                    self.resolve_expression(increment);
+                }
+                self.resolve_statements(body);
+                self.pop_scope();
+            }
+            Node::ForInStatement {
+                variable,
+                variable_span,
+                iterable,
+                body,
+                span,
+            } => {
+                self.resolve_expression(iterable);
+                self.push_scope(ScopeKind::Loop, *span);
+                self.declare(
+                    variable,
+                    DeclarationKind::LoopVariable,
+                    *variable_span,
+                    *variable_span,
+                    None,
+                    true,
+                );
+                self.resolve_statements(body);
+                self.pop_scope();
+            }
+            Node::Block { statements, span } => {
+                self.resolve_block(ScopeKind::Block, statements, *span)
+            }
+        }
+    }
+
+    fn resolve_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Literal { .. } | Expression::Error { .. } => {}
+            Expression::Variable { name, span } => self.reference(name, *span, false),
+            Expression::BinaryOperation { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expression::UnaryOperation { operand, .. } => self.resolve_expression(operand),
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                match callee.as_ref() {
+                    Expression::Variable { name, span } => self.reference(name, *span, true),
+                    callee => self.resolve_expression(callee),
+                }
+                for argument in arguments {
+                    self.resolve_expression(argument);
+                }
+            }
+            Expression::PropertyAccess { object, .. } => self.resolve_expression(object),
+            Expression::ArrayAccess { array, index, .. } => {
+                self.resolve_expression(array);
+                self.resolve_expression(index);
+            }
+            Expression::Assignment { target, value, .. } => {
+                self.resolve_expression(target);
+                self.resolve_expression(value);
+            }
+            Expression::ArrayLiteral { elements, .. }
+            | Expression::InterpolatedString {
+                parts: elements, ..
+            } => {
+                for element in elements {
+                    self.resolve_expression(element);
+                }
+            }
+            Expression::ObjectLiteral { properties, .. } => {
+                for property in properties {
+                    self.resolve_expression(&property.value);
+                }
+            }
+            Expression::Lambda {
+                params,
+                body,
+                return_type,
+                span,
+            } => self.resolve_function(params, return_type, body, *span),
+        }
+    }
+}
+
+/// The declared type of a function; missing parameter types are `any`. A
+/// missing return type is `never` until inference joins what the body
+/// returns, so a recursive call adds nothing to it.
+fn function_type(params: &[Parameter], return_type: &Option<Type>) -> Type {
+    Type::Function {
+        params: params
+            .iter()
+            .map(|param| {
+                param.typ.clone().unwrap_or_else(|| Type::Basic {
+                    name: "any".to_string(),
+                    span: Span::default(),
+                })
+            })
+            .collect(),
+        return_type: Box::new(return_type.clone().unwrap_or_else(|| Type::Basic {
+            name: "never".to_string(),
+            span: Span::default(),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// For every reference in `source`, the name and the line its
+    /// declaration is on, or `None` when it does not resolve.
+    fn bindings(source: &str) -> Vec<(String, Option<usize>)> {
+        let resolution = resolve(&parser::parse(source).ast, "file:///resolver.bn");
+        let mut references = resolution.references.clone();
+        references.sort_by_key(|reference| reference.span.start);
+        references
+            .into_iter()
+            .map(|reference| {
+                let line = reference.symbol.map(|symbol| {
+                    let start = resolution.symbols[symbol].name_span.start;
+                    source[..start].matches('\n').count()
+                });
+                (reference.name, line)
+            })
+            .collect()
+    }
+
+    fn binding(name: &str, line: Option<usize>) -> (String, Option<usize>) {
+        (name.to_string(), line)
+    }
+
+    #[test]
+    fn inner_declarations_shadow_outer_ones() {
+        let source = "let x = 1\n\
+                      fn f(x) {\n\
+                      print(x)\n\
+                      if x { let x = 2\n\
+                      print(x) }\n\
+                      print(x)\n\
+                      }\n\
+                      print(x)";
+        assert_eq!(
+            bindings(source),
+            vec![
+                binding("print", None),
+                binding("x", Some(1)),
+                binding("x", Some(1)),
+                binding("print", None),
+                binding("x", Some(3)),
+                binding("print", None),
+                binding("x", Some(1)),
+                binding("print", None),
+                binding("x", Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn variables_are_visible_after_their_declaration_only() {
+        let source = "print(a)\nlet a = a\n{ let b = 1 }\nprint(a, b)";
+        assert_eq!(
+            bindings(source),
+            vec![
+                binding("print", None),
+                binding("a", None),
+                binding("a", None),
+                binding("print", None),
+                binding("a", Some(1)),
+                binding("b", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_and_types_are_hoisted() {
+        let source = "let p = g()\n\
+                      fn g() { return h(later) }\n\
+                      fn h(v) { return Point }\n\
+                      struct Point { x: int }\n\
+                      let later = 1";
+        assert_eq!(
+            bindings(source),
+            vec![
+                binding("g", Some(1)),
+                binding("h", Some(2)),
+                binding("later", Some(4)),
+                binding("Point", Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_variables_stay_in_the_loop() {
+        let source = "for i in items { print(i) }\n\
+                      for (let j = 0; j < 3; j += 1) { }\n\
+                      print(i, j)";
+        let unresolved: Vec<String> = bindings(source)
+            .into_iter()
+            .filter(|(name, line)| line.is_none() && name != "print")
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(unresolved, vec!["items", "i", "j"]);
+    }
+
+    #[test]
+    fn references_to_a_symbol_in_source_order() {
+        let source = "let n = 1\nfn f() { return n }\nn = n + 1";
+        let resolution = resolve(&parser::parse(source).ast, "file:///resolver.bn");
+        let n = resolution.symbol_at(4).unwrap();
+        let starts: Vec<usize> = resolution
+            .references_to(n)
+            .iter()
+            .map(|span| span.start)
+            .collect();
+        assert_eq!(starts, vec![26, 30, 34]);
+        assert_eq!(resolution.symbol_at(30), Some(n));
+    }
+
+    #[test]
+    fn blocks_cover_their_braces() {
+        let source = "fn f(a) {\n  let b = 1\n  if a {\n    let c = 2\n\n  } else {}\n  while a {\n    \n    let d = 3\n  }\n}";
+        let resolution = resolve(&parser::parse(source).ast, "file:///scopes.bn");
+        let scope_at = |marker: &str| {
+            let scope = resolution.scope_at(source.find(marker).unwrap() + 1);
+            let mut names: Vec<&str> = resolution
+                .visible_symbols(scope)
+                .into_iter()
+                .map(|symbol| resolution.symbols[symbol].name.as_str())
+                .collect();
+            names.sort();
+            (resolution.scopes[scope].kind, names)
+        };
+
+        assert_eq!(
+            scope_at("\n\n  }"),
+            (ScopeKind::Block, vec!["a", "b", "c", "f"])
+        );
+        assert_eq!(scope_at("{}"), (ScopeKind::Block, vec!["a", "b", "f"]));
+        assert_eq!(
+            scope_at("{\n    \n"),
+            (ScopeKind::Loop, vec!["a", "b", "d", "f"])
+        );
+        assert_eq!(scope_at("1\n"), (ScopeKind::Function, vec!["a", "b", "f"]));
+    }
+}
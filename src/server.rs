@@ -3,7 +3,7 @@ use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
 
-use crate::analyzer::{BurnAnalyzer, DocumentSymbol as BurnDocumentSymbol, SymbolType};
+use crate::analyzer::{BurnAnalyzer, SymbolType};
 use crate::typechecker;
 use crate::utils;
 use log::{error, info};
@@ -33,7 +33,7 @@ impl BurnLanguageServer {
         let uri_str = uri.to_string();
 
         let diagnostics = match self.document_map.get(&uri_str) {
-            Some(document) => {
+            Some(_) => {
                 // Use analyzer to get diagnostics
                 let errors = self.analyzer.analyze_document(&uri_str);
 
@@ -41,16 +41,7 @@ impl BurnLanguageServer {
                 errors
                     .iter()
                     .map(|err| Diagnostic {
-                        range: Range {
-                            start: Position {
-                                line: err.line as u32,
-                                character: err.column as u32,
-                            },
-                            end: Position {
-                                line: err.line as u32,
-                                character: (err.column + err.length) as u32,
-                            },
-                        },
+                        range: err.range,
                         severity: Some(match err.error_type {
                             crate::analyzer::ErrorType::ParseError => DiagnosticSeverity::ERROR,
                             crate::analyzer::ErrorType::TypeError => DiagnosticSeverity::ERROR,
@@ -60,6 +51,7 @@ impl BurnLanguageServer {
                         }),
                         message: err.message.clone(),
                         source: Some("burn-analyzer".to_string()),
+                        tags: (!err.tags.is_empty()).then(|| err.tags.clone()),
                         ..Diagnostic::default()
                     })
                     .collect()
@@ -100,7 +92,9 @@ impl LanguageServer for BurnLanguageServer {
         }
 
         let capabilities = ServerCapabilities {
-            text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                TextDocumentSyncKind::INCREMENTAL,
+            )),
             hover_provider: Some(HoverProviderCapability::Simple(true)),
             completion_provider: Some(CompletionOptions {
                 resolve_provider: Some(true),
@@ -108,6 +102,7 @@ impl LanguageServer for BurnLanguageServer {
                 ..CompletionOptions::default()
             }),
             definition_provider: Some(OneOf::Left(true)),
+            references_provider: Some(OneOf::Left(true)),
             document_symbol_provider: Some(OneOf::Left(true)),
             code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
             document_formatting_provider: Some(OneOf::Left(true)),
@@ -147,10 +142,10 @@ impl LanguageServer for BurnLanguageServer {
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        if let Some(change) = params.content_changes.last() {
-            let uri = params.text_document.uri.to_string();
-            self.document_map.insert(uri.clone(), change.text.clone());
-            self.analyzer.open_document(&uri, change.text.clone());
+        let uri = params.text_document.uri.to_string();
+
+        if let Some(content) = self.analyzer.change_document(&uri, params.content_changes) {
+            self.document_map.insert(uri, content);
 
             if let Err(e) = self.validate_document(&params.text_document.uri).await {
                 error!("Error validating document: {:?}", e);
@@ -178,7 +173,7 @@ impl LanguageServer for BurnLanguageServer {
         let position = params.text_document_position_params.position;
 
         if let Some(document) = self.analyzer.get_document(&uri) {
-            return crate::hover::on_hover(&document.content, position, &self.type_checker);
+            return crate::hover::on_hover(&document, position, &self.type_checker);
         }
 
         Ok(None)
@@ -191,11 +186,7 @@ impl LanguageServer for BurnLanguageServer {
         if let Some(document) = self.analyzer.get_document(&uri) {
             // Call completion resolver here
             return Ok(Some(CompletionResponse::Array(
-                crate::typechecker::get_completions(
-                    &document.content,
-                    position,
-                    &self.type_checker,
-                ),
+                crate::typechecker::get_completions(&document, position, &self.type_checker),
             )));
         }
 
@@ -230,16 +221,7 @@ impl LanguageServer for BurnLanguageServer {
                         .uri
                         .clone()
                 }),
-                range: Range {
-                    start: Position {
-                        line: definition.line as u32,
-                        character: definition.character as u32,
-                    },
-                    end: Position {
-                        line: definition.line as u32,
-                        character: definition.character as u32 + 1,
-                    },
-                },
+                range: definition.range,
             };
 
             return Ok(Some(GotoDefinitionResponse::Scalar(location)));
@@ -248,6 +230,32 @@ impl LanguageServer for BurnLanguageServer {
         Ok(None)
     }
 
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        let locations: Vec<Location> = self
+            .analyzer
+            .find_references(
+                uri.as_str(),
+                position.line as usize,
+                position.character as usize,
+                params.context.include_declaration,
+            )
+            .into_iter()
+            .map(|range| Location {
+                uri: uri.clone(),
+                range,
+            })
+            .collect();
+
+        if locations.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(locations))
+    }
+
     async fn document_symbol(
         &self,
         params: DocumentSymbolParams,
@@ -265,25 +273,17 @@ impl LanguageServer for BurnLanguageServer {
         for symbol in burn_symbols {
             let location = Location {
                 uri: params.text_document.uri.clone(),
-                range: Range {
-                    start: Position {
-                        line: symbol.line as u32,
-                        character: symbol.character as u32,
-                    },
-                    end: Position {
-                        line: symbol.line as u32,
-                        character: symbol.character as u32 + symbol.name.len() as u32,
-                    },
-                },
+                range: symbol.range,
             };
 
+            #[allow(deprecated)]
             symbols.push(SymbolInformation {
                 name: symbol.name,
                 kind: self.convert_symbol_type(symbol.symbol_type),
                 tags: None,
                 deprecated: Some(false),
                 location,
-                container_name: None,
+                container_name: symbol.container_name,
             });
         }
 
@@ -291,14 +291,37 @@ impl LanguageServer for BurnLanguageServer {
     }
 
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
-        // Placeholder for formatting implementation
-        // In the future, this would integrate with a Burn formatter
+        let uri = params.text_document.uri.to_string();
+
+        if let Some(document) = self.analyzer.get_document(&uri) {
+            // Re-indenting a file that does not parse would follow the broken
+            // brace structure, so leave it alone until it is fixed.
+            if document.parse_errors.is_empty() {
+                return Ok(Some(crate::formatter::format(
+                    &document.cst,
+                    &document.line_index,
+                    &params.options,
+                )));
+            }
+        }
 
         Ok(None)
     }
 
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
-        // Placeholder for code action implementation
+        let uri = params.text_document.uri.to_string();
+
+        if let Some(document) = self.analyzer.get_document(&uri) {
+            let actions = crate::code_actions::get_code_actions(
+                &document,
+                &params.text_document.uri,
+                params.range,
+                &params.context.diagnostics,
+            );
+            if !actions.is_empty() {
+                return Ok(Some(actions));
+            }
+        }
 
         Ok(None)
     }
@@ -1,27 +1,50 @@
-use log::{debug, error};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, Position};
 
-use crate::ast::{Ast, Expression, Type};
+use crate::analyzer::Document;
+use crate::ast::{Ast, Expression, LiteralValue, Node, Span, StructField, Type};
+use crate::cfg::ControlFlowGraph;
+use crate::inference::{self, TypeContext};
+use crate::resolver::{DeclarationKind, Resolution, SymbolId};
+use crate::types::{Ty, TyKind};
 use crate::utils;
 
 pub struct TypeErrorInfo {
     pub message: String,
-    pub line: usize,
-    pub column: usize,
-    pub length: usize,
+    pub span: Span,
+}
+
+/// Everything checking a file found: the errors to report, and the
+/// assignments to constants that quick fixes can make valid.
+pub struct TypeCheckResult {
+    pub errors: Vec<TypeErrorInfo>,
+    pub const_assignments: Vec<ConstAssignment>,
+}
+
+/// An assignment to a variable or field declared `const`.
+#[derive(Debug, Clone)]
+pub struct ConstAssignment {
+    /// The assigned name at the assignment.
+    pub span: Span,
+    pub declaration: SymbolId,
 }
 
 pub struct BurnTypeChecker {
-    variables: Mutex<HashMap<String, HashMap<String, String>>>,
+    variables: Mutex<HashMap<String, HashMap<String, Ty>>>,
 
     workspace_root: Mutex<Option<PathBuf>>,
 
     current_file: Mutex<Option<String>>,
 }
 
+impl Default for BurnTypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BurnTypeChecker {
     pub fn new() -> Self {
         BurnTypeChecker {
@@ -41,7 +64,12 @@ impl BurnTypeChecker {
         *current = Some(file_uri.to_string());
     }
 
-    pub fn check_types(&self, ast: &Ast, file_path: &str) -> Result<(), Vec<TypeErrorInfo>> {
+    pub fn check_types(
+        &self,
+        ast: &Ast,
+        resolution: &Resolution,
+        file_path: &str,
+    ) -> TypeCheckResult {
         self.set_current_file(file_path);
 
         let mut variable_types = HashMap::new();
@@ -50,188 +78,1142 @@ impl BurnTypeChecker {
         for node in &ast.nodes {
             match node {
                 crate::ast::Node::VariableDeclaration {
-                    name,
-                    data_type,
-                    line,
-                    column,
-                    ..
-                } => {
-                    let type_str = match data_type {
-                        Some(t) => t.to_string(),
-                        None => "any".to_string(),
-                    };
-                    variable_types.insert(name.clone(), type_str);
+                    name, name_span, ..
                 }
-                crate::ast::Node::FunctionDeclaration {
-                    name,
-                    params,
-                    return_type,
-                    line,
-                    column,
-                    ..
-                } => {
-                    let param_types: Vec<String> = params
-                        .iter()
-                        .map(|p| {
-                            p.typ
-                                .clone()
-                                .map_or_else(|| "any".to_string(), |t| t.to_string())
-                        })
-                        .collect();
-
-                    let return_type_str = match return_type {
-                        Some(t) => t.to_string(),
-                        None => "void".to_string(),
-                    };
-
-                    let fn_type = format!("fn({})->{}", param_types.join(", "), return_type_str);
-                    variable_types.insert(name.clone(), fn_type);
+                | crate::ast::Node::FunctionDeclaration {
+                    name, name_span, ..
                 }
-                crate::ast::Node::StructDeclaration {
-                    name,
-                    fields,
-                    line,
-                    column,
-                    ..
+                | crate::ast::Node::StructDeclaration {
+                    name, name_span, ..
+                }
+                | crate::ast::Node::ClassDeclaration {
+                    name, name_span, ..
                 } => {
-                    variable_types.insert(name.clone(), format!("struct {}", name));
-
-                    for field in fields {
-                        let field_type = match &field.typ {
-                            Some(t) => t.to_string(),
-                            None => "any".to_string(),
-                        };
-                    }
+                    let ty = resolution
+                        .symbols
+                        .iter()
+                        .find(|symbol| symbol.name_span == *name_span)
+                        .map_or(Ty::ANY, |symbol| symbol.ty);
+                    variable_types.insert(name.clone(), ty);
                 }
 
                 _ => {}
             }
         }
 
-        for node in &ast.nodes {}
+        // `import "lib"` brings in names the resolver cannot see.
+        let imports_whole_module = ast.nodes.iter().any(|node| {
+            matches!(node, Node::ImportDeclaration { imported_items, .. } if imported_items.is_empty())
+        });
+        check_names(resolution, imports_whole_module, &mut errors);
 
-        if errors.is_empty() {
-            let mut all_variables = self.variables.lock().unwrap();
-            all_variables.insert(file_path.to_string(), variable_types);
-            Ok(())
-        } else {
-            Err(errors)
+        let mut checker = TypeCheck {
+            resolution,
+            context: TypeContext::new(resolution),
+            errors: &mut errors,
+            return_types: Vec::new(),
+            const_assignments: Vec::new(),
+        };
+        checker.check_statements(&ast.nodes);
+        let const_assignments = checker.const_assignments;
+
+        let mut all_variables = self.variables.lock().unwrap();
+        all_variables.insert(file_path.to_string(), variable_types);
+
+        TypeCheckResult {
+            errors,
+            const_assignments,
         }
     }
 
-    pub fn get_variable_type(&self, variable_name: &str) -> Option<String> {
+    /// Drops the types recorded for `file_path`, which must not outlive the
+    /// analysis they come from.
+    pub fn forget_file(&self, file_path: &str) {
+        self.variables.lock().unwrap().remove(file_path);
+    }
+
+    pub fn get_variable_type(&self, variable_name: &str) -> Option<Ty> {
         let current_file = self.current_file.lock().unwrap();
 
         if let Some(file) = &*current_file {
             let variables = self.variables.lock().unwrap();
 
             if let Some(file_vars) = variables.get(file) {
-                return file_vars.get(variable_name).cloned();
+                return file_vars.get(variable_name).copied();
             }
         }
 
-        match variable_name {
-            "String" => Some("type".to_string()),
-            "Number" => Some("type".to_string()),
-            "Boolean" => Some("type".to_string()),
-            "Array" => Some("type".to_string()),
-            "Object" => Some("type".to_string()),
-            "Date" => Some("class".to_string()),
-            "Http" => Some("namespace".to_string()),
-            "Time" => Some("namespace".to_string()),
-            _ => None,
+        STDLIB_OBJECTS
+            .contains(&variable_name)
+            .then(|| Ty::named(variable_name))
+    }
+
+    /// The type of the name `name` as seen from `offset`: the innermost
+    /// declaration in scope there, or a file-level or standard library name.
+    pub fn get_type_at(&self, resolution: &Resolution, name: &str, offset: usize) -> Option<Ty> {
+        resolution
+            .visible_symbols(resolution.scope_at(offset))
+            .into_iter()
+            .find(|&symbol| resolution.symbols[symbol].name == name)
+            .map(|symbol| resolution.symbols[symbol].ty)
+            .or_else(|| self.get_variable_type(name))
+    }
+
+    pub fn get_property_type(
+        &self,
+        resolution: &Resolution,
+        object_type: Ty,
+        property_name: &str,
+    ) -> Option<Ty> {
+        match object_type.kind() {
+            TyKind::Struct(_) | TyKind::Class(_) => resolution
+                .member(object_type, property_name)
+                .map(|member| resolution.symbols[member].ty),
+            _ => builtin_member(object_type, property_name),
         }
     }
+}
+
+/// Members of the builtin and standard library types.
+pub fn builtin_member(object_type: Ty, property_name: &str) -> Option<Ty> {
+    let function = Ty::function;
+    let member = match object_type.kind() {
+        TyKind::String => match property_name {
+            "length" => Ty::INT,
+            "toUpperCase" | "toLowerCase" => function(vec![], Ty::STRING),
+            "substring" => function(vec![Ty::INT, Ty::INT], Ty::STRING),
+            "indexOf" => function(vec![Ty::STRING], Ty::INT),
+            "split" => function(vec![Ty::STRING], Ty::array(Ty::STRING)),
+            _ => return None,
+        },
+        TyKind::Array(element) => match property_name {
+            "length" => Ty::INT,
+            "push" | "unshift" => function(vec![element], Ty::INT),
+            "pop" | "shift" => function(vec![], element),
+            "join" => function(vec![Ty::STRING], Ty::STRING),
+            "map" => function(vec![function(vec![element], Ty::ANY)], Ty::array(Ty::ANY)),
+            "filter" => function(vec![function(vec![element], Ty::BOOL)], object_type),
+            _ => return None,
+        },
+        TyKind::Named(name) => match (name.as_str(), property_name) {
+            (
+                "Date",
+                "getTime" | "getDay" | "getMonth" | "getFullYear" | "getHours" | "getMinutes"
+                | "getSeconds",
+            ) => function(vec![], Ty::INT),
+            ("Http", "get" | "delete") => function(vec![Ty::STRING], Ty::named("HttpResponse")),
+            ("Http", "post" | "put") => {
+                function(vec![Ty::STRING, Ty::OBJECT], Ty::named("HttpResponse"))
+            }
+            ("Time", "now") => function(vec![], Ty::INT),
+            ("Time", "sleep") => function(vec![Ty::INT], Ty::VOID),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(member)
+}
 
-    pub fn get_property_type(&self, object_type: &str, property_name: &str) -> Option<String> {
-        match object_type {
-            "String" => match property_name {
-                "length" => Some("number".to_string()),
-                "toUpperCase" => Some("fn()->String".to_string()),
-                "toLowerCase" => Some("fn()->String".to_string()),
-                "substring" => Some("fn(number, number)->String".to_string()),
-                _ => None,
+/// Names of the members `builtin_member` describes, in completion order.
+fn builtin_member_names(object_type: Ty) -> &'static [&'static str] {
+    match object_type.kind() {
+        TyKind::String => &[
+            "length",
+            "toUpperCase",
+            "toLowerCase",
+            "substring",
+            "indexOf",
+            "split",
+        ],
+        TyKind::Array(_) => &[
+            "length", "push", "pop", "shift", "unshift", "join", "map", "filter",
+        ],
+        TyKind::Named(name) => match name.as_str() {
+            "Date" => &[
+                "getTime",
+                "getDay",
+                "getMonth",
+                "getFullYear",
+                "getHours",
+                "getMinutes",
+                "getSeconds",
+            ],
+            "Http" => &["get", "post", "put", "delete"],
+            "Time" => &["now", "sleep"],
+            _ => &[],
+        },
+        _ => &[],
+    }
+}
+
+/// Builtin functions available everywhere.
+pub const BUILTIN_FUNCTIONS: [&str; 7] = [
+    "print",
+    "println",
+    "len",
+    "typeof",
+    "parseInt",
+    "parseFloat",
+    "Date",
+];
+
+/// Standard library objects whose members are described by `builtin_member`.
+pub const STDLIB_OBJECTS: [&str; 2] = ["Http", "Time"];
+
+/// The type of a builtin function or standard library object.
+pub fn builtin_type(name: &str) -> Option<Ty> {
+    let ty = match name {
+        "print" | "println" => Ty::function(vec![Ty::ANY], Ty::VOID),
+        "len" => Ty::function(vec![Ty::ANY], Ty::INT),
+        "typeof" => Ty::function(vec![Ty::ANY], Ty::STRING),
+        "parseInt" => Ty::function(vec![Ty::STRING], Ty::INT),
+        "parseFloat" => Ty::function(vec![Ty::STRING], Ty::FLOAT),
+        "Date" => Ty::function(vec![], Ty::named("Date")),
+        name if STDLIB_OBJECTS.contains(&name) => Ty::named(name),
+        _ => return None,
+    };
+    Some(ty)
+}
+
+/// Reports every reference the resolver could not bind to a declaration and
+/// that is not a builtin, suggesting the closest name in scope. After an
+/// `import "lib"` any other name may come from the module, so only those
+/// that look like a misspelled local or builtin are reported.
+fn check_names(
+    resolution: &Resolution,
+    imports_whole_module: bool,
+    errors: &mut Vec<TypeErrorInfo>,
+) {
+    for reference in &resolution.references {
+        let name = reference.name.as_str();
+        if reference.symbol.is_some()
+            || BUILTIN_FUNCTIONS.contains(&name)
+            || STDLIB_OBJECTS.contains(&name)
+        {
+            continue;
+        }
+
+        let in_scope = resolution
+            .visible_symbols(reference.scope)
+            .into_iter()
+            .map(|symbol| resolution.symbols[symbol].name.as_str());
+        let candidates: Vec<&str> = if reference.is_callee {
+            in_scope.chain(BUILTIN_FUNCTIONS).collect()
+        } else {
+            in_scope
+                .chain(BUILTIN_FUNCTIONS)
+                .chain(STDLIB_OBJECTS)
+                .collect()
+        };
+
+        let what = if reference.is_callee {
+            "function"
+        } else {
+            "variable"
+        };
+        let message = match utils::closest_match(name, candidates) {
+            Some(suggestion) => format!(
+                "Undefined {} '{}'. Did you mean '{}'?",
+                what, name, suggestion
+            ),
+            None if imports_whole_module => continue,
+            None => format!("Undefined {} '{}'", what, name),
+        };
+
+        errors.push(TypeErrorInfo {
+            message,
+            span: reference.span,
+        });
+    }
+}
+
+/// Walks every statement and expression of a file and reports values whose
+/// types do not fit where they are used.
+struct TypeCheck<'a, 'e> {
+    resolution: &'a Resolution,
+    context: TypeContext<'a>,
+    errors: &'e mut Vec<TypeErrorInfo>,
+    /// Declared return types of the enclosing functions, innermost last.
+    return_types: Vec<Option<Ty>>,
+    const_assignments: Vec<ConstAssignment>,
+}
+
+impl TypeCheck<'_, '_> {
+    fn check_statements(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            self.check_node(node);
+        }
+    }
+
+    fn check_node(&mut self, node: &Node) {
+        match node {
+            Node::VariableDeclaration {
+                initializer,
+                data_type,
+                ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.check_expression(initializer);
+                    if let Some(data_type) = data_type {
+                        let expected = self.context.lower(data_type);
+                        self.check_assignable(expected, initializer);
+                    }
+                }
+            }
+            Node::FunctionDeclaration {
+                name,
+                name_span,
+                body,
+                return_type,
+                ..
+            } => self.check_function(
+                &format!("Function '{}'", name),
+                *name_span,
+                body,
+                return_type,
+            ),
+            Node::StructDeclaration { fields, .. } => {
+                for field in fields {
+                    if let Some(initializer) = &field.initializer {
+                        self.check_expression(initializer);
+                        if let Some(typ) = &field.typ {
+                            let expected = self.context.lower(typ);
+                            self.check_assignable(expected, initializer);
+                        }
+                    }
+                }
+            }
+            Node::ClassDeclaration {
+                methods,
+                properties,
+                ..
+            } => {
+                for property in properties {
+                    if let Some(initializer) = &property.initializer {
+                        self.check_expression(initializer);
+                        if let Some(typ) = &property.typ {
+                            let expected = self.context.lower(typ);
+                            self.check_assignable(expected, initializer);
+                        }
+                    }
+                }
+                self.check_statements(methods);
+            }
+            Node::ExpressionStatement { expression, .. } => self.check_expression(expression),
+            Node::ReturnStatement { expression, span } => {
+                if let Some(expression) = expression {
+                    self.check_expression(expression);
+                }
+                self.check_return(expression.as_deref(), *span);
+            }
+            Node::IfStatement {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => self.check_if(condition, then_branch, else_branch.as_deref()),
+            Node::WhileStatement {
+                condition, body, ..
+            } => {
+                self.forget_assigned(body);
+                self.check_expression(condition);
+                let narrowing = self.condition_narrowing(condition);
+                self.check_narrowed(&narrowing.when_true, body);
+            }
+            Node::ForStatement {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.check_node(initializer);
+                }
+                self.forget_assigned(body);
+                let mut narrowing = Narrowing::default();
+                if let Some(increment) = increment {
+                    let mut assigned = Vec::new();
+                    self.assigned_in_expression(increment, &mut assigned);
+                    for symbol in assigned {
+                        self.context.forget_narrowing(symbol);
+                    }
+                }
+                if let Some(condition) = condition {
+                    self.check_expression(condition);
+                    narrowing = self.condition_narrowing(condition);
+                }
+                self.check_narrowed(&narrowing.when_true, body);
+                if let Some(increment) = increment {
+                    self.check_expression(increment);
+                }
+            }
+            Node::ForInStatement { iterable, body, .. } => {
+                self.check_expression(iterable);
+                self.forget_assigned(body);
+                self.check_narrowed(&[], body);
+            }
+            Node::Block { statements, .. } => self.check_statements(statements),
+            Node::ImportDeclaration { .. }
+            | Node::BreakStatement { .. }
+            | Node::ContinueStatement { .. }
+            | Node::Error { .. } => {}
+        }
+    }
+
+    fn check_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Literal { .. } | Expression::Variable { .. } | Expression::Error { .. } => {
+            }
+            Expression::BinaryOperation {
+                operator,
+                left,
+                right,
+                span,
+            } => {
+                self.check_expression(left);
+                // The right operand of `&&` only runs when the left one held,
+                // and that of `||` when it did not.
+                let narrowing = self.condition_narrowing(left);
+                let before = self.context.narrowing();
+                match operator.as_str() {
+                    "&&" => self.narrow(&narrowing.when_true),
+                    "||" => self.narrow(&narrowing.when_false),
+                    _ => {}
+                }
+                self.check_expression(right);
+                self.context.restore_narrowing(before);
+
+                let left = self.context.expression_type(left);
+                let right = self.context.expression_type(right);
+                self.check_operands(operator, left, right, *span);
+            }
+            Expression::UnaryOperation { operand, .. } => self.check_expression(operand),
+            Expression::Call {
+                callee,
+                arguments,
+                span,
+            } => {
+                self.check_expression(callee);
+                for argument in arguments {
+                    self.check_expression(argument);
+                }
+                self.check_call(callee, arguments, *span);
+            }
+            Expression::PropertyAccess {
+                object,
+                property,
+                span,
+            } => {
+                self.check_expression(object);
+                self.check_member(object, property, *span);
+            }
+            Expression::ArrayAccess { array, index, .. } => {
+                self.check_expression(array);
+                self.check_expression(index);
+            }
+            Expression::Assignment {
+                operator,
+                target,
+                value,
+                span,
+            } => {
+                self.check_expression(target);
+                self.check_expression(value);
+                self.check_mutable(target);
+
+                // Reading the target sees its narrowed type, storing into it
+                // is checked against the declared one.
+                let symbol = self.context.symbol_of(target);
+                let current_type = self.context.expression_type(target);
+                let target_type =
+                    symbol.map_or(current_type, |symbol| self.context.symbol_type(symbol));
+                let mut value_type = self.context.expression_type(value);
+                match operator.strip_suffix('=').filter(|op| !op.is_empty()) {
+                    Some(operator) => {
+                        if self.check_operands(operator, current_type, value_type, *span) {
+                            value_type = inference::binary_type(operator, current_type, value_type);
+                            self.report_mismatch(target_type, value_type, *span);
+                        }
+                    }
+                    None => self.report_mismatch(target_type, value_type, value.span()),
+                }
+
+                if let Some(symbol) = symbol {
+                    self.context.forget_narrowing(symbol);
+                    if target_type.is_nullable() && !value_type.is_nullable() {
+                        self.context.narrow(symbol, target_type.non_null());
+                    }
+                }
+            }
+            Expression::ArrayLiteral { elements, .. }
+            | Expression::InterpolatedString {
+                parts: elements, ..
+            } => {
+                for element in elements {
+                    self.check_expression(element);
+                }
+            }
+            Expression::ObjectLiteral { properties, .. } => {
+                for property in properties {
+                    self.check_expression(&property.value);
+                }
+            }
+            Expression::Lambda {
+                body,
+                return_type,
+                span,
+                ..
+            } => self.check_function("Lambda", *span, body, return_type),
+        }
+    }
+
+    /// Checks a function body and, when it declares a non-void return type,
+    /// that no path through it ends without returning.
+    fn check_function(
+        &mut self,
+        subject: &str,
+        span: Span,
+        body: &[Node],
+        return_type: &Option<Type>,
+    ) {
+        // A function may run at any time, so checks made around its
+        // declaration say nothing about the variables it uses.
+        let narrowed = self.context.narrowing();
+        self.context.restore_narrowing(HashMap::new());
+
+        let return_type = return_type.as_ref().map(|typ| self.context.lower(typ));
+        self.return_types.push(return_type);
+        self.check_statements(body);
+        self.return_types.pop();
+        self.context.restore_narrowing(narrowed);
+
+        if let Some(return_type) = return_type {
+            if return_type != Ty::VOID && return_type != Ty::ANY && !ends_abruptly(body) {
+                self.errors.push(TypeErrorInfo {
+                    message: format!(
+                        "{} must return a value of type '{}' on every path",
+                        subject, return_type
+                    ),
+                    span,
+                });
+            }
+        }
+    }
+
+    fn check_return(&mut self, expression: Option<&Expression>, span: Span) {
+        let Some(&Some(expected)) = self.return_types.last() else {
+            return;
+        };
+
+        match expression {
+            Some(value) if expected == Ty::VOID => {
+                let actual = self.context.expression_type(value);
+                self.errors.push(TypeErrorInfo {
+                    message: format!(
+                        "Cannot return a value of type '{}' from a function returning 'void'",
+                        actual
+                    ),
+                    span: value.span(),
+                });
+            }
+            Some(value) => self.check_assignable(expected, value),
+            None if !Ty::VOID.is_assignable_to(expected) => {
+                self.errors.push(TypeErrorInfo {
+                    message: format!("Expected a return value of type '{}'", expected),
+                    span,
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// Checks an `if` with each branch seeing what its condition implies.
+    /// When one branch always leaves, what the other implies holds after
+    /// the `if`, as in `if x == null { return }`.
+    fn check_if(
+        &mut self,
+        condition: &Expression,
+        then_branch: &[Node],
+        else_branch: Option<&[Node]>,
+    ) {
+        self.check_expression(condition);
+        let narrowing = self.condition_narrowing(condition);
+
+        self.check_narrowed(&narrowing.when_true, then_branch);
+        if let Some(else_branch) = else_branch {
+            self.check_narrowed(&narrowing.when_false, else_branch);
+        }
+
+        let then_exits = ends_abruptly(then_branch);
+        let else_exits = else_branch.is_some_and(ends_abruptly);
+        if then_exits && !else_exits {
+            self.narrow(&narrowing.when_false);
+        } else if else_exits && !then_exits {
+            self.narrow(&narrowing.when_true);
+        }
+
+        if !then_exits {
+            self.forget_assigned(then_branch);
+        }
+        if let Some(else_branch) = else_branch.filter(|_| !else_exits) {
+            self.forget_assigned(else_branch);
+        }
+    }
+
+    /// Checks `nodes` with `facts` applied, then goes back to what was known
+    /// before.
+    fn check_narrowed(&mut self, facts: &[(SymbolId, Ty)], nodes: &[Node]) {
+        let before = self.context.narrowing();
+        self.narrow(facts);
+        self.check_statements(nodes);
+        self.context.restore_narrowing(before);
+    }
+
+    fn narrow(&mut self, facts: &[(SymbolId, Ty)]) {
+        for &(symbol, ty) in facts {
+            self.context.narrow(symbol, ty);
+        }
+    }
+
+    /// What a condition tells about the variables it tests.
+    fn condition_narrowing(&self, condition: &Expression) -> Narrowing {
+        match condition {
+            Expression::BinaryOperation {
+                operator,
+                left,
+                right,
+                ..
+            } => match operator.as_str() {
+                "==" | "!=" => {
+                    let mut narrowing = match (left.as_ref(), right.as_ref()) {
+                        (variable, null) | (null, variable) if is_null(null) => {
+                            self.null_narrowing(variable)
+                        }
+                        (
+                            test,
+                            Expression::Literal {
+                                value: LiteralValue::String(name),
+                                ..
+                            },
+                        )
+                        | (
+                            Expression::Literal {
+                                value: LiteralValue::String(name),
+                                ..
+                            },
+                            test,
+                        ) => match self.typeof_operand(test) {
+                            Some(variable) => self.typeof_narrowing(variable, name),
+                            None => return Narrowing::default(),
+                        },
+                        _ => return Narrowing::default(),
+                    };
+                    if operator == "!=" {
+                        std::mem::swap(&mut narrowing.when_true, &mut narrowing.when_false);
+                    }
+                    narrowing
+                }
+                "&&" => Narrowing {
+                    when_true: [
+                        self.condition_narrowing(left).when_true,
+                        self.condition_narrowing(right).when_true,
+                    ]
+                    .concat(),
+                    when_false: Vec::new(),
+                },
+                "||" => Narrowing {
+                    when_true: Vec::new(),
+                    when_false: [
+                        self.condition_narrowing(left).when_false,
+                        self.condition_narrowing(right).when_false,
+                    ]
+                    .concat(),
+                },
+                _ => Narrowing::default(),
             },
-            "Array" => match property_name {
-                "length" => Some("number".to_string()),
-                "push" => Some("fn(any)->number".to_string()),
-                "pop" => Some("fn()->any".to_string()),
-                "join" => Some("fn(String)->String".to_string()),
-                _ => None,
+            Expression::UnaryOperation {
+                operator, operand, ..
+            } if operator == "!" => {
+                let narrowing = self.condition_narrowing(operand);
+                Narrowing {
+                    when_true: narrowing.when_false,
+                    when_false: narrowing.when_true,
+                }
+            }
+            Expression::Variable { .. } => Narrowing {
+                when_true: self.null_narrowing(condition).when_false,
+                when_false: Vec::new(),
             },
-            "Date" => match property_name {
-                "getTime" => Some("fn()->number".to_string()),
-                "getDay" => Some("fn()->number".to_string()),
-                "getMonth" => Some("fn()->number".to_string()),
-                "getFullYear" => Some("fn()->number".to_string()),
-                _ => None,
+            _ => Narrowing::default(),
+        }
+    }
+
+    /// What `variable == null` tells about a variable that may be null.
+    fn null_narrowing(&self, variable: &Expression) -> Narrowing {
+        let ty = self.context.expression_type(variable);
+        match self.context.symbol_of(variable) {
+            Some(symbol) if ty.is_nullable() => Narrowing {
+                when_true: vec![(symbol, Ty::NULL)],
+                when_false: vec![(symbol, ty.non_null())],
             },
-            "Http" => match property_name {
-                "get" => Some("fn(String)->HttpResponse".to_string()),
-                "post" => Some("fn(String, Object)->HttpResponse".to_string()),
-                _ => None,
+            _ => Narrowing::default(),
+        }
+    }
+
+    /// The variable `expression` passes to the builtin `typeof`, if it is
+    /// such a call.
+    fn typeof_operand<'e>(&self, expression: &'e Expression) -> Option<&'e Expression> {
+        let Expression::Call {
+            callee, arguments, ..
+        } = expression
+        else {
+            return None;
+        };
+        let is_typeof = matches!(callee.as_ref(), Expression::Variable { name, .. } if name == "typeof")
+            && self.context.symbol_of(callee).is_none();
+        match arguments.as_slice() {
+            [argument] if is_typeof => Some(argument),
+            _ => None,
+        }
+    }
+
+    /// What `typeof(variable) == name` tells about a variable whose type has
+    /// several variants. Variants `typeof` gives no known name for stay on
+    /// both sides.
+    fn typeof_narrowing(&self, variable: &Expression, name: &str) -> Narrowing {
+        let ty = self.context.expression_type(variable);
+        let variants = ty.variants();
+        let Some(symbol) = self
+            .context
+            .symbol_of(variable)
+            .filter(|_| variants.len() > 1)
+        else {
+            return Narrowing::default();
+        };
+
+        let matching = |matches: bool| {
+            Ty::union(variants.iter().copied().filter(|variant| {
+                variant
+                    .typeof_name()
+                    .is_none_or(|variant_name| (variant_name == name) == matches)
+            }))
+        };
+        Narrowing {
+            when_true: vec![(symbol, matching(true))],
+            when_false: vec![(symbol, matching(false))],
+        }
+    }
+
+    /// Drops what is known about variables that `nodes` assign to.
+    fn forget_assigned(&mut self, nodes: &[Node]) {
+        let mut assigned = Vec::new();
+        self.assigned_in_statements(nodes, &mut assigned);
+        for symbol in assigned {
+            self.context.forget_narrowing(symbol);
+        }
+    }
+
+    fn assigned_in_statements(&self, nodes: &[Node], assigned: &mut Vec<SymbolId>) {
+        for node in nodes {
+            match node {
+                Node::VariableDeclaration {
+                    initializer: Some(expression),
+                    ..
+                }
+                | Node::ExpressionStatement { expression, .. }
+                | Node::ReturnStatement {
+                    expression: Some(expression),
+                    ..
+                } => self.assigned_in_expression(expression, assigned),
+                Node::IfStatement {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    self.assigned_in_expression(condition, assigned);
+                    self.assigned_in_statements(then_branch, assigned);
+                    if let Some(else_branch) = else_branch {
+                        self.assigned_in_statements(else_branch, assigned);
+                    }
+                }
+                Node::WhileStatement {
+                    condition, body, ..
+                } => {
+                    self.assigned_in_expression(condition, assigned);
+                    self.assigned_in_statements(body, assigned);
+                }
+                Node::ForStatement {
+                    initializer,
+                    condition,
+                    increment,
+                    body,
+                    ..
+                } => {
+                    if let Some(initializer) = initializer {
+                        self.assigned_in_statements(std::slice::from_ref(initializer), assigned);
+                    }
+                    for expression in condition.iter().chain(increment) {
+                        self.assigned_in_expression(expression, assigned);
+                    }
+                    self.assigned_in_statements(body, assigned);
+                }
+                Node::ForInStatement { iterable, body, .. } => {
+                    self.assigned_in_expression(iterable, assigned);
+                    self.assigned_in_statements(body, assigned);
+                }
+                Node::Block { statements, .. } => self.assigned_in_statements(statements, assigned),
+                _ => {}
+            }
+        }
+    }
+
+    /// Collects the variables assigned in `expression`. Lambdas are skipped:
+    /// what they assign is forgotten when they are checked.
+    fn assigned_in_expression(&self, expression: &Expression, assigned: &mut Vec<SymbolId>) {
+        match expression {
+            Expression::Assignment { target, value, .. } => {
+                assigned.extend(self.context.symbol_of(target));
+                self.assigned_in_expression(target, assigned);
+                self.assigned_in_expression(value, assigned);
+            }
+            Expression::BinaryOperation { left, right, .. } => {
+                self.assigned_in_expression(left, assigned);
+                self.assigned_in_expression(right, assigned);
+            }
+            Expression::UnaryOperation { operand, .. } => {
+                self.assigned_in_expression(operand, assigned)
+            }
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                self.assigned_in_expression(callee, assigned);
+                for argument in arguments {
+                    self.assigned_in_expression(argument, assigned);
+                }
+            }
+            Expression::PropertyAccess { object, .. } => {
+                self.assigned_in_expression(object, assigned)
+            }
+            Expression::ArrayAccess { array, index, .. } => {
+                self.assigned_in_expression(array, assigned);
+                self.assigned_in_expression(index, assigned);
+            }
+            Expression::ArrayLiteral { elements, .. }
+            | Expression::InterpolatedString {
+                parts: elements, ..
+            } => {
+                for element in elements {
+                    self.assigned_in_expression(element, assigned);
+                }
+            }
+            Expression::ObjectLiteral { properties, .. } => {
+                for property in properties {
+                    self.assigned_in_expression(&property.value, assigned);
+                }
+            }
+            Expression::Literal { .. }
+            | Expression::Variable { .. }
+            | Expression::Lambda { .. }
+            | Expression::Error { .. } => {}
+        }
+    }
+
+    /// Reports assigning to a constant or to a constant field.
+    fn check_mutable(&mut self, target: &Expression) {
+        let (declaration, span) = match target {
+            Expression::Variable { span, .. } => match self.context.symbol_of(target) {
+                Some(symbol) => (symbol, *span),
+                None => return,
             },
-            "Time" => match property_name {
-                "now" => Some("fn()->number".to_string()),
-                "sleep" => Some("fn(number)->void".to_string()),
-                _ => None,
+            Expression::PropertyAccess {
+                object,
+                property,
+                span,
+            } => {
+                let object_type = self.context.expression_type(object).non_null();
+                let member = self
+                    .context
+                    .members(object_type)
+                    .into_iter()
+                    .find(|&member| self.resolution.symbols[member].name == *property);
+                match member {
+                    Some(member) => (member, Span::new(span.end - property.len(), span.end)),
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+
+        let symbol = &self.resolution.symbols[declaration];
+        let message = match symbol.kind {
+            _ if symbol.is_mutable => return,
+            DeclarationKind::Variable => format!("Cannot assign to constant '{}'", symbol.name),
+            DeclarationKind::Property => {
+                format!("Cannot assign to constant field '{}'", symbol.name)
+            }
+            _ => return,
+        };
+        self.errors.push(TypeErrorInfo { message, span });
+        self.const_assignments
+            .push(ConstAssignment { span, declaration });
+    }
+
+    /// Reports accessing a member of a value that may be null, one that a
+    /// struct or class does not declare, or one that some variant of a union
+    /// lacks.
+    fn check_member(&mut self, object: &Expression, property: &str, span: Span) {
+        // The parser already reported the missing name.
+        if property.is_empty() {
+            return;
+        }
+        let object_type = self.context.expression_type(object);
+        if object_type.is_nullable() {
+            self.errors.push(TypeErrorInfo {
+                message: format!(
+                    "Cannot access '{}' on a value of type '{}' that may be null",
+                    property, object_type
+                ),
+                span: object.span(),
+            });
+        }
+
+        let object_type = object_type.non_null();
+        let what = match object_type.kind() {
+            TyKind::Struct(_) => "struct",
+            TyKind::Class(_) => "class",
+            TyKind::Union(variants) => {
+                self.check_union_member(object_type, &variants, property, span);
+                return;
+            }
+            _ => return,
+        };
+        if self.context.member_type(object_type, property).is_some() {
+            return;
+        }
+
+        let members = self.context.members(object_type);
+        let candidates = members
+            .iter()
+            .map(|&member| self.resolution.symbols[member].name.as_str());
+        let message = match utils::closest_match(property, candidates) {
+            Some(suggestion) => format!(
+                "Unknown member '{}' on {} '{}'. Did you mean '{}'?",
+                property, what, object_type, suggestion
+            ),
+            None => format!(
+                "Unknown member '{}' on {} '{}'",
+                property, what, object_type
+            ),
+        };
+        self.errors.push(TypeErrorInfo {
+            message,
+            span: Span::new(span.end - property.len(), span.end),
+        });
+    }
+
+    fn check_union_member(&mut self, union: Ty, variants: &[Ty], property: &str, span: Span) {
+        // Members of standard library and imported types are not all known.
+        let missing: Vec<String> = variants
+            .iter()
+            .filter(|variant| {
+                !matches!(
+                    variant.kind(),
+                    TyKind::Named(_) | TyKind::Object | TyKind::Any
+                )
+            })
+            .filter(|&&variant| self.context.member_type(variant, property).is_none())
+            .map(|variant| format!("'{}'", variant))
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+
+        self.errors.push(TypeErrorInfo {
+            message: format!(
+                "Member '{}' does not exist on every variant of '{}': missing on {}",
+                property,
+                union,
+                missing.join(", ")
+            ),
+            span: Span::new(span.end - property.len(), span.end),
+        });
+    }
+
+    /// Checks the arguments of a call against the signature of its callee.
+    /// Constructing a struct or class is not checked.
+    fn check_call(&mut self, callee: &Expression, arguments: &[Expression], span: Span) {
+        if self.context.constructed_type(callee).is_some() {
+            return;
+        }
+
+        let name = match callee {
+            Expression::Variable { name, .. } => format!("'{}'", name),
+            Expression::PropertyAccess {
+                object, property, ..
+            } => match object.as_ref() {
+                Expression::Variable { name, .. } => format!("'{}.{}'", name, property),
+                _ => format!("'{}'", property),
             },
+            _ => "this function".to_string(),
+        };
+        let callee_type = self.context.expression_type(callee);
+        if callee_type.is_nullable() {
+            self.errors.push(TypeErrorInfo {
+                message: format!(
+                    "Cannot call {} of type '{}', it may be null",
+                    name, callee_type
+                ),
+                span: callee.span(),
+            });
+        }
 
-            s if s.starts_with("struct ") => {
-                let struct_name = s.trim_start_matches("struct ");
-                let current_file = self.current_file.lock().unwrap();
+        let callee_type = callee_type.non_null();
+        let params = match callee_type.kind() {
+            TyKind::Function { params, .. } => params,
+            TyKind::Union(_) | TyKind::Any => return,
+            _ => {
+                self.errors.push(TypeErrorInfo {
+                    message: format!(
+                        "Cannot call {} of type '{}', expected a function",
+                        name, callee_type
+                    ),
+                    span: callee.span(),
+                });
+                return;
+            }
+        };
 
-                if let Some(file) = &*current_file {
-                    Some("any".to_string())
-                } else {
-                    None
-                }
+        if params.len() != arguments.len() {
+            self.errors.push(TypeErrorInfo {
+                message: format!(
+                    "Function {} expects {}, found {}",
+                    name,
+                    count_arguments(params.len()),
+                    arguments.len()
+                ),
+                span,
+            });
+            return;
+        }
+
+        for (&param, argument) in params.iter().zip(arguments) {
+            self.check_assignable(param, argument);
+        }
+    }
+
+    fn check_assignable(&mut self, expected: Ty, value: &Expression) {
+        let actual = self.context.expression_type(value);
+        self.report_mismatch(expected, actual, value.span());
+    }
+
+    fn report_mismatch(&mut self, expected: Ty, actual: Ty, span: Span) {
+        if !actual.is_assignable_to(expected) {
+            self.errors.push(TypeErrorInfo {
+                message: format!("Type mismatch: expected '{}', found '{}'", expected, actual),
+                span,
+            });
+        }
+    }
+
+    /// Reports operands a binary operator cannot combine and returns whether
+    /// they were accepted.
+    fn check_operands(&mut self, operator: &str, left: Ty, right: Ty, span: Span) -> bool {
+        let both =
+            |accepts: fn(Ty) -> bool| left.all_members(accepts) && right.all_members(accepts);
+        let expected = match operator {
+            "+" if both(Ty::is_numeric)
+                || left.all_members(is_string)
+                || right.all_members(is_string) =>
+            {
+                return true
             }
-            _ => None,
+            "+" => "'number' or 'string'",
+            "-" | "*" | "/" | "%" if both(Ty::is_numeric) => return true,
+            "-" | "*" | "/" | "%" => "'number'",
+            "<" | "<=" | ">" | ">=" if both(Ty::is_numeric) || both(is_string) => return true,
+            "<" | "<=" | ">" | ">=" => "'number' or 'string'",
+            "&&" | "||" if is_condition(left) && is_condition(right) => return true,
+            "&&" | "||" => "'bool' or optional",
+            ".." if both(|ty| ty == Ty::INT) => return true,
+            ".." => "'int'",
+            // Any value may be checked against `null`, even one that cannot be.
+            "==" | "!=" if left == Ty::NULL || right == Ty::NULL => return true,
+            "==" | "!=" if left.is_assignable_to(right) || right.is_assignable_to(left) => {
+                return true
+            }
+            "==" | "!=" => "comparable",
+            _ => return true,
+        };
+
+        self.errors.push(TypeErrorInfo {
+            message: format!(
+                "Operator '{}' expects {} operands, found '{}' and '{}'",
+                operator, expected, left, right
+            ),
+            span,
+        });
+        false
+    }
+}
+
+/// Whether running `nodes` never reaches what follows them, because every
+/// path returns, breaks, continues or stays in a loop that never ends.
+fn ends_abruptly(nodes: &[Node]) -> bool {
+    !ControlFlowGraph::build(nodes).falls_through()
+}
+
+/// Whether a value of type `ty` can be tested by itself, like the condition
+/// of an `if`: a `bool`, or a value that may be null.
+fn is_condition(ty: Ty) -> bool {
+    ty.is_nullable() || ty.all_members(|ty| ty == Ty::BOOL)
+}
+
+fn is_null(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::Literal {
+            value: LiteralValue::Null,
+            ..
         }
+    )
+}
+
+/// Narrower types a condition implies for some variables when it holds and
+/// when it does not.
+#[derive(Default)]
+struct Narrowing {
+    when_true: Vec<(SymbolId, Ty)>,
+    when_false: Vec<(SymbolId, Ty)>,
+}
+
+fn count_arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        count => format!("{} arguments", count),
     }
 }
 
+fn is_string(ty: Ty) -> bool {
+    ty == Ty::STRING
+}
+
 pub fn get_completions(
-    document: &str,
+    document: &Document,
     position: Position,
     type_checker: &std::sync::Arc<BurnTypeChecker>,
 ) -> Vec<CompletionItem> {
     let mut items = Vec::new();
+    let resolution = &document.resolution;
 
-    if let Ok(offset) = utils::position_to_offset(document, position) {
-        let text_before = &document[..offset];
-
-        if let Some(last_char) = text_before.chars().last() {
-            if last_char == '.' {
-                if let Some(property_start) = text_before.rfind('.') {
-                    let object_end = property_start;
-
-                    if let Some(object_start) = text_before[..object_end]
-                        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
-                        .map(|pos| pos + 1)
-                        .or(Some(0))
-                    {
-                        let object_name = text_before[object_start..object_end].trim();
-
-                        if let Some(object_type) = type_checker.get_variable_type(object_name) {
-                            match object_type.as_str() {
-                                "String" => add_string_completions(&mut items),
-                                "Array" => add_array_completions(&mut items),
-                                "Date" => add_date_completions(&mut items),
-                                "Http" => add_http_completions(&mut items),
-                                "Time" => add_time_completions(&mut items),
-
-                                _ => {}
-                            }
-
-                            return items;
-                        }
-                    }
-                }
-
-                return default_property_completions();
-            }
+    if let Ok(offset) = utils::position_to_offset(&document.content, position) {
+        if let Some(object) = accessed_object(&document.ast.nodes, offset) {
+            let object_type = TypeContext::new(resolution)
+                .expression_type(object)
+                .non_null();
+            add_member_completions(&mut items, resolution, object_type);
+            return items;
         }
     }
 
@@ -246,7 +1228,11 @@ pub fn get_completions(
                     items.push(CompletionItem {
                         label: var_name.clone(),
                         kind: Some(CompletionItemKind::VARIABLE),
-                        detail: Some(var_type.clone()),
+                        detail: Some(match var_type.kind() {
+                            TyKind::Struct(nominal) => format!("struct {}", nominal.name),
+                            TyKind::Class(nominal) => format!("class {}", nominal.name),
+                            _ => var_type.to_string(),
+                        }),
                         ..Default::default()
                     });
                 }
@@ -274,9 +1260,7 @@ fn add_keyword_completions(items: &mut Vec<CompletionItem>) {
 }
 
 fn add_type_completions(items: &mut Vec<CompletionItem>) {
-    let types = [
-        "String", "Number", "Boolean", "Array", "Object", "Date", "Function", "any", "void",
-    ];
+    let types = ["int", "float", "string", "bool", "Date", "any", "void"];
 
     for &typ in &types {
         items.push(CompletionItem {
@@ -288,164 +1272,639 @@ fn add_type_completions(items: &mut Vec<CompletionItem>) {
 }
 
 fn add_builtin_function_completions(items: &mut Vec<CompletionItem>) {
-    let builtins = [
-        ("print", "fn(any)->void"),
-        ("println", "fn(any)->void"),
-        ("len", "fn(collection)->Number"),
-        ("typeof", "fn(any)->String"),
-        ("parseInt", "fn(String)->Number"),
-        ("parseFloat", "fn(String)->Number"),
-    ];
-
-    for &(name, signature) in &builtins {
+    for name in BUILTIN_FUNCTIONS {
         items.push(CompletionItem {
             label: name.to_string(),
             kind: Some(CompletionItemKind::FUNCTION),
-            detail: Some(signature.to_string()),
+            detail: builtin_type(name).map(|ty| ty.to_string()),
             ..Default::default()
         });
     }
 }
 
-fn add_string_completions(items: &mut Vec<CompletionItem>) {
-    let methods = [
-        ("length", "number", CompletionItemKind::PROPERTY),
-        ("toUpperCase", "fn()->String", CompletionItemKind::METHOD),
-        ("toLowerCase", "fn()->String", CompletionItemKind::METHOD),
-        (
-            "substring",
-            "fn(number, number)->String",
-            CompletionItemKind::METHOD,
-        ),
-        ("indexOf", "fn(String)->number", CompletionItemKind::METHOD),
-        ("split", "fn(String)->Array", CompletionItemKind::METHOD),
-    ];
+/// Offers the members of a struct or class declared in the file, or of a
+/// builtin or standard library type.
+fn add_member_completions(
+    items: &mut Vec<CompletionItem>,
+    resolution: &Resolution,
+    object_type: Ty,
+) {
+    let members: Vec<(&str, Ty)> = match resolution.type_declaration(object_type) {
+        Some(declaration) => resolution
+            .members(declaration)
+            .into_iter()
+            .map(|member| {
+                let member = &resolution.symbols[member];
+                (member.name.as_str(), member.ty)
+            })
+            .collect(),
+        None => builtin_member_names(object_type)
+            .iter()
+            .filter_map(|&name| Some((name, builtin_member(object_type, name)?)))
+            .collect(),
+    };
 
-    for &(name, detail, kind) in &methods {
+    for (name, ty) in members {
+        let kind = match ty.kind() {
+            TyKind::Function { .. } => CompletionItemKind::METHOD,
+            _ => CompletionItemKind::PROPERTY,
+        };
         items.push(CompletionItem {
             label: name.to_string(),
             kind: Some(kind),
-            detail: Some(detail.to_string()),
+            detail: Some(ty.to_string()),
             ..Default::default()
         });
     }
 }
 
-fn add_array_completions(items: &mut Vec<CompletionItem>) {
-    let methods = [
-        ("length", "number", CompletionItemKind::PROPERTY),
-        ("push", "fn(any)->number", CompletionItemKind::METHOD),
-        ("pop", "fn()->any", CompletionItemKind::METHOD),
-        ("shift", "fn()->any", CompletionItemKind::METHOD),
-        ("unshift", "fn(any)->number", CompletionItemKind::METHOD),
-        ("join", "fn(String)->String", CompletionItemKind::METHOD),
-        ("map", "fn(fn(any)->any)->Array", CompletionItemKind::METHOD),
-        (
-            "filter",
-            "fn(fn(any)->Boolean)->Array",
-            CompletionItemKind::METHOD,
-        ),
-    ];
+/// The object of the property access that ends at `offset`, whether its
+/// name is partly typed or still missing after the `.`.
+fn accessed_object(nodes: &[Node], offset: usize) -> Option<&Expression> {
+    nodes
+        .iter()
+        .find_map(|node| node_accessed_object(node, offset))
+}
 
-    for &(name, detail, kind) in &methods {
-        items.push(CompletionItem {
-            label: name.to_string(),
-            kind: Some(kind),
-            detail: Some(detail.to_string()),
-            ..Default::default()
-        });
+fn node_accessed_object(node: &Node, offset: usize) -> Option<&Expression> {
+    let span = node.span();
+    if offset < span.start || span.end < offset {
+        return None;
+    }
+
+    match node {
+        Node::VariableDeclaration { initializer, .. } => {
+            optional_accessed_object(initializer.as_deref(), offset)
+        }
+        Node::FunctionDeclaration { body, .. } => accessed_object(body, offset),
+        Node::StructDeclaration { fields, .. } => fields_accessed_object(fields, offset),
+        Node::ClassDeclaration {
+            methods,
+            properties,
+            ..
+        } => {
+            accessed_object(methods, offset).or_else(|| fields_accessed_object(properties, offset))
+        }
+        Node::ExpressionStatement { expression, .. } => {
+            expression_accessed_object(expression, offset)
+        }
+        Node::ReturnStatement { expression, .. } => {
+            optional_accessed_object(expression.as_deref(), offset)
+        }
+        Node::IfStatement {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => expression_accessed_object(condition, offset)
+            .or_else(|| accessed_object(then_branch, offset))
+            .or_else(|| accessed_object(else_branch.as_deref()?, offset)),
+        Node::WhileStatement {
+            condition, body, ..
+        } => {
+            expression_accessed_object(condition, offset).or_else(|| accessed_object(body, offset))
+        }
+        Node::ForStatement {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => initializer
+            .as_deref()
+            .and_then(|initializer| node_accessed_object(initializer, offset))
+            .or_else(|| optional_accessed_object(condition.as_deref(), offset))
+            .or_else(|| optional_accessed_object(increment.as_deref(), offset))
+            .or_else(|| accessed_object(body, offset)),
+        Node::ForInStatement { iterable, body, .. } => {
+            expression_accessed_object(iterable, offset).or_else(|| accessed_object(body, offset))
+        }
+        Node::Block { statements, .. } => accessed_object(statements, offset),
+        Node::ImportDeclaration { .. }
+        | Node::BreakStatement { .. }
+        | Node::ContinueStatement { .. }
+        | Node::Error { .. } => None,
     }
 }
 
-fn add_date_completions(items: &mut Vec<CompletionItem>) {
-    let methods = [
-        ("getTime", "fn()->number", CompletionItemKind::METHOD),
-        ("getDay", "fn()->number", CompletionItemKind::METHOD),
-        ("getMonth", "fn()->number", CompletionItemKind::METHOD),
-        ("getFullYear", "fn()->number", CompletionItemKind::METHOD),
-        ("getHours", "fn()->number", CompletionItemKind::METHOD),
-        ("getMinutes", "fn()->number", CompletionItemKind::METHOD),
-        ("getSeconds", "fn()->number", CompletionItemKind::METHOD),
-    ];
+fn optional_accessed_object(expression: Option<&Expression>, offset: usize) -> Option<&Expression> {
+    expression_accessed_object(expression?, offset)
+}
 
-    for &(name, detail, kind) in &methods {
-        items.push(CompletionItem {
-            label: name.to_string(),
-            kind: Some(kind),
-            detail: Some(detail.to_string()),
-            ..Default::default()
-        });
-    }
+fn fields_accessed_object(fields: &[StructField], offset: usize) -> Option<&Expression> {
+    fields
+        .iter()
+        .find_map(|field| expression_accessed_object(field.initializer.as_ref()?, offset))
 }
 
-fn add_http_completions(items: &mut Vec<CompletionItem>) {
-    let methods = [
-        (
-            "get",
-            "fn(String)->HttpResponse",
-            CompletionItemKind::METHOD,
-        ),
-        (
-            "post",
-            "fn(String, Object)->HttpResponse",
-            CompletionItemKind::METHOD,
-        ),
-        (
-            "put",
-            "fn(String, Object)->HttpResponse",
-            CompletionItemKind::METHOD,
-        ),
-        (
-            "delete",
-            "fn(String)->HttpResponse",
-            CompletionItemKind::METHOD,
-        ),
-    ];
+fn list_accessed_object(expressions: &[Expression], offset: usize) -> Option<&Expression> {
+    expressions
+        .iter()
+        .find_map(|expression| expression_accessed_object(expression, offset))
+}
 
-    for &(name, detail, kind) in &methods {
-        items.push(CompletionItem {
-            label: name.to_string(),
-            kind: Some(kind),
-            detail: Some(detail.to_string()),
-            ..Default::default()
-        });
+fn expression_accessed_object(expression: &Expression, offset: usize) -> Option<&Expression> {
+    let span = expression.span();
+    if offset < span.start || span.end < offset {
+        return None;
+    }
+
+    match expression {
+        Expression::PropertyAccess { object, span, .. } => {
+            expression_accessed_object(object, offset)
+                .or_else(|| (span.end == offset).then_some(&**object))
+        }
+        Expression::BinaryOperation { left, right, .. } => expression_accessed_object(left, offset)
+            .or_else(|| expression_accessed_object(right, offset)),
+        Expression::UnaryOperation { operand, .. } => expression_accessed_object(operand, offset),
+        Expression::Call {
+            callee, arguments, ..
+        } => expression_accessed_object(callee, offset)
+            .or_else(|| list_accessed_object(arguments, offset)),
+        Expression::ArrayAccess { array, index, .. } => expression_accessed_object(array, offset)
+            .or_else(|| expression_accessed_object(index, offset)),
+        Expression::Assignment { target, value, .. } => expression_accessed_object(target, offset)
+            .or_else(|| expression_accessed_object(value, offset)),
+        Expression::ArrayLiteral { elements, .. }
+        | Expression::InterpolatedString {
+            parts: elements, ..
+        } => list_accessed_object(elements, offset),
+        Expression::ObjectLiteral { properties, .. } => properties
+            .iter()
+            .find_map(|property| expression_accessed_object(&property.value, offset)),
+        Expression::Lambda { body, .. } => accessed_object(body, offset),
+        Expression::Literal { .. } | Expression::Variable { .. } | Expression::Error { .. } => None,
     }
 }
 
-fn add_time_completions(items: &mut Vec<CompletionItem>) {
-    let methods = [
-        ("now", "fn()->number", CompletionItemKind::METHOD),
-        ("sleep", "fn(number)->void", CompletionItemKind::METHOD),
-    ];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, resolver};
 
-    for &(name, detail, kind) in &methods {
-        items.push(CompletionItem {
-            label: name.to_string(),
-            kind: Some(kind),
-            detail: Some(detail.to_string()),
-            ..Default::default()
-        });
+    /// The messages `check_types` reports for `source`.
+    fn errors(source: &str) -> Vec<String> {
+        let result = parser::parse(source);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        let mut resolution = resolver::resolve(&result.ast, "file:///typechecker.bn");
+        inference::infer_types(&result.ast, &mut resolution);
+
+        BurnTypeChecker::new()
+            .check_types(&result.ast, &resolution, "file:///typechecker.bn")
+            .errors
+            .into_iter()
+            .map(|err| err.message)
+            .collect()
     }
-}
 
-fn default_property_completions() -> Vec<CompletionItem> {
-    let mut items = Vec::new();
+    #[test]
+    fn undefined_names_are_reported_with_suggestions() {
+        assert_eq!(
+            errors("let count = 1\nprint(cuont)\nprnt(count)\nmissing()"),
+            vec![
+                "Undefined variable 'cuont'. Did you mean 'count'?",
+                "Undefined function 'prnt'. Did you mean 'print'?",
+                "Undefined function 'missing'",
+            ]
+        );
+        assert_eq!(
+            errors("fn f() { let inner = 1 }\nprint(inner)"),
+            vec!["Undefined variable 'inner'"]
+        );
+    }
 
-    let default_props = [
-        ("length", "property", CompletionItemKind::PROPERTY),
-        ("name", "property", CompletionItemKind::PROPERTY),
-        ("toString", "fn()->String", CompletionItemKind::METHOD),
-        ("valueOf", "fn()->any", CompletionItemKind::METHOD),
-    ];
+    #[test]
+    fn declared_and_builtin_names_are_defined() {
+        let source = "import { helper } from \"lib\"\n\
+                      let t = Time.now()\n\
+                      let d = Date()\n\
+                      let y = d.getFullYear()\n\
+                      print([helper(t), len(\"abc\"), parseInt(\"1\")])\n\
+                      later()\n\
+                      fn later() {}";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
 
-    for &(name, detail, kind) in &default_props {
-        items.push(CompletionItem {
-            label: name.to_string(),
-            kind: Some(kind),
-            detail: Some(detail.to_string()),
-            ..Default::default()
-        });
+    #[test]
+    fn this_is_bound_in_methods() {
+        let source = "class Counter {\n\
+                      count: int = 0\n\
+                      fn add() { this.count += 1 }\n\
+                      fn get(): int { return this.count }\n\
+                      }";
+        assert_eq!(errors(source), Vec::<String>::new());
+
+        assert_eq!(
+            errors("class C {\n  count: int\n  fn f() { print(this.cuont) }\n}\nprint(this)"),
+            vec![
+                "Undefined variable 'this'",
+                "Unknown member 'cuont' on class 'C'. Did you mean 'count'?",
+            ]
+        );
     }
 
-    items
+    #[test]
+    fn whole_module_imports_may_define_any_name() {
+        assert_eq!(
+            errors("import \"lib\"\nprint(fromLib(x))"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn recursive_functions_return_what_their_base_case_returns() {
+        assert_eq!(
+            errors(
+                "fn f(n: int) { if n > 0 { return 1 }\n return f(n - 1) }\n\
+                 let x: int = f(3)\n\
+                 fn even(n: int) { if n == 0 { return \"yes\" }\n return odd(n - 1) }\n\
+                 fn odd(n: int) { if n == 0 { return \"no\" }\n return even(n - 1) }\n\
+                 let s: string = even(4)"
+            ),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            errors(
+                "fn f(n: int) { if n > 0 { return 1 }\n return f(n - 1) }\nlet s: string = f(3)"
+            ),
+            vec!["Type mismatch: expected 'string', found 'int'"]
+        );
+    }
+
+    #[test]
+    fn whole_module_imports_keep_typos_reported() {
+        assert_eq!(
+            errors(
+                "import \"lib\"\n\
+                 let count = 1\n\
+                 pritn(count)\n\
+                 print(cuont)\n\
+                 fromLib(count)"
+            ),
+            vec![
+                "Undefined function 'pritn'. Did you mean 'print'?",
+                "Undefined variable 'cuont'. Did you mean 'count'?",
+            ]
+        );
+    }
+
+    #[test]
+    fn initializers_and_assignments_must_match_the_declared_type() {
+        assert_eq!(
+            errors(
+                "let a: int = \"one\"\n\
+                 var b = 1\n\
+                 b = \"two\"\n\
+                 struct P { x: int }\n\
+                 let p = P()\n\
+                 p.x = true\n\
+                 let c: int[] = [1, \"x\"]"
+            ),
+            vec![
+                "Type mismatch: expected 'int', found 'string'",
+                "Type mismatch: expected 'int', found 'string'",
+                "Type mismatch: expected 'int', found 'bool'",
+                "Type mismatch: expected 'int[]', found '(int | string)[]'",
+            ]
+        );
+    }
+
+    #[test]
+    fn compatible_values_are_accepted() {
+        let source = "let a: float = 1\n\
+                      let b: int? = null\n\
+                      let c: int | string = \"x\"\n\
+                      var d = 1\n\
+                      d += 2\n\
+                      let e: any = [1]\n\
+                      let f: string[] = []";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn operands_must_fit_the_operator() {
+        assert_eq!(
+            errors("let a = 1 - \"x\"\nlet b = true && 1\nlet c = 1..2.5\nlet d = [1] < 2"),
+            vec![
+                "Operator '-' expects 'number' operands, found 'int' and 'string'",
+                "Operator '&&' expects 'bool' or optional operands, found 'bool' and 'int'",
+                "Operator '..' expects 'int' operands, found 'int' and 'float'",
+                "Operator '<' expects 'number' or 'string' operands, found 'int[]' and 'int'",
+            ]
+        );
+        assert_eq!(
+            errors("let a = \"n: \" + 1\nlet b = 1.5 * 2\nlet c = \"a\" < \"b\""),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn calls_must_match_the_signature() {
+        assert_eq!(
+            errors(
+                "fn add(a: int, b: int): int { return a + b }\n\
+                 add(1)\n\
+                 add(1, 2, 3)\n\
+                 add(1, \"2\")\n\
+                 let n = 5\n\
+                 n()\n\
+                 \"s\".substring(1)\n\
+                 Time.sleep(\"1\")"
+            ),
+            vec![
+                "Function 'add' expects 2 arguments, found 1",
+                "Function 'add' expects 2 arguments, found 3",
+                "Type mismatch: expected 'int', found 'string'",
+                "Cannot call 'n' of type 'int', expected a function",
+                "Function 'substring' expects 2 arguments, found 1",
+                "Type mismatch: expected 'int', found 'string'",
+            ]
+        );
+    }
+
+    #[test]
+    fn matching_calls_are_accepted() {
+        let source = "fn greet(name: string, times: int) { }\n\
+                      fn any_args(a, b) { }\n\
+                      greet(\"x\", 2)\n\
+                      any_args(1, \"y\")\n\
+                      let f = fn(x: float) -> float { return x }\n\
+                      f(1)\n\
+                      struct P { x: int }\n\
+                      let p = P(1, 2, 3)\n\
+                      [1].push(2)";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn functions_must_return_on_every_path() {
+        assert_eq!(
+            errors(
+                "fn a(x: bool): int { if x { return 1 } }\n\
+                 fn b(): int { while true { break } }\n\
+                 fn c(): int { for (let i = 0; i < 3; i += 1) { return i } }\n\
+                 fn d(): int { }"
+            ),
+            vec![
+                "Function 'a' must return a value of type 'int' on every path",
+                "Function 'b' must return a value of type 'int' on every path",
+                "Function 'c' must return a value of type 'int' on every path",
+                "Function 'd' must return a value of type 'int' on every path",
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_that_never_fall_through_return() {
+        let source = "fn a(x: bool): int { if x { return 1 } else { return 2 } }\n\
+                      fn b(): int { for (;;) { return 1 } }\n\
+                      fn c(): int { while true { } }\n\
+                      fn d(): int { if true { return 1 } }\n\
+                      fn e(x: bool): int { while true { if x { return 1 } } }\n\
+                      fn f(): int { { return 1 } }\n\
+                      fn g() { }";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn returned_values_must_match_the_return_type() {
+        assert_eq!(
+            errors(
+                "fn a(): int { return \"x\" }\n\
+                 fn b() { return 1 }\n\
+                 fn c(): void { return 1 }\n\
+                 fn d(): string { return }\n\
+                 fn e(): int? { return null }"
+            ),
+            vec![
+                "Type mismatch: expected 'int', found 'string'",
+                "Cannot return a value of type 'int' from a function returning 'void'",
+                "Expected a return value of type 'string'",
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_values_must_be_checked_for_null() {
+        assert_eq!(
+            errors(
+                "let s: string? = null\n\
+                 print(s.length)\n\
+                 let t: string = s\n\
+                 let u: string = null\n\
+                 let f: (fn() -> int)? = null\n\
+                 f()"
+            ),
+            vec![
+                "Cannot access 'length' on a value of type 'string?' that may be null",
+                "Type mismatch: expected 'string', found 'string?'",
+                "Type mismatch: expected 'string', found 'null'",
+                "Cannot call 'f' of type '(fn() -> int)?', it may be null",
+            ]
+        );
+        assert_eq!(
+            errors("let s: string? = \"a\"\nlet n: int? = null\nlet t: string? = s"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn null_checks_narrow_optional_values() {
+        let source = "fn f(s: string?, t: string?): int {\n\
+                      if s != null { print(s.length) }\n\
+                      if null != t { print(t.length) }\n\
+                      while s != null { print(s.length) }\n\
+                      if s == null { return 0 }\n\
+                      return s.length\n\
+                      }";
+        assert_eq!(errors(source), Vec::<String>::new());
+
+        assert_eq!(
+            errors(
+                "fn f(s: string?): int {\n\
+                 if s == null { print(1) }\n\
+                 return s.length\n\
+                 }\n\
+                 fn g(s: string?) {\n\
+                 var t: string? = s\n\
+                 if t != null {\n\
+                 t = null\n\
+                 print(t.length)\n\
+                 }\n\
+                 }"
+            ),
+            vec![
+                "Cannot access 'length' on a value of type 'string?' that may be null",
+                "Cannot access 'length' on a value of type 'string?' that may be null",
+            ]
+        );
+    }
+
+    #[test]
+    fn union_values_must_fit_every_use() {
+        assert_eq!(
+            errors(
+                "let x: int | string = 1\n\
+                 let y: int | string = \"a\"\n\
+                 let z: int | string = true\n\
+                 let n: int = x\n\
+                 print(x.length)\n\
+                 struct A { a: int }\n\
+                 struct B { b: int }\n\
+                 fn f(v: A | B) { print(v.a) }"
+            ),
+            vec![
+                "Type mismatch: expected 'int | string', found 'bool'",
+                "Type mismatch: expected 'int', found 'int | string'",
+                "Member 'length' does not exist on every variant of 'int | string': missing on 'int'",
+                "Member 'a' does not exist on every variant of 'A | B': missing on 'B'",
+            ]
+        );
+        assert_eq!(
+            errors(
+                "let x: string | int[] = \"a\"\n\
+                 print(x.length)\n\
+                 let y: (int | string)? = null\n\
+                 let z: int | string | null = y"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn typeof_checks_narrow_unions() {
+        let source = "fn f(x: int | string): int {\n\
+                      if typeof(x) == \"string\" { print(x.length) } else { let n: int = x }\n\
+                      if typeof(x) != \"int\" { print(x.length) }\n\
+                      if typeof(x) == \"string\" { return x.length }\n\
+                      return x\n\
+                      }";
+        assert_eq!(errors(source), Vec::<String>::new());
+
+        assert_eq!(
+            errors(
+                "fn f(x: int | string) {\n\
+                 if typeof(x) == \"int\" { let s: string = x }\n\
+                 }"
+            ),
+            vec!["Type mismatch: expected 'string', found 'int'"]
+        );
+    }
+
+    #[test]
+    fn assignments_to_constants_are_reported() {
+        assert_eq!(
+            errors(
+                "const a = 1\n\
+                 a = 2\n\
+                 a += 1\n\
+                 struct P { const x: int, y: int }\n\
+                 let p = P()\n\
+                 p.x = 1\n\
+                 class C {\n\
+                 const id: int = 1\n\
+                 fn f() { this.id = 2 }\n\
+                 }"
+            ),
+            vec![
+                "Cannot assign to constant 'a'",
+                "Cannot assign to constant 'a'",
+                "Cannot assign to constant field 'x'",
+                "Cannot assign to constant field 'id'",
+            ]
+        );
+        assert_eq!(
+            errors(
+                "let b = 1\n\
+                 b = 2\n\
+                 var c = 1\n\
+                 c += 1\n\
+                 struct P { y: int }\n\
+                 let p = P()\n\
+                 p.y = 2\n\
+                 const arr = [1]\n\
+                 arr[0] = 2"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    /// The labels completed at the `|` in `source`.
+    fn completions(source: &str) -> Vec<String> {
+        let offset = source.find('|').unwrap();
+        let content = source.replacen('|', "", 1);
+        let position = utils::offset_to_position(&content, offset).unwrap();
+
+        let type_checker = std::sync::Arc::new(BurnTypeChecker::new());
+        let analyzer = crate::analyzer::BurnAnalyzer::new(type_checker.clone());
+        analyzer.open_document("file:///completion.bn", content);
+        let document = analyzer.get_document("file:///completion.bn").unwrap();
+
+        get_completions(&document, position, &type_checker)
+            .into_iter()
+            .map(|item| item.label)
+            .collect()
+    }
+
+    #[test]
+    fn members_of_the_receiver_type_are_completed() {
+        let point = "struct Point { x: int, y: int }\nlet p = Point()\n";
+        assert_eq!(completions(&format!("{}p.|", point)), vec!["x", "y"]);
+        assert_eq!(
+            completions(&format!("{}print(p.x|)", point)),
+            vec!["x", "y"]
+        );
+        assert_eq!(
+            completions("let s = \"abc\"\nlet n = s.toUpperCase().|"),
+            completions("\"\".|")
+        );
+        assert_eq!(completions("let s = \"\"\ns.|")[0], "length");
+        assert_eq!(
+            completions("class C {\n  count: int\n  fn f() { this.| }\n}"),
+            vec!["count", "f"]
+        );
+        assert_eq!(
+            completions("class Node {\n  next: Node?\n  value: int\n}\nlet n = Node()\nn.next.|"),
+            vec!["next", "value"]
+        );
+    }
+
+    #[test]
+    fn unknown_receivers_complete_nothing() {
+        assert_eq!(completions("let a = missing\na.|"), Vec::<String>::new());
+        assert_eq!(completions("let n = 1\nn.|"), Vec::<String>::new());
+        assert_eq!(completions("fn f(x) { x.| }"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn other_positions_complete_keywords_and_builtins() {
+        let labels = completions("let count = 1\nco|");
+        assert!(labels.iter().any(|label| label == "fn"));
+        assert!(labels.iter().any(|label| label == "print"));
+        assert!(!labels.iter().any(|label| label == "length"));
+    }
+
+    #[test]
+    fn optional_values_are_tested_like_conditions() {
+        let source = "fn f(s: string?, n: int?) {\n\
+                      if s && s.length > 0 { print(s.length) }\n\
+                      if !s || s.length == 0 { return }\n\
+                      print(s.length)\n\
+                      let b: bool = n && true\n\
+                      }";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn anything_may_be_compared_with_null() {
+        let source = "let x: int = 1\n\
+                      if x != null { print(x) }\n\
+                      if null == x { print(x) }\n\
+                      let p = [1] == null";
+        assert_eq!(errors(source), Vec::<String>::new());
+        assert_eq!(
+            errors("let p = 1 == \"1\""),
+            vec!["Operator '==' expects comparable operands, found 'int' and 'string'"]
+        );
+    }
 }
@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{LazyLock, RwLock};
+
+use crate::ast::{Span, Type};
+
+/// A semantic type. Types are interned, so a `Ty` is a small copyable
+/// handle and two handles are equal exactly when the types are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ty(u32);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TyKind {
+    Int,
+    Float,
+    String,
+    Bool,
+    Null,
+    Void,
+    /// A value nothing is known about; it is compatible with every type.
+    Any,
+    /// The type of no value, e.g. what a `return` evaluates to.
+    Never,
+    /// An object literal, which stands in for an instance of any named type.
+    Object,
+    Struct(Nominal),
+    Class(Nominal),
+    /// A builtin or standard library type such as `Date` or `Http`, or a
+    /// name declared in another file.
+    Named(String),
+    Array(Ty),
+    Optional(Ty),
+    Union(Vec<Ty>),
+    Function {
+        params: Vec<Ty>,
+        return_type: Ty,
+    },
+}
+
+/// Where a struct or class is declared. Two declarations of the same name
+/// in different files are different types.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Nominal {
+    pub name: String,
+    pub uri: String,
+    pub span: Span,
+}
+
+/// Slots are never reused: a released type keeps its slot as `any`, so a
+/// handle that outlives it cannot turn into some newer type.
+struct Interner {
+    kinds: Vec<TyKind>,
+    ids: HashMap<TyKind, Ty>,
+}
+
+impl Interner {
+    fn intern(&mut self, kind: TyKind) -> Ty {
+        if let Some(&ty) = self.ids.get(&kind) {
+            return ty;
+        }
+        self.kinds.push(kind.clone());
+        let ty = Ty(self.kinds.len() as u32 - 1);
+        self.ids.insert(kind, ty);
+        ty
+    }
+
+    fn release(&mut self, uri: &str) {
+        // Parts are interned before the types built from them, so one pass
+        // in slot order sees every part decided before what uses it.
+        let mut released = vec![false; self.kinds.len()];
+        for slot in 0..self.kinds.len() {
+            let is_released = |ty: &Ty| released[ty.0 as usize];
+            released[slot] = match &self.kinds[slot] {
+                TyKind::Struct(nominal) | TyKind::Class(nominal) => nominal.uri == uri,
+                TyKind::Array(inner) | TyKind::Optional(inner) => is_released(inner),
+                TyKind::Union(members) => members.iter().any(is_released),
+                TyKind::Function {
+                    params,
+                    return_type,
+                } => is_released(return_type) || params.iter().any(is_released),
+                _ => false,
+            };
+            if released[slot] {
+                let kind = std::mem::replace(&mut self.kinds[slot], TyKind::Any);
+                self.ids.remove(&kind);
+            }
+        }
+    }
+}
+
+/// Shared by every document. The primitives come first, in the order of the
+/// constants on `Ty`.
+static INTERNER: LazyLock<RwLock<Interner>> = LazyLock::new(|| {
+    let mut interner = Interner {
+        kinds: Vec::new(),
+        ids: HashMap::new(),
+    };
+    for kind in [
+        TyKind::Int,
+        TyKind::Float,
+        TyKind::String,
+        TyKind::Bool,
+        TyKind::Null,
+        TyKind::Void,
+        TyKind::Any,
+        TyKind::Never,
+        TyKind::Object,
+    ] {
+        interner.intern(kind);
+    }
+    RwLock::new(interner)
+});
+
+/// Frees the types of the structs and classes declared in `uri` and every
+/// type built from them, for when a document is reanalysed or closed. A
+/// handle kept past this, e.g. by a request still answering from the old
+/// document, reads as `any`.
+pub fn release(uri: &str) {
+    INTERNER.write().unwrap().release(uri);
+}
+
+impl Ty {
+    pub const INT: Ty = Ty(0);
+    pub const FLOAT: Ty = Ty(1);
+    pub const STRING: Ty = Ty(2);
+    pub const BOOL: Ty = Ty(3);
+    pub const NULL: Ty = Ty(4);
+    pub const VOID: Ty = Ty(5);
+    pub const ANY: Ty = Ty(6);
+    pub const NEVER: Ty = Ty(7);
+    pub const OBJECT: Ty = Ty(8);
+
+    pub fn intern(kind: TyKind) -> Ty {
+        if let Some(&ty) = INTERNER.read().unwrap().ids.get(&kind) {
+            return ty;
+        }
+        INTERNER.write().unwrap().intern(kind)
+    }
+
+    pub fn kind(self) -> TyKind {
+        INTERNER.read().unwrap().kinds[self.0 as usize].clone()
+    }
+
+    pub fn named(name: &str) -> Ty {
+        Ty::intern(TyKind::Named(name.to_string()))
+    }
+
+    pub fn array(element: Ty) -> Ty {
+        Ty::intern(TyKind::Array(element))
+    }
+
+    pub fn optional(inner: Ty) -> Ty {
+        Ty::intern(TyKind::Optional(inner))
+    }
+
+    pub fn function(params: Vec<Ty>, return_type: Ty) -> Ty {
+        Ty::intern(TyKind::Function {
+            params,
+            return_type,
+        })
+    }
+
+    /// Merges the types of several values into one: a single type when they
+    /// all agree, a flat union otherwise, `any` as soon as one of them is
+    /// unknown and `never` when there are none.
+    pub fn union<I>(types: I) -> Ty
+    where
+        I: IntoIterator<Item = Ty>,
+    {
+        let mut members = Vec::new();
+        for ty in types {
+            let flattened = match ty.kind() {
+                TyKind::Union(members) => members,
+                _ => vec![ty],
+            };
+            for ty in flattened {
+                if ty == Ty::ANY {
+                    return Ty::ANY;
+                }
+                if ty != Ty::NEVER && !members.contains(&ty) {
+                    members.push(ty);
+                }
+            }
+        }
+
+        match members.len() {
+            0 => Ty::NEVER,
+            1 => members[0],
+            _ => Ty::intern(TyKind::Union(members)),
+        }
+    }
+
+    /// Lowers a type written in the source. `lookup` resolves other names to
+    /// the struct or class they declare; anything else becomes a named type.
+    pub fn from_annotation(typ: &Type, lookup: &dyn Fn(&str) -> Option<Ty>) -> Ty {
+        match typ {
+            Type::Basic { name, .. } => match name.as_str() {
+                "int" => Ty::INT,
+                "float" | "number" => Ty::FLOAT,
+                "string" => Ty::STRING,
+                "bool" => Ty::BOOL,
+                "null" => Ty::NULL,
+                "void" => Ty::VOID,
+                "any" => Ty::ANY,
+                "never" => Ty::NEVER,
+                "object" => Ty::OBJECT,
+                name => lookup(name).unwrap_or_else(|| Ty::named(name)),
+            },
+            Type::Array(element) => Ty::array(Ty::from_annotation(element, lookup)),
+            Type::Optional(inner) => Ty::optional(Ty::from_annotation(inner, lookup)),
+            Type::Union(types) => Ty::union(types.iter().map(|ty| Ty::from_annotation(ty, lookup))),
+            Type::Function {
+                params,
+                return_type,
+            } => Ty::function(
+                params
+                    .iter()
+                    .map(|param| Ty::from_annotation(param, lookup))
+                    .collect(),
+                Ty::from_annotation(return_type, lookup),
+            ),
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        self == Ty::INT || self == Ty::FLOAT
+    }
+
+    /// Whether a value of this type may be `null`.
+    pub fn is_nullable(self) -> bool {
+        match self.kind() {
+            TyKind::Null | TyKind::Optional(_) => true,
+            TyKind::Union(members) => members.iter().any(|member| member.is_nullable()),
+            _ => false,
+        }
+    }
+
+    /// This type without `null`, as it is known to be once a value was
+    /// checked against `null`.
+    pub fn non_null(self) -> Ty {
+        match self.kind() {
+            TyKind::Null => Ty::NEVER,
+            TyKind::Optional(inner) => inner.non_null(),
+            TyKind::Union(members) => Ty::union(members.into_iter().map(Ty::non_null)),
+            _ => self,
+        }
+    }
+
+    /// The types a value of this type may have at runtime: the members of a
+    /// union, the inner type and `null` of an optional, or the type itself.
+    pub fn variants(self) -> Vec<Ty> {
+        match self.kind() {
+            TyKind::Union(members) => members.into_iter().flat_map(Ty::variants).collect(),
+            TyKind::Optional(inner) => {
+                let mut variants = inner.variants();
+                variants.push(Ty::NULL);
+                variants
+            }
+            _ => vec![self],
+        }
+    }
+
+    /// What `typeof` returns for a value of this type, if that is known.
+    pub fn typeof_name(self) -> Option<String> {
+        let name = match self.kind() {
+            TyKind::Int => "int",
+            TyKind::Float => "float",
+            TyKind::String => "string",
+            TyKind::Bool => "bool",
+            TyKind::Null => "null",
+            TyKind::Array(_) => "array",
+            TyKind::Function { .. } => "function",
+            TyKind::Struct(nominal) | TyKind::Class(nominal) => return Some(nominal.name),
+            TyKind::Named(name) => return Some(name),
+            _ => return None,
+        };
+        Some(name.to_string())
+    }
+
+    /// What indexing into or iterating over a value of this type yields.
+    pub fn element(self) -> Ty {
+        match self.kind() {
+            TyKind::Array(element) => element,
+            TyKind::String => Ty::STRING,
+            _ => Ty::ANY,
+        }
+    }
+
+    /// Whether `accepts` holds for this type, or for every member of a
+    /// union. Unknown types are accepted.
+    pub fn all_members(self, accepts: impl Fn(Ty) -> bool + Copy) -> bool {
+        match self.kind() {
+            TyKind::Union(members) => members.iter().all(|member| member.all_members(accepts)),
+            _ => self == Ty::ANY || accepts(self),
+        }
+    }
+
+    /// Whether a value of this type may be stored where a value of type
+    /// `expected` is declared. Unknown types are assignable both ways.
+    pub fn is_assignable_to(self, expected: Ty) -> bool {
+        if self == expected || self == Ty::ANY || expected == Ty::ANY || self == Ty::NEVER {
+            return true;
+        }
+
+        match (expected.kind(), self.kind()) {
+            (_, TyKind::Union(members)) => members
+                .iter()
+                .all(|member| member.is_assignable_to(expected)),
+            // Either a value or null, like a union of the two.
+            (_, TyKind::Optional(actual)) => {
+                Ty::NULL.is_assignable_to(expected) && actual.is_assignable_to(expected)
+            }
+            (TyKind::Union(members), _) => {
+                members.iter().any(|&member| self.is_assignable_to(member))
+            }
+            (TyKind::Optional(_), TyKind::Null) => true,
+            (TyKind::Optional(expected), _) => self.is_assignable_to(expected),
+            (TyKind::Array(expected), TyKind::Array(actual)) => actual.is_assignable_to(expected),
+            (
+                TyKind::Function {
+                    params: expected_params,
+                    return_type: expected_return,
+                },
+                TyKind::Function {
+                    params: actual_params,
+                    return_type: actual_return,
+                },
+            ) => {
+                expected_params.len() == actual_params.len()
+                    && expected_params
+                        .iter()
+                        .zip(&actual_params)
+                        .all(|(&expected, &actual)| expected.is_assignable_to(actual))
+                    && (expected_return == Ty::VOID
+                        || actual_return.is_assignable_to(expected_return))
+            }
+            (TyKind::Float, TyKind::Int) => true,
+            (TyKind::Struct(_) | TyKind::Class(_) | TyKind::Named(_), TyKind::Object) => true,
+            _ => false,
+        }
+    }
+
+    /// Writes the type where it is part of a larger one, parenthesising what
+    /// would otherwise bind differently when read back.
+    fn fmt_operand(self, f: &mut fmt::Formatter<'_>, wrap_functions: bool) -> fmt::Result {
+        match self.kind() {
+            TyKind::Union(_) => write!(f, "({})", self),
+            TyKind::Function { .. } if wrap_functions => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind() {
+            TyKind::Int => write!(f, "int"),
+            TyKind::Float => write!(f, "float"),
+            TyKind::String => write!(f, "string"),
+            TyKind::Bool => write!(f, "bool"),
+            TyKind::Null => write!(f, "null"),
+            TyKind::Void => write!(f, "void"),
+            TyKind::Any => write!(f, "any"),
+            TyKind::Never => write!(f, "never"),
+            TyKind::Object => write!(f, "object"),
+            TyKind::Struct(nominal) | TyKind::Class(nominal) => write!(f, "{}", nominal.name),
+            TyKind::Named(name) => write!(f, "{}", name),
+            TyKind::Array(element) => {
+                element.fmt_operand(f, true)?;
+                write!(f, "[]")
+            }
+            TyKind::Optional(inner) => {
+                inner.fmt_operand(f, true)?;
+                write!(f, "?")
+            }
+            TyKind::Union(members) => {
+                let members: Vec<String> = members.iter().map(|ty| ty.to_string()).collect();
+                write!(f, "{}", members.join(" | "))
+            }
+            TyKind::Function {
+                params,
+                return_type,
+            } => {
+                let params: Vec<String> = params.iter().map(|ty| ty.to_string()).collect();
+                write!(f, "fn({}) -> ", params.join(", "))?;
+                return_type.fmt_operand(f, false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(uri: &str) -> TyKind {
+        TyKind::Struct(Nominal {
+            name: "Point".to_string(),
+            uri: uri.to_string(),
+            span: Span::new(7, 12),
+        })
+    }
+
+    #[test]
+    fn same_name_in_two_files_is_two_types() {
+        let a = Ty::intern(point("file:///same_name_a.bn"));
+        let b = Ty::intern(point("file:///same_name_b.bn"));
+        assert_ne!(a, b);
+        assert_eq!(a, Ty::intern(point("file:///same_name_a.bn")));
+        assert_eq!(a.to_string(), "Point");
+        assert!(!a.is_assignable_to(b));
+        assert!(Ty::OBJECT.is_assignable_to(b));
+    }
+
+    #[test]
+    fn release_frees_every_type_built_from_the_file() {
+        let mut interner = Interner {
+            kinds: Vec::new(),
+            ids: HashMap::new(),
+        };
+        let int = interner.intern(TyKind::Int);
+        let a = interner.intern(point("file:///a.bn"));
+        let b = interner.intern(point("file:///b.bn"));
+        let list = interner.intern(TyKind::Array(a));
+        let function = interner.intern(TyKind::Function {
+            params: vec![int],
+            return_type: list,
+        });
+        let union = interner.intern(TyKind::Union(vec![int, b]));
+
+        interner.release("file:///a.bn");
+        for ty in [a, list, function] {
+            assert_eq!(interner.kinds[ty.0 as usize], TyKind::Any);
+        }
+        assert_eq!(interner.ids.len(), 3);
+        assert_eq!(interner.intern(TyKind::Union(vec![int, b])), union);
+
+        // Released slots stay `any`, whatever is interned after them.
+        let c = interner.intern(point("file:///c.bn"));
+        let a_again = interner.intern(point("file:///a.bn"));
+        assert_eq!(c.0, 6);
+        assert_eq!(a_again.0, 7);
+        assert_eq!(interner.kinds[a.0 as usize], TyKind::Any);
+    }
+
+    #[test]
+    fn unions_are_flat_and_deduplicated() {
+        let union = Ty::union([Ty::INT, Ty::union([Ty::STRING, Ty::INT]), Ty::NEVER]);
+        assert_eq!(union.to_string(), "int | string");
+        assert_eq!(Ty::union([Ty::INT, Ty::ANY]), Ty::ANY);
+        assert_eq!(Ty::union([]), Ty::NEVER);
+        assert_eq!(Ty::union([Ty::FLOAT]), Ty::FLOAT);
+    }
+
+    #[test]
+    fn assignability() {
+        let int_or_null = Ty::union([Ty::INT, Ty::NULL]);
+        let cases = [
+            (Ty::INT, Ty::FLOAT, true),
+            (Ty::FLOAT, Ty::INT, false),
+            (Ty::NULL, Ty::optional(Ty::INT), true),
+            (
+                Ty::optional(Ty::union([Ty::INT, Ty::STRING])),
+                Ty::union([Ty::INT, Ty::STRING, Ty::NULL]),
+                true,
+            ),
+            (
+                Ty::optional(Ty::INT),
+                Ty::union([Ty::INT, Ty::STRING]),
+                false,
+            ),
+            (Ty::INT, Ty::optional(Ty::INT), true),
+            (Ty::optional(Ty::INT), Ty::INT, false),
+            (int_or_null, Ty::optional(Ty::INT), true),
+            (Ty::INT, Ty::union([Ty::INT, Ty::STRING]), true),
+            (Ty::union([Ty::INT, Ty::STRING]), Ty::INT, false),
+            (Ty::array(Ty::INT), Ty::array(Ty::FLOAT), true),
+            (Ty::array(Ty::STRING), Ty::array(Ty::INT), false),
+            (Ty::ANY, Ty::INT, true),
+            (Ty::STRING, Ty::ANY, true),
+            (
+                Ty::function(vec![Ty::FLOAT], Ty::INT),
+                Ty::function(vec![Ty::INT], Ty::VOID),
+                true,
+            ),
+            (
+                Ty::function(vec![Ty::INT], Ty::INT),
+                Ty::function(vec![Ty::FLOAT], Ty::INT),
+                false,
+            ),
+        ];
+        for (actual, expected, assignable) in cases {
+            assert_eq!(
+                actual.is_assignable_to(expected),
+                assignable,
+                "{} to {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn nested_types_print_back_the_same() {
+        let function = Ty::function(vec![Ty::INT], Ty::union([Ty::INT, Ty::NULL]));
+        assert_eq!(function.to_string(), "fn(int) -> (int | null)");
+        assert_eq!(
+            Ty::array(function).to_string(),
+            "(fn(int) -> (int | null))[]"
+        );
+        assert_eq!(
+            Ty::optional(Ty::union([Ty::INT, Ty::STRING])).to_string(),
+            "(int | string)?"
+        );
+        assert_eq!(Ty::optional(Ty::INT).non_null(), Ty::INT);
+    }
+}
@@ -1,9 +1,12 @@
 use log::error;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::{Position, Range};
 use url::Url;
 
+use crate::ast::Span;
+
 pub fn get_path_from_uri(uri: &Url) -> String {
     match uri.to_file_path() {
         Ok(path) => path.to_string_lossy().into_owned(),
@@ -36,27 +39,17 @@ pub fn get_burn_version() -> String {
 }
 
 pub fn position_to_offset(text: &str, position: Position) -> Result<usize> {
-    let lines: Vec<&str> = text.lines().collect();
-
-    if position.line as usize >= lines.len() {
-        error!("Invalid line position: {}", position.line);
-        return Err(tower_lsp::jsonrpc::Error {
-            code: tower_lsp::jsonrpc::ErrorCode::InvalidParams,
-            message: "Invalid position".into(),
-            data: None,
-        });
-    }
-
-    let mut offset = 0;
-    for i in 0..position.line as usize {
-        offset += lines[i].len() + 1;
+    match LineIndex::new(text).offset(position) {
+        Some(offset) => Ok(offset),
+        None => {
+            error!("Invalid line position: {}", position.line);
+            Err(tower_lsp::jsonrpc::Error {
+                code: tower_lsp::jsonrpc::ErrorCode::InvalidParams,
+                message: "Invalid position".into(),
+                data: None,
+            })
+        }
     }
-
-    let line = lines[position.line as usize];
-    let column = position.character as usize;
-    let column = column.min(line.len());
-
-    Ok(offset + column)
 }
 
 pub fn offset_to_position(text: &str, offset: usize) -> Result<Position> {
@@ -69,23 +62,91 @@ pub fn offset_to_position(text: &str, offset: usize) -> Result<Position> {
         });
     }
 
-    let mut line = 0;
-    let mut char_count = 0;
+    Ok(LineIndex::new(text).position(offset))
+}
+
+/// Converts between byte offsets and LSP positions, whose `character` is
+/// counted in UTF-16 code units.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    /// Non-ASCII characters per line as (byte column, UTF-8 length, UTF-16 length).
+    wide_chars: HashMap<usize, Vec<(usize, usize, usize)>>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut line_starts = vec![0];
+        let mut wide_chars: HashMap<usize, Vec<(usize, usize, usize)>> = HashMap::new();
+
+        for (offset, c) in text.char_indices() {
+            if c == '\n' {
+                line_starts.push(offset + 1);
+            } else if !c.is_ascii() {
+                let line = line_starts.len() - 1;
+                let column = offset - line_starts[line];
+                wide_chars
+                    .entry(line)
+                    .or_default()
+                    .push((column, c.len_utf8(), c.len_utf16()));
+            }
+        }
+
+        LineIndex {
+            line_starts,
+            wide_chars,
+            len: text.len(),
+        }
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.len);
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let mut column = offset - self.line_starts[line];
 
-    for (i, c) in text.char_indices() {
-        if i >= offset {
-            break;
+        if let Some(chars) = self.wide_chars.get(&line) {
+            let byte_column = column;
+            for &(start, utf8_len, utf16_len) in chars {
+                if start >= byte_column {
+                    break;
+                }
+                column = column + utf16_len - utf8_len;
+            }
         }
 
-        if c == '\n' {
-            line += 1;
-            char_count = 0;
-        } else {
-            char_count += 1;
+        Position::new(line as u32, column as u32)
+    }
+
+    /// Byte offset of a position, or `None` if the line does not exist.
+    /// Characters past the end of the line are clamped to it.
+    pub fn offset(&self, position: Position) -> Option<usize> {
+        let line = position.line as usize;
+        let line_start = *self.line_starts.get(line)?;
+        let line_end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.len, |&next| next - 1);
+
+        let mut remaining = position.character as usize;
+        let mut column = 0;
+        for &(start, utf8_len, utf16_len) in self.wide_chars.get(&line).into_iter().flatten() {
+            if remaining <= start - column {
+                break;
+            }
+            remaining = remaining.saturating_sub(start - column + utf16_len);
+            column = start + utf8_len;
         }
+
+        Some((line_start + column + remaining).min(line_end))
     }
 
-    Ok(Position::new(line as u32, char_count as u32))
+    pub fn range(&self, span: Span) -> Range {
+        Range {
+            start: self.position(span.start),
+            end: self.position(span.end),
+        }
+    }
 }
 
 pub fn create_range(start: Position, end: Position) -> Range {
@@ -126,7 +187,7 @@ pub fn find_word_at_offset(text: &str, offset: usize) -> Option<(usize, usize)>
     let text_after = &text[offset..];
     let end_offset = text_after
         .find(|c: char| !(c.is_alphanumeric() || c == '_'))
-        .unwrap_or_else(|| text_after.len());
+        .unwrap_or(text_after.len());
     let end = offset + end_offset;
 
     if end > start {
@@ -135,3 +196,116 @@ pub fn find_word_at_offset(text: &str, offset: usize) -> Option<(usize, usize)>
         None
     }
 }
+
+/// Edit distance between two identifiers, counted in characters, where
+/// swapping two adjacent characters counts as a single edit (`pritn`).
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+/// The candidate closest to `name`, if any is close enough to be a likely
+/// typo: at most one edit per three characters, and never a full rewrite.
+pub fn closest_match<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let limit = (name.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .filter(|&candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, candidate)| {
+            distance <= limit && distance < candidate.chars().count().max(name.chars().count())
+        })
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_count_utf16_code_units() {
+        let text = "ab\n𝄞x é y\nz";
+        let index = LineIndex::new(text);
+
+        assert_eq!(index.position(0), Position::new(0, 0));
+        assert_eq!(index.position(3), Position::new(1, 0));
+        // `𝄞` is four bytes but two UTF-16 code units.
+        assert_eq!(index.position(7), Position::new(1, 2));
+        assert_eq!(index.position(9), Position::new(1, 4));
+        // `é` is two bytes but one code unit.
+        assert_eq!(index.position(11), Position::new(1, 5));
+        assert_eq!(index.position(text.len()), Position::new(2, 1));
+    }
+
+    #[test]
+    fn offsets_round_trip_through_positions() {
+        let text = "ab\n𝄞x é y\nz";
+        let index = LineIndex::new(text);
+        for offset in (0..=text.len()).filter(|&offset| text.is_char_boundary(offset)) {
+            assert_eq!(index.offset(index.position(offset)), Some(offset));
+        }
+    }
+
+    #[test]
+    fn offsets_clamp_to_the_line() {
+        let index = LineIndex::new("ab\n𝄞x\n");
+        assert_eq!(index.offset(Position::new(0, 100)), Some(2));
+        assert_eq!(index.offset(Position::new(1, 100)), Some(8));
+        assert_eq!(index.offset(Position::new(2, 0)), Some(9));
+        assert_eq!(index.offset(Position::new(3, 0)), None);
+    }
+
+    #[test]
+    fn spans_map_to_ranges() {
+        let text = "let größe = 1\nprint(größe)";
+        let result = crate::parser::parse(text);
+        let index = LineIndex::new(text);
+
+        let crate::ast::Node::VariableDeclaration { name_span, .. } = &result.ast.nodes[0] else {
+            panic!("expected a declaration, found {:?}", result.ast.nodes[0]);
+        };
+        assert_eq!(&text[name_span.start..name_span.end], "größe");
+        assert_eq!(
+            index.range(*name_span),
+            Range {
+                start: Position::new(0, 4),
+                end: Position::new(0, 9),
+            }
+        );
+        assert_eq!(
+            index.range(result.ast.nodes[1].span()),
+            Range {
+                start: Position::new(1, 0),
+                end: Position::new(1, 12),
+            }
+        );
+    }
+}